@@ -0,0 +1,184 @@
+use rust_engine::{*, chunk::MeshSource, world::{ChunkCluster, World}};
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+// Memory use of generated terrain and meshing throughput at every lod, run with
+// cargo bench --bench chunks. There is no harness, each case prints its own numbers.
+// Palette storage is compared against the same chunks held as flat voxel arrays.
+const COLUMNS: i32 = 8;
+const MESH_TIME: Duration = Duration::from_secs(2);
+
+fn main()
+{
+    let mut world = World::new().with_seed(SEED).without_store();
+    let surface = generate(&mut world);
+    let flat: HashMap<_, _> = world.chunks.iter()
+        .map(|(&pos, chunk)| (pos, Arc::new(FlatChunk::new(chunk))))
+        .collect();
+    memory(&world, &flat);
+    let baseline = meshing_flat(&world, &flat, &surface);
+    for lod in 0..=LOD_DISTANCES.len() as u32
+    {
+        meshing(&world, &surface, lod, baseline);
+    }
+}
+
+
+//COLUMNS x COLUMNS chunk columns from the bottom of the world up to the sky, lit. Returns the
+//chunks the surface runs through, the ones with their neighbours all loaded
+fn generate(world: &mut World) -> Vec<(i32, i32, i32)>
+{
+    let generator = world.generator();
+    let start = Instant::now();
+    let mut positions = Vec::new();
+    let mut surface = Vec::new();
+    for x in 0..COLUMNS
+    {
+        for z in 0..COLUMNS
+        {
+            let heightmap = generator.heightmap((x, z));
+            for y in 0..heightmap.top
+            {
+                let (chunk, _) = generator.chunk((x, y, z), &heightmap);
+                world.chunks.insert((x, y, z), Arc::new(chunk));
+                positions.push((x, y, z));
+            }
+
+            let inner = (1..COLUMNS - 1).contains(&x) && (1..COLUMNS - 1).contains(&z);
+            if inner && heightmap.top > 1
+            {
+                surface.push((x, heightmap.top - 2, z));
+            }
+        }
+    }
+    world.light_chunks(&positions);
+
+    println!("generated and lit {} chunks in {:?}", positions.len(), start.elapsed());
+    surface
+}
+
+
+//the voxels the way chunks stored them before palettes, light is kept out of both sides
+struct FlatChunk {
+    voxels: Vec<VOXELS>,
+    light: Vec<u8>,
+}
+
+impl FlatChunk {
+    fn new(chunk: &chunk::Chunk) -> FlatChunk
+    {
+        let index = |i: i32| (i % CHUNK_SIZE, i / CHUNK_AREA, i / CHUNK_SIZE % CHUNK_SIZE);
+        FlatChunk{
+            voxels: chunk.voxels.iter().collect(),
+            light: (0..CHUNK_VOL).map(|i| {
+                let (x, y, z) = index(i);
+                chunk.get_light(x, y, z)
+            }).collect()
+        }
+    }
+
+
+    fn heap_size(&self) -> usize
+    {
+        self.voxels.capacity() * size_of::<VOXELS>()
+    }
+}
+
+//ChunkCluster over flat chunks, unloaded neighbours read the same way
+struct FlatCluster {
+    neighbours: [Option<Arc<FlatChunk>>; 27],
+}
+
+impl FlatCluster {
+    fn new(flat: &HashMap<(i32, i32, i32), Arc<FlatChunk>>, pos: (i32, i32, i32)) -> FlatCluster
+    {
+        let mut neighbours: [Option<Arc<FlatChunk>>; 27] = Default::default();
+        for (i, pos) in ChunkCluster::positions(pos).enumerate()
+        {
+            neighbours[i] = flat.get(&pos).cloned();
+        }
+        FlatCluster{neighbours}
+    }
+
+
+    fn neighbour(&self, x: i32, y: i32, z: i32) -> Option<(&FlatChunk, usize)>
+    {
+        let (dx, dy, dz) = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+        let chunk = self.neighbours[((dx+1) + (dz+1)*3 + (dy+1)*9) as usize].as_deref()?;
+        let (x, y, z) = (x.rem_euclid(CHUNK_SIZE), y.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE));
+        Some((chunk, (x + z*CHUNK_SIZE + y*CHUNK_AREA) as usize))
+    }
+}
+
+impl MeshSource for FlatCluster {
+    fn size(&self) -> i32
+    {
+        CHUNK_SIZE
+    }
+
+    fn get_voxel(&self, x: i32, y: i32, z: i32) -> VOXELS
+    {
+        self.neighbour(x, y, z).map_or(VOXELS::EMPTY, |(chunk, i)| chunk.voxels[i])
+    }
+
+    fn get_light(&self, x: i32, y: i32, z: i32) -> u8
+    {
+        self.neighbour(x, y, z).map_or(light::Channel::Sky.set(0, light::MAX_LIGHT), |(chunk, i)| chunk.light[i])
+    }
+}
+
+
+fn memory(world: &World, flat: &HashMap<(i32, i32, i32), Arc<FlatChunk>>)
+{
+    let chunks = world.chunks.len();
+    let single = world.chunks.values().filter(|chunk| chunk.voxels.uniform().is_some()).count();
+    let kib = |bytes: usize| bytes as f64 / chunks as f64 / 1024.0;
+    let palette = world.voxel_memory();
+    let flat: usize = flat.values().map(|chunk| size_of::<chunk::Chunk>() + chunk.heap_size()).sum();
+    println!("voxels: {} chunks, {} of one voxel", chunks, single);
+    println!("  palette {:.1} KiB per chunk, flat {:.1} KiB per chunk, palette is {:.1}% of flat",
+        kib(palette), kib(flat), palette as f64 * 100.0 / flat as f64);
+}
+
+
+//chunks/s meshing lod 0 from flat arrays
+fn meshing_flat(world: &World, flat: &HashMap<(i32, i32, i32), Arc<FlatChunk>>, surface: &[(i32, i32, i32)]) -> f64
+{
+    let start = Instant::now();
+    let mut meshes = 0u64;
+    while start.elapsed() < MESH_TIME
+    {
+        for &pos in surface
+        {
+            std::hint::black_box(world.chunks[&pos].mesh_source(&FlatCluster::new(flat, pos)));
+            meshes += 1;
+        }
+    }
+    meshes as f64 / start.elapsed().as_secs_f64()
+}
+
+
+//lod 0 is shown next to the flat arrays
+fn meshing(world: &World, surface: &[(i32, i32, i32)], lod: u32, flat: f64)
+{
+    let start = Instant::now();
+    let (mut meshes, mut quads) = (0u64, 0u64);
+    while start.elapsed() < MESH_TIME
+    {
+        for &(x, y, z) in surface
+        {
+            let cluster = ChunkCluster::new(world, x, y, z);
+            let mesh = world.chunks[&(x, y, z)].get_mesh(cluster, lod::Levels::uniform(lod));
+            quads += (mesh.quads.len() + mesh.translucent.len()) as u64;
+            meshes += 1;
+        }
+    }
+
+    let seconds = start.elapsed().as_secs_f64();
+    let rate = meshes as f64 / seconds;
+    print!("get_mesh lod {}: {:.0} chunks/s, {:.0} quads per chunk", lod, rate, quads as f64 / meshes as f64);
+    if lod == 0
+    {
+        print!(", flat arrays {:.0} chunks/s, palette at {:.0}% of flat", flat, rate * 100.0 / flat);
+    }
+    println!();
+}
@@ -4,13 +4,11 @@ layout(location = 0) flat in float shading;
 layout(location = 1) flat in uint voxel_id;
 layout(location = 2) flat in uint face_id;
 layout(location = 3) in vec3 vertex_pos;
+layout(location = 4) in float ao_shading;
+layout(location = 5) flat in float light_shading;
 
 layout(location = 0) out vec4 FragColor;
 
-const vec2 face_texture_offset[6] = vec2[] (
-    vec2(2,0), vec2(0,0), vec2(1,0), vec2(1,0), vec2(1,0), vec2(1,0)
-);
-
 vec2 uv[6] = vec2[6](
     fract(vertex_pos.xz), vec2(0,1)+vec2(1,-1)*fract(vertex_pos.xz),
     vec2(1,1)+vec2(-1,-1)*fract(vertex_pos.zy), vec2(0,1)+vec2(1,-1)*fract(vertex_pos.zy),
@@ -26,12 +24,13 @@ layout(binding = 1) uniform sampler2DArray tex_array;
 
 void main()
 {
-    vec2 uv_coords = (uv[face_id] + face_texture_offset[face_id]) * vec2(1/3.0,1);
+    vec2 uv_coords = uv[face_id];
 #ifdef TESTING
     FragColor = texture(test, uv_coords);
 #else
-    FragColor = texture(tex_array, vec3(uv_coords, voxel_id));
+    //every block owns six layers, one per face, translucent ones carry their alpha in the texture
+    FragColor = texture(tex_array, vec3(uv_coords, voxel_id * 6 + face_id));
 #endif
 
-    FragColor *= shading;
+    FragColor.rgb *= shading * ao_shading * light_shading;
 }
@@ -1,31 +1,93 @@
 #version 450 core
 
-layout (location = 0) in uint compressed_data;
+#ifdef VULKAN
+#define VERTEX_INDEX gl_VertexIndex
+#define DRAW_INDEX gl_InstanceIndex
+#else
+#extension GL_ARB_shader_draw_parameters : require
+#define VERTEX_INDEX gl_VertexID
+#define DRAW_INDEX (gl_BaseInstanceARB + gl_InstanceID)
+#endif
 
 layout(std140, binding = 0) uniform UniformBufferObject {
-    mat4 m_model;
     mat4 m_view;
     mat4 m_proj;
 } ubo;    
 
+//see vertex.rs for the layout
+layout(std430, binding = 2) readonly buffer QuadBuffer {
+    uvec2 quads[];
+};
+
+//world position of each mesh's chunk and its lod in w, indexed by the draw's first instance
+layout(std430, binding = 3) readonly buffer OriginBuffer {
+    ivec4 origins[];
+};
+
+
 layout(location = 0) flat out float shading;
 layout(location = 1) flat out uint voxel_id;
 layout(location = 2) flat out uint face_id;
 layout(location = 3) out vec3 vertex_pos;
+layout(location = 4) out float ao_shading;
+layout(location = 5) flat out float light_shading;
 
 ivec3 pos;
+uint ao_id;
+uint light_level;
+
+//quad corners as (u, v) offsets, same order as Chunk::quad_corners
+const uvec2 corners[24] = uvec2[] (
+    uvec2(0,1), uvec2(1,1), uvec2(1,0), uvec2(0,0),
+    uvec2(0,0), uvec2(1,0), uvec2(1,1), uvec2(0,1),
+    uvec2(0,1), uvec2(0,0), uvec2(1,0), uvec2(1,1),
+    uvec2(0,0), uvec2(0,1), uvec2(1,1), uvec2(1,0),
+    uvec2(0,0), uvec2(1,0), uvec2(1,1), uvec2(0,1),
+    uvec2(1,0), uvec2(0,0), uvec2(0,1), uvec2(1,1)
+);
+
+//corners of the two triangles, the flipped split runs along the other diagonal
+const uint triangle_corners[12] = uint[] (
+    0, 1, 2, 2, 3, 0,
+    1, 2, 3, 3, 0, 1
+);
+
+//vertex format 3, see vertex.rs
+void unpack_quad(uvec2 quad, uint vertex) {
+    uint COORD_STRIDE = 5; uint COORD_MASK = (1<<COORD_STRIDE)-1;
+    uint FACE_ID_STRIDE = 3; uint FACE_ID_MASK = (1<<FACE_ID_STRIDE)-1;
+    uint SIZE_STRIDE = 5; uint SIZE_MASK = (1<<SIZE_STRIDE)-1;
+    uint VOXEL_ID_STRIDE = 16; uint VOXEL_ID_MASK = (1<<VOXEL_ID_STRIDE)-1;
+    uint LIGHT_STRIDE = 4; uint LIGHT_MASK = (1<<LIGHT_STRIDE)-1;
+    uint AO_STRIDE = 2; uint AO_MASK = (1<<AO_STRIDE)-1;
 
-void unpack_data(uint compressed_data) {
-    int COORD_STRIDE = 6; int COORD_MASK = (1<<COORD_STRIDE)-1;
-    int FACE_ID_STRIDE = 3;
-    int VOXEL_ID_STRIDE = 4; int VOXEL_ID_MASK = (1<<VOXEL_ID_STRIDE)-1;
-
-    voxel_id = compressed_data & VOXEL_ID_MASK; compressed_data >>= VOXEL_ID_STRIDE;
-    uint z = compressed_data & COORD_MASK; compressed_data >>= COORD_STRIDE;
-    uint y = compressed_data & COORD_MASK; compressed_data >>= COORD_STRIDE;
-    uint x = compressed_data & COORD_MASK; compressed_data >>= COORD_STRIDE;
-    face_id = compressed_data;
-    pos = ivec3(x,y,z);
+    uint data = quad.x;
+    uint u = data & COORD_MASK; data >>= COORD_STRIDE;
+    uint v = data & COORD_MASK; data >>= COORD_STRIDE;
+    uint depth = data & COORD_MASK; data >>= COORD_STRIDE;
+    face_id = data & FACE_ID_MASK; data >>= FACE_ID_STRIDE;
+    uint width = (data & SIZE_MASK) + 1; data >>= SIZE_STRIDE;
+    uint height = (data & SIZE_MASK) + 1; data >>= SIZE_STRIDE;
+    uint flip = data & 1;
+    //the remaining bits are a texture rotation nothing sets yet
+
+    data = quad.y;
+    voxel_id = data & VOXEL_ID_MASK; data >>= VOXEL_ID_STRIDE;
+    uint block_light = data & LIGHT_MASK; data >>= LIGHT_STRIDE;
+    uint sky_light = data & LIGHT_MASK; data >>= LIGHT_STRIDE;
+    light_level = max(block_light, sky_light);
+
+    uint corner = triangle_corners[flip * 6 + vertex];
+    ao_id = (data >> (corner * AO_STRIDE)) & AO_MASK;
+
+    uvec2 uv = uvec2(u, v) + corners[face_id * 4 + corner] * uvec2(width, height);
+    //even faces point towards +axis and sit on the far side of their voxel layer
+    uint plane = depth + 1 - (face_id & 1);
+    switch (face_id >> 1) {
+        case 0: pos = ivec3(uv.x, plane, uv.y); break;
+        case 1: pos = ivec3(plane, uv.x, uv.y); break;
+        default: pos = ivec3(uv.x, uv.y, plane); break;
+    }
 }
 
 float get_shading(uint n) {
@@ -34,6 +96,17 @@ float get_shading(uint n) {
     )[n];
 }
 
+float get_ao(uint n) {
+    return float[4](
+        1.0, 0.75, 0.55, 0.4
+    )[n];
+}
+
+//each light level is 80% as bright as the one above it, with a floor so caves aren't pitch black
+float get_light(uint level) {
+    return max(pow(0.8, 15.0 - float(level)), 0.05);
+}
+
 const vec2 uv[4] = vec2[] (
     vec2(0,0), vec2(1,0), vec2(1,1), vec2(0,1)
 );
@@ -42,16 +115,16 @@ const int uv_indices[6] = int[] (
     3, 2, 1, 1, 0, 3
 );
 
-const vec2 face_texture_offset[6] = vec2[] (
-    vec2(2,0), vec2(0,0), vec2(1,0), vec2(1,0), vec2(1,0), vec2(1,0)
-);
-
 void main()
 {
-    unpack_data(compressed_data);
+    unpack_quad(quads[VERTEX_INDEX / 6], VERTEX_INDEX % 6);
     shading = get_shading(face_id);
-    vertex_pos = (vec4(pos, 1.0) * ubo.m_model).xyz;
-    gl_Position = vec4(pos, 1.0) * ubo.m_model * ubo.m_view * ubo.m_proj;
+    ao_shading = get_ao(ao_id);
+    light_shading = get_light(light_level);
+    //lod meshes count in cells of 1 << lod voxels
+    ivec4 origin = origins[DRAW_INDEX];
+    vertex_pos = vec3((pos << origin.w) + origin.xyz);
+    gl_Position = vec4(vertex_pos, 1.0) * ubo.m_view * ubo.m_proj;
 
 #ifdef VULKAN
     gl_Position.y = - gl_Position.y;
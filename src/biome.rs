@@ -0,0 +1,210 @@
+use crate::*;
+
+// Biomes are picked from two low frequency climate maps, temperature and humidity.
+// Every biome sits at a point in climate space and gets a weight that falls off with
+// distance to it. Heights are the weighted sum of every biome's height curve so
+// borders blend smoothly, materials come from the strongest biome.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Biome {
+    Desert,
+    Plains,
+    Forest,
+    Tundra,
+    Mountains,
+    Ocean
+}
+
+pub struct BiomeInfo {
+    //(temperature, humidity) the biome is centered on, both in -1..1
+    pub climate: (f64, f64),
+    pub base_height: f64,
+    pub amplitude: f64,
+    //ridged biomes fold the detail noise so peaks are sharp instead of rounded
+    pub ridged: bool,
+    pub surface: VOXELS,
+    pub subsurface: VOXELS,
+}
+
+const DESERT: BiomeInfo = BiomeInfo {
+    climate: (0.6, -0.6), base_height: 14.0, amplitude: 5.0, ridged: false,
+    surface: VOXELS::SAND, subsurface: VOXELS::SAND
+};
+const PLAINS: BiomeInfo = BiomeInfo {
+    climate: (0.2, 0.0), base_height: 16.0, amplitude: 6.0, ridged: false,
+    surface: VOXELS::GRASS, subsurface: VOXELS::DIRT
+};
+const FOREST: BiomeInfo = BiomeInfo {
+    climate: (0.1, 0.5), base_height: 18.0, amplitude: 10.0, ridged: false,
+    surface: VOXELS::GRASS, subsurface: VOXELS::DIRT
+};
+const TUNDRA: BiomeInfo = BiomeInfo {
+    climate: (-0.7, 0.0), base_height: 18.0, amplitude: 8.0, ridged: false,
+    surface: VOXELS::SNOW, subsurface: VOXELS::DIRT
+};
+const MOUNTAINS: BiomeInfo = BiomeInfo {
+    climate: (-0.3, -0.5), base_height: 28.0, amplitude: 48.0, ridged: true,
+    surface: VOXELS::COBBLESTONE, subsurface: VOXELS::COBBLESTONE
+};
+const OCEAN: BiomeInfo = BiomeInfo {
+    climate: (0.3, 0.9), base_height: 3.0, amplitude: 3.0, ridged: false,
+    surface: VOXELS::SAND, subsurface: VOXELS::SAND
+};
+
+impl Biome {
+    pub const ALL: [Biome; 6] = [
+        Biome::Desert, Biome::Plains, Biome::Forest, Biome::Tundra, Biome::Mountains, Biome::Ocean
+    ];
+
+    pub fn info(&self) -> &'static BiomeInfo
+    {
+        match self {
+            Biome::Desert => &DESERT,
+            Biome::Plains => &PLAINS,
+            Biome::Forest => &FOREST,
+            Biome::Tundra => &TUNDRA,
+            Biome::Mountains => &MOUNTAINS,
+            Biome::Ocean => &OCEAN
+        }
+    }
+
+    fn height(&self, detail:f64) -> f64
+    {
+        let info = self.info();
+        if info.ridged
+        {
+            info.base_height + info.amplitude * (1.0 - detail.abs())
+        }
+        else
+        {
+            info.base_height + info.amplitude * detail
+        }
+    }
+
+    fn weight(&self, (temperature, humidity):(f64, f64)) -> f64
+    {
+        let (t, h) = self.info().climate;
+        let dist_sq = (temperature - t).powi(2) + (humidity - h).powi(2);
+        (-dist_sq * INV_BIOME_BLEND).exp()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Column {
+    pub height: i32,
+    pub biome: Biome,
+}
+
+impl Column {
+    pub fn sample(noise:&util::Noise, x:i32, z:i32) -> Column
+    {
+        let (x, z) = (x as f64, z as f64);
+        let climate = noise.get_climate(x, z);
+        let detail = noise.get_detail(x, z);
+
+        let mut biome = Biome::Plains;
+        let mut best = 0.0;
+        let mut total = 0.0;
+        let mut height = 0.0;
+        for b in Biome::ALL
+        {
+            let w = b.weight(climate);
+            if w > best
+            {
+                best = w;
+                biome = b;
+            }
+            total += w;
+            height += w * b.height(detail);
+        }
+
+        //weights never all vanish since exp is positive, the max keeps the floor above 0
+        Column{height: std::cmp::max((height / total) as i32, 1), biome}
+    }
+
+    //material for a solid voxel at global height gy, depth solid voxels below the nearest air
+    pub fn voxel_at(&self, gy:i32, depth:i32) -> VOXELS
+    {
+        let info = self.biome.info();
+
+        if depth >= SUBSURFACE_DEPTH
+        {
+            VOXELS::COBBLESTONE
+        }
+        else if gy < SEA_LEVEL
+        {
+            //everything under water turns to sand so shores read as beaches
+            VOXELS::SAND
+        }
+        else if depth == 0 && gy >= SNOW_LINE
+        {
+            VOXELS::SNOW
+        }
+        else if depth == 0
+        {
+            info.surface
+        }
+        else
+        {
+            info.subsurface
+        }
+    }
+}
+
+// Every column of a chunk column, sampled once and shared by all the chunks stacked in it.
+// Terrain only ever fills voxels below max(height + 2*OVERHANG_RANGE, SEA_LEVEL), so chunks
+// from top up are sky. Between the bedrock layer at 0 and the lowest height - 2*OVERHANG_RANGE
+// every voxel is solid unless a worm passes by, caves keeps the height range of each worm
+// that reaches into the column.
+pub struct Heightmap
+{
+    columns: Vec<Column>,
+    lowest: i32,
+    caves: Vec<(f64, f64)>,
+    pub top: i32,
+}
+
+impl Heightmap {
+    pub fn sample(noise:&util::Noise, (cx, cz):(i32, i32)) -> Heightmap
+    {
+        let columns: Vec<Column> = (0..CHUNK_SIZE)
+            .flat_map(|z| (0..CHUNK_SIZE).map(move |x| (x, z)))
+            .map(|(x, z)| Column::sample(noise, cx*CHUNK_SIZE + x, cz*CHUNK_SIZE + z))
+            .collect();
+
+        let band = 2*OVERHANG_RANGE;
+        let lowest = columns.iter().map(|column| column.height - band).min().unwrap();
+        let highest = columns.iter().map(|column| std::cmp::max(column.height + band, SEA_LEVEL)).max().unwrap();
+
+        let (x0, z0) = ((cx*CHUNK_SIZE) as f64, (cz*CHUNK_SIZE) as f64);
+        let size = CHUNK_SIZE as f64;
+        let caves = cave::Worm::near(noise, (cx, 0, cz))
+            .map(|worm| worm.bounds())
+            .filter(|(min, max)| max.0 >= x0 && min.0 <= x0 + size && max.2 >= z0 && min.2 <= z0 + size)
+            .map(|(min, max)| (min.1, max.1))
+            .collect();
+
+        Heightmap{
+            columns,
+            lowest,
+            caves,
+            top: (highest + CHUNK_SIZE - 1).div_euclid(CHUNK_SIZE)
+        }
+    }
+
+
+    //chunk y of the column is solid all the way through, without building it
+    pub fn is_solid(&self, y:i32) -> bool
+    {
+        let (lo, hi) = (y*CHUNK_SIZE, (y + 1)*CHUNK_SIZE);
+        lo >= 0 && hi <= self.lowest
+            && self.caves.iter().all(|&(min, max)| max < lo as f64 || min > hi as f64)
+    }
+
+
+    //x and z local to the chunk column
+    #[inline(always)]
+    pub fn column(&self, x:i32, z:i32) -> &Column
+    {
+        &self.columns[(x + z*CHUNK_SIZE) as usize]
+    }
+}
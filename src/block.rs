@@ -0,0 +1,213 @@
+use crate::*;
+use std::sync::OnceLock;
+
+// Block types come from BLOCKS_PATH instead of code. A voxel is an index into the registry,
+// blocks the generator places by name (VOXELS::GRASS and friends) have to sit at their
+// fixed index, anything after them is free for new blocks. Every block gets six layers in
+// the texture array, one per face, so the shader finds a face at voxel_id * 6 + face_id.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Collision {
+    None,
+    Full,
+    //box inside the unit cell
+    Box{min: [f32; 3], max: [f32; 3]}
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockInfo {
+    pub name: String,
+    //texture names in Face order, None renders nothing for that face
+    pub textures: [Option<String>; 6],
+    //blocks light and hides the faces of its neighbours
+    pub opaque: bool,
+    //drawn in the blended pass, alpha is baked into its texture layers
+    pub translucent: bool,
+    pub alpha: f32,
+    pub emission: u8,
+    //can be targeted and edited
+    pub solid: bool,
+    pub collision: Collision,
+}
+
+pub struct Registry {
+    pub blocks: Vec<BlockInfo>,
+    pub texture_dir: String,
+}
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+//loaded on first use, a broken block file is not something the game can run without
+pub fn registry() -> &'static Registry
+{
+    REGISTRY.get_or_init(|| Registry::load(BLOCKS_PATH)
+        .unwrap_or_else(|err| panic!("failed to load {}: {}", BLOCKS_PATH, err)))
+}
+
+pub fn get(voxel: VOXELS) -> &'static BlockInfo
+{
+    &registry().blocks[voxel.0 as usize]
+}
+
+
+fn parse_collision(value: Option<&json::Value>) -> Result<Collision, String>
+{
+    match value {
+        None => Ok(Collision::Full),
+        Some(json::Value::String(s)) if s == "full" => Ok(Collision::Full),
+        Some(json::Value::String(s)) if s == "none" => Ok(Collision::None),
+        Some(json::Value::Array(values)) if values.len() == 6 => {
+            let v: Vec<f32> = values.iter()
+                .map(|v| v.as_f64().map(|n| n as f32).ok_or("collision box must be numbers"))
+                .collect::<Result<_, _>>()?;
+            Ok(Collision::Box{min: [v[0], v[1], v[2]], max: [v[3], v[4], v[5]]})
+        }
+        Some(_) => Err("collision must be \"full\", \"none\" or [x0, y0, z0, x1, y1, z1]".to_string())
+    }
+}
+
+
+fn parse_textures(value: Option<&json::Value>) -> Result<[Option<String>; 6], String>
+{
+    let Some(value) = value else {
+        return Ok(Default::default());
+    };
+
+    //the most specific key wins, sides fall back to "side" and everything to "all"
+    let lookup = |keys: &[&str]| -> Result<Option<String>, String> {
+        for key in keys
+        {
+            if let Some(name) = value.get(key)
+            {
+                return name.as_str().map(|s| Some(s.to_string()))
+                    .ok_or(format!("texture \"{}\" must be a string", key));
+            }
+        }
+        Ok(None)
+    };
+
+    Ok([
+        lookup(&["top", "all"])?,
+        lookup(&["bottom", "all"])?,
+        lookup(&["right", "side", "all"])?,
+        lookup(&["left", "side", "all"])?,
+        lookup(&["front", "side", "all"])?,
+        lookup(&["back", "side", "all"])?,
+    ])
+}
+
+
+impl BlockInfo {
+    fn parse(value: &json::Value) -> Result<BlockInfo, String>
+    {
+        let name = value.get("name").and_then(|v| v.as_str())
+            .ok_or("block is missing a name")?
+            .to_string();
+        let flag = |key: &str, default: bool| value.get(key).and_then(|v| v.as_bool()).unwrap_or(default);
+        let number = |key: &str, default: f64| value.get(key).and_then(|v| v.as_f64()).unwrap_or(default);
+
+        let block = BlockInfo{
+            textures: parse_textures(value.get("textures")).map_err(|e| format!("{}: {}", name, e))?,
+            opaque: flag("opaque", true),
+            translucent: flag("translucent", false),
+            alpha: number("alpha", 1.0) as f32,
+            emission: number("emission", 0.0).clamp(0.0, 15.0) as u8,
+            solid: flag("solid", true),
+            collision: parse_collision(value.get("collision")).map_err(|e| format!("{}: {}", name, e))?,
+            name,
+        };
+        Ok(block)
+    }
+}
+
+
+impl Registry {
+    pub fn load(path: &str) -> Result<Registry, String>
+    {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let root = json::parse(&text)?;
+
+        let texture_dir = root.get("texture_dir").and_then(|v| v.as_str())
+            .unwrap_or("assets/blocks")
+            .to_string();
+        let blocks = root.get("blocks").and_then(|v| v.as_array())
+            .ok_or("missing \"blocks\" array")?
+            .iter()
+            .map(BlockInfo::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (voxel, name) in BUILTIN_BLOCKS
+        {
+            match blocks.get(voxel.0 as usize) {
+                Some(block) if block.name == name => {}
+                _ => return Err(format!("block {} must be \"{}\"", voxel.0, name))
+            }
+        }
+
+        //ids have to fit in VOXEL_ID_BITS of a vertex
+        let max_blocks = 1usize << VOXEL_ID_BITS;
+        if blocks.len() > max_blocks
+        {
+            return Err(format!("{} blocks, at most {} are supported", blocks.len(), max_blocks));
+        }
+
+        Ok(Registry{blocks, texture_dir})
+    }
+
+
+    pub fn len(&self) -> usize
+    {
+        self.blocks.len()
+    }
+
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.blocks.is_empty()
+    }
+
+
+    //(size, layers, rgba8 pixels) with six layers per block in Face order,
+    //faces without a texture and the alpha of translucent blocks are baked in here
+    pub fn build_texture_array(&self) -> Result<(u32, i32, Vec<u8>), String>
+    {
+        let mut cache: std::collections::HashMap<&str, image::RgbaImage> = std::collections::HashMap::new();
+        for name in self.blocks.iter().flat_map(|block| block.textures.iter().flatten())
+        {
+            if cache.contains_key(name.as_str())
+            {
+                continue;
+            }
+            let path = format!("{}/{}.png", self.texture_dir, name);
+            let image = image::open(&path).map_err(|e| format!("{}: {}", path, e))?.into_rgba8();
+            cache.insert(name, image);
+        }
+
+        let size = cache.values().next().map_or(1, |image| image.width());
+        if let Some((name, _)) = cache.iter().find(|(_, image)| image.width() != size || image.height() != size)
+        {
+            return Err(format!("texture {} is not {}x{} like the others", name, size, size));
+        }
+
+        let layer_size = (size * size * 4) as usize;
+        let mut pixels = Vec::with_capacity(layer_size * 6 * self.blocks.len());
+        for block in self.blocks.iter()
+        {
+            for texture in block.textures.iter()
+            {
+                match texture {
+                    Some(name) => {
+                        let start = pixels.len();
+                        pixels.extend_from_slice(cache[name.as_str()].as_raw());
+                        for px in pixels[start..].chunks_exact_mut(4)
+                        {
+                            px[3] = (px[3] as f32 * block.alpha) as u8;
+                        }
+                    }
+                    None => pixels.resize(pixels.len() + layer_size, 0)
+                }
+            }
+        }
+
+        Ok((size, (6 * self.blocks.len()) as i32, pixels))
+    }
+}
@@ -1,8 +1,22 @@
-use crate::math::{dot, H_PI};
-use crate::{settings::*};
+use crate::math::{self, H_PI};
+use crate::{settings::*, physics};
 use core::f32;
 
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MoveMode {
+    Fly,
+    Walk
+}
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlockAction {
+    Break,
+    Place
+}
+
+
 pub struct Player 
 {
     pub pitch:f32,
@@ -18,9 +32,26 @@ pub struct Player
     pub forward:[f32; 3],
     pub right:[f32; 3],
     pub up:[f32; 3],
+    pub aspect:f32,
+    pub mode:MoveMode,
+    body:physics::Body,
+    physics:physics::Physics,
+    //horizontal direction requested by input this frame while walking
+    wish:[f32; 3],
+    jump:bool,
+    pub selected:VOXELS,
+    action:Option<BlockAction>,
 }
 
 
+impl Default for Player 
+{
+    fn default() -> Self 
+    {
+        Player::new()
+    }
+}
+
 impl Player
 {
     pub fn new() -> Player 
@@ -38,7 +69,105 @@ impl Player
             sensitivity:0.01,
             forward: [0.0, 0.0, -1.0],
             right: [1.0, 0.0, 0.0],
-            up: [0.0, 1.0, 0.0]}
+            up: [0.0, 1.0, 0.0],
+            aspect: ASPECT_RATIO,
+            mode: MoveMode::Fly,
+            body: physics::Body::new([START_X, START_Y - EYE_HEIGHT, START_Z], PLAYER_HALF_WIDTH, PLAYER_HEIGHT),
+            physics: physics::Physics::new(),
+            wish: [0.0; 3],
+            jump: false,
+            selected: VOXELS::COBBLESTONE,
+            action: None}
+    }
+
+
+    pub fn take_action(&mut self) -> Option<BlockAction> 
+    {
+        self.action.take()
+    }
+
+
+    //camera looks down -forward
+    pub fn view_ray(&self) -> ([f32; 3], [f32; 3]) 
+    {
+        let [x,y,z] = self.forward;
+        ([self.x, self.y, self.z], [-x, -y, -z])
+    }
+
+
+    //only a walking body takes up space, flying passes through placed blocks anyway
+    pub fn occupies(&self, x:i32, y:i32, z:i32) -> bool 
+    {
+        if self.mode == MoveMode::Fly 
+        {
+            return false;
+        }
+
+        let aabb = self.body.aabb();
+        let min = [x as f32, y as f32, z as f32];
+        (0..3).all(|axis| aabb.min[axis] < min[axis] + 1.0 && aabb.max[axis] > min[axis])
+    }
+
+
+    //flying translates straight away, walking only records where to go for simulate
+    fn translate(&mut self, [x,y,z]:[f32; 3], delta_time:f32)
+    {
+        match self.mode 
+        {
+            MoveMode::Fly => 
+            {
+                self.x += x*self.speed*delta_time;
+                self.y += y*self.speed*delta_time;
+                self.z += z*self.speed*delta_time;
+            }
+            MoveMode::Walk => 
+            {
+                self.wish[0] += x;
+                self.wish[2] += z;
+            }
+        }
+    }
+
+
+    pub fn toggle_mode(&mut self) 
+    {
+        self.mode = match self.mode 
+        {
+            MoveMode::Fly => 
+            {
+                self.body.pos = [self.x, self.y - EYE_HEIGHT, self.z];
+                self.body.vel = [0.0; 3];
+                MoveMode::Walk
+            }
+            MoveMode::Walk => MoveMode::Fly
+        };
+    }
+
+
+    pub fn simulate(&mut self, world:&impl physics::SolidQuery, dt:f32) 
+    {
+        let [wx, _, wz] = std::mem::take(&mut self.wish);
+        let jump = std::mem::take(&mut self.jump);
+        if self.mode == MoveMode::Fly 
+        {
+            return;
+        }
+
+        let len = (wx*wx + wz*wz).sqrt();
+        let (vx, vz) = if len > 0.0 {(wx / len * WALK_SPEED, wz / len * WALK_SPEED)} else {(0.0, 0.0)};
+        self.body.vel[0] = vx;
+        self.body.vel[2] = vz;
+
+        if jump && self.body.on_ground 
+        {
+            self.body.vel[1] = JUMP_VELOCITY;
+            self.body.on_ground = false;
+        }
+
+        self.physics.update(&mut self.body, world, dt);
+        self.x = self.body.pos[0];
+        self.y = self.body.pos[1] + EYE_HEIGHT;
+        self.z = self.body.pos[2];
     }
 }
 
@@ -56,7 +185,7 @@ pub trait Camera
     fn update(&mut self, events: &[PlayerEvent], dt:f32);
     fn get_view_mat(&self)->[f32;16];
     fn get_proj_mat(&self)->[f32;16];
-    fn is_in_frustum(&self, pos: (f32, f32, f32))->bool;
+    fn get_frustum(&self)->math::Frustum;
 }
 
 
@@ -64,50 +193,46 @@ impl Camera for Player
 {
     fn move_up(self:&mut Player, delta_time:f32) 
     {
-        let [x,y,z] = self.up;
-        self.x += x*self.speed*delta_time;
-        self.y += y*self.speed*delta_time;
-        self.z += z*self.speed*delta_time;
+        //vertical movement only exists while flying
+        if self.mode == MoveMode::Fly 
+        {
+            let [x,y,z] = self.up;
+            self.translate([x,y,z], delta_time);
+        }
     }
 
     fn move_down(&mut self, delta_time:f32) 
     {
-        let [x,y,z] = self.up;
-        self.x -= x*self.speed*delta_time;
-        self.y -= y*self.speed*delta_time;
-        self.z -= z*self.speed*delta_time;
+        //vertical movement only exists while flying
+        if self.mode == MoveMode::Fly 
+        {
+            let [x,y,z] = self.up;
+            self.translate([-x,-y,-z], delta_time);
+        }
     }
 
     fn move_right(&mut self, delta_time:f32) 
     {
         let [x,y,z] = self.right;
-        self.x += x*self.speed*delta_time;
-        self.y += y*self.speed*delta_time;
-        self.z += z*self.speed*delta_time;
+        self.translate([x,y,z], delta_time);
     }
 
     fn move_left(&mut self, delta_time:f32) 
     {
         let [x,y,z] = self.right;
-        self.x -= x*self.speed*delta_time;
-        self.y -= y*self.speed*delta_time;
-        self.z -= z*self.speed*delta_time;
+        self.translate([-x,-y,-z], delta_time);
     }
 
     fn move_forward(&mut self, delta_time:f32) 
     {
         let [x,y,z] = self.forward;
-        self.x -= x*self.speed*delta_time;
-        self.y -= y*self.speed*delta_time;
-        self.z -= z*self.speed*delta_time;
+        self.translate([-x,-y,-z], delta_time);
     }
 
     fn move_backward(&mut self, delta_time:f32) 
     {
         let [x,y,z] = self.forward;
-        self.x += x*self.speed*delta_time;
-        self.y += y*self.speed*delta_time;
-        self.z += z*self.speed*delta_time;
+        self.translate([x,y,z], delta_time);
     }
 
     fn rot_pitch(&mut self, dy:f32) 
@@ -146,6 +271,11 @@ impl Camera for Player
                 PlayerEvent::MoveBackward => self.move_backward(dt),
                 PlayerEvent::RotPitch(angle) => self.rot_pitch(*angle),
                 PlayerEvent::RotYaw(angle) => self.rot_yaw(*angle),
+                PlayerEvent::Jump => self.jump = true,
+                PlayerEvent::ToggleFly => self.toggle_mode(),
+                PlayerEvent::Break => self.action = Some(BlockAction::Break),
+                PlayerEvent::Place => self.action = Some(BlockAction::Place),
+                PlayerEvent::Select(voxel) => self.selected = *voxel,
             }
         }
     }
@@ -164,31 +294,14 @@ impl Camera for Player
     #[inline(always)]
     fn get_proj_mat(&self) -> [f32;16] 
     {
-        PROJECTION
+        let mut proj = PROJECTION;
+        proj[0] = INV_VFOV / self.aspect;
+        proj
     }
 
-    fn is_in_frustum(&self, (x, y, z): (f32, f32, f32)) -> bool 
+    fn get_frustum(&self) -> math::Frustum 
     {
-        let dv = [x-self.x,y-self.y,z-self.z];
-        let depth = dot(self.forward, dv);
-        if depth > -NEAR + CHUNK_RADIUS || depth < -FAR - CHUNK_RADIUS 
-        {
-            return false;
-        }
-
-        let vertical = dot(self.up, dv).abs();
-        if vertical > FRUSTUM_Y - depth * VFOV_TAN 
-        {
-            return false;
-        }
-
-        let horizontal = dot(self.right, dv).abs();
-        if horizontal > FRUSTUM_X - depth * HFOV_TAN 
-        {
-            return false;
-        }
-
-        true
+        math::Frustum::from_view_proj(&math::mat_mul(&self.get_proj_mat(), &self.get_view_mat()))
     }
 }
 
@@ -200,5 +313,98 @@ pub enum PlayerEvent {
     MoveForward,
     MoveBackward,
     RotPitch(f32),
-    RotYaw(f32)
-}
\ No newline at end of file
+    RotYaw(f32),
+    Jump,
+    ToggleFly,
+    Break,
+    Place,
+    Select(VOXELS)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn looking(yaw: f32, pitch: f32) -> math::Frustum
+    {
+        let mut player = Player::new();
+        (player.x, player.y, player.z) = (0.0, 0.0, 0.0);
+        (player.yaw, player.pitch) = (yaw, pitch);
+        player.update(&[], 0.0);
+        player.get_frustum()
+    }
+
+
+    //a unit cube centered on the point
+    fn sees(frustum: &math::Frustum, [x, y, z]: [f32; 3]) -> bool
+    {
+        frustum.intersects_aabb([x - 0.5, y - 0.5, z - 0.5], [x + 0.5, y + 0.5, z + 0.5])
+    }
+
+
+    #[test]
+    fn straight_ahead()
+    {
+        let frustum = looking(0.0, 0.0);
+        assert!(sees(&frustum, [0.0, 0.0, -10.0]));
+        assert!(sees(&frustum, [3.0, 4.0, -10.0]));
+        assert!(!sees(&frustum, [0.0, 20.0, -10.0]));
+        assert!(!sees(&frustum, [-30.0, 0.0, -10.0]));
+        assert!(!sees(&frustum, [0.0, 0.0, -FAR - 10.0]));
+    }
+
+
+    #[test]
+    fn behind_the_camera()
+    {
+        let frustum = looking(0.0, 0.0);
+        assert!(!sees(&frustum, [0.0, 0.0, 10.0]));
+        assert!(!sees(&frustum, [0.0, 0.0, 1.0]));
+        //behind but wide enough to reach past the near plane
+        assert!(frustum.intersects_aabb([-1.0, -1.0, -1.0], [1.0, 1.0, 5.0]));
+        //around the camera
+        assert!(frustum.intersects_aabb([-32.0, -32.0, -32.0], [32.0, 32.0, 32.0]));
+    }
+
+
+    #[test]
+    fn straight_up_and_down()
+    {
+        let up = looking(0.0, H_PI);
+        assert!(sees(&up, [0.0, 10.0, 0.0]));
+        assert!(sees(&up, [2.0, 100.0, -2.0]));
+        assert!(!sees(&up, [0.0, -10.0, 0.0]));
+        assert!(!sees(&up, [0.0, 0.0, -10.0]));
+        assert!(!sees(&up, [10.0, 0.0, 0.0]));
+
+        let down = looking(0.0, -H_PI);
+        assert!(sees(&down, [0.0, -10.0, 0.0]));
+        assert!(!sees(&down, [0.0, 10.0, 0.0]));
+        assert!(!sees(&down, [0.0, 0.0, -10.0]));
+
+        //turning doesn't change what's straight below
+        let down = looking(2.0, -H_PI);
+        assert!(sees(&down, [0.0, -10.0, 0.0]));
+        assert!(!sees(&down, [0.0, 10.0, 0.0]));
+    }
+
+
+    #[test]
+    fn yaw_near_right_angles()
+    {
+        for yaw in [H_PI, H_PI - 0.01, H_PI + 0.01]
+        {
+            let left = looking(yaw, 0.0);
+            assert!(sees(&left, [-10.0, 0.0, 0.0]), "{}", yaw);
+            assert!(!sees(&left, [10.0, 0.0, 0.0]), "{}", yaw);
+            assert!(!sees(&left, [0.0, 0.0, -10.0]), "{}", yaw);
+            assert!(!sees(&left, [0.0, 0.0, 10.0]), "{}", yaw);
+
+            let right = looking(-yaw, 0.0);
+            assert!(sees(&right, [10.0, 0.0, 0.0]), "{}", yaw);
+            assert!(!sees(&right, [-10.0, 0.0, 0.0]), "{}", yaw);
+            assert!(!sees(&right, [0.0, 0.0, -10.0]), "{}", yaw);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::*;
+
+// Worm caves. The world is split into CAVE_CELL wide columns and each one may own a worm
+// that starts somewhere inside it and wanders for CAVE_STEPS steps, steered by 3D noise.
+// Everything about a worm comes from the seed and its cell, so every chunk it passes
+// through traces the exact same path and tunnels line up across chunk borders no matter
+// which thread builds which chunk first.
+pub struct Worm {
+    //(x, y, z, radius) along the path
+    points: Vec<(f64, f64, f64, f64)>,
+    min: (f64, f64, f64),
+    max: (f64, f64, f64),
+}
+
+impl Worm {
+    pub fn new(noise:&util::Noise, (cell_x, cell_z):(i32, i32)) -> Option<Worm>
+    {
+        let h = util::hash(noise.seed, (cell_x, 0, cell_z));
+        if h % 100 >= CAVE_CHANCE
+        {
+            return None;
+        }
+
+        let bits = |shift:u32, range:i32| ((h >> shift) % range as u64) as i32;
+        let mut x = (cell_x * CAVE_CELL + bits(8, CAVE_CELL)) as f64;
+        let mut y = (CAVE_MIN_Y + bits(16, CAVE_MAX_Y - CAVE_MIN_Y)) as f64;
+        let mut z = (cell_z * CAVE_CELL + bits(24, CAVE_CELL)) as f64;
+        let mut yaw = bits(32, 628) as f64 * 0.01;
+        let mut pitch = 0.0f64;
+
+        //offsets keep the steering channels from sampling the same noise
+        let offset = (cell_x * CAVE_CELL * 7) as f64;
+        let mut points = Vec::with_capacity(CAVE_STEPS as usize);
+        let mut min = (x, y, z);
+        let mut max = (x, y, z);
+        for _ in 0..CAVE_STEPS
+        {
+            let t = noise.get_cave(x, y, z + offset) * 0.5 + 0.5;
+            let radius = CAVE_MIN_RADIUS + (CAVE_MAX_RADIUS - CAVE_MIN_RADIUS) * t.clamp(0.0, 1.0);
+            points.push((x, y, z, radius));
+
+            min = (min.0.min(x - radius), min.1.min(y - radius), min.2.min(z - radius));
+            max = (max.0.max(x + radius), max.1.max(y + radius), max.2.max(z + radius));
+
+            yaw += noise.get_cave(x + 1000.0 + offset, y, z) * 0.35;
+            //pitch is pulled back to level so worms roam sideways instead of diving
+            pitch = (pitch * 0.8 + noise.get_cave(x, y + 1000.0, z + offset) * 0.3).clamp(-0.7, 0.7);
+
+            x += yaw.cos() * pitch.cos() * CAVE_STEP_LENGTH;
+            y += pitch.sin() * CAVE_STEP_LENGTH;
+            z += yaw.sin() * pitch.cos() * CAVE_STEP_LENGTH;
+        }
+
+        Some(Worm{points, min, max})
+    }
+
+    //(min, max) corners of the box every carved voxel lies in
+    pub fn bounds(&self) -> ((f64, f64, f64), (f64, f64, f64))
+    {
+        (self.min, self.max)
+    }
+
+
+    //every worm whose cell is close enough to reach the chunk, the bounds are checked in carve
+    pub fn near(noise:&util::Noise, (cx,_,cz):(i32, i32, i32)) -> impl Iterator<Item = Worm> + '_
+    {
+        let reach = CAVE_REACH as i32;
+        let (x0, z0) = ((cx*CHUNK_SIZE - reach).div_euclid(CAVE_CELL), (cz*CHUNK_SIZE - reach).div_euclid(CAVE_CELL));
+        let (x1, z1) = (((cx+1)*CHUNK_SIZE + reach).div_euclid(CAVE_CELL), ((cz+1)*CHUNK_SIZE + reach).div_euclid(CAVE_CELL));
+
+        (x0..=x1).flat_map(move |x| (z0..=z1).map(move |z| (x, z)))
+            .filter_map(move |cell| Worm::new(noise, cell))
+    }
+
+    pub fn carve(&self, chunk:&mut chunk::Chunk)
+    {
+        let origin = (chunk.pos.0*CHUNK_SIZE, chunk.pos.1*CHUNK_SIZE, chunk.pos.2*CHUNK_SIZE);
+        let size = CHUNK_SIZE as f64;
+        let (ox, oy, oz) = (origin.0 as f64, origin.1 as f64, origin.2 as f64);
+        if self.max.0 < ox || self.min.0 > ox + size
+            || self.max.1 < oy || self.min.1 > oy + size
+            || self.max.2 < oz || self.min.2 > oz + size
+        {
+            return;
+        }
+
+        for &(px, py, pz, r) in &self.points
+        {
+            if px + r < ox || px - r > ox + size
+                || py + r < oy || py - r > oy + size
+                || pz + r < oz || pz - r > oz + size
+            {
+                continue;
+            }
+
+            let r_sq = r * r;
+            let lo = |p:f64, o:i32| std::cmp::max((p - r).floor() as i32 - o, 0);
+            let hi = |p:f64, o:i32| std::cmp::min((p + r).ceil() as i32 - o, CHUNK_SIZE - 1);
+            for x in lo(px, origin.0)..=hi(px, origin.0)
+            {
+                for y in lo(py, origin.1)..=hi(py, origin.1)
+                {
+                    for z in lo(pz, origin.2)..=hi(pz, origin.2)
+                    {
+                        let (gx, gy, gz) = (x + origin.0, y + origin.1, z + origin.2);
+                        let dist_sq = (gx as f64 + 0.5 - px).powi(2)
+                            + (gy as f64 + 0.5 - py).powi(2)
+                            + (gz as f64 + 0.5 - pz).powi(2);
+
+                        //the bottom layer stays so nothing can fall out of the world
+                        if dist_sq > r_sq || gy < 1
+                        {
+                            continue;
+                        }
+
+                        //water is left alone so lakes and oceans keep their shape
+                        match chunk.get_voxel(x, y, z)
+                        {
+                            VOXELS::EMPTY | VOXELS::WATER => {}
+                            _ => chunk.set_voxel(x, y, z, VOXELS::EMPTY).expect("cave carved outside the chunk")
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,17 @@
 use crate::{*, world::{ChunkCluster, Face}};
 use std::collections::HashMap;
 
+//(voxel, corner occlusion, light) -> plane -> one row bitmask per line of the plane
+type GreedyPlanes = HashMap<(VOXELS, [u32; 4], u32), HashMap<u32, [u32; 32]>>;
+
 #[derive(Clone, Debug)]
 pub struct Chunk {
     pub pos: (i32, i32, i32),
     pub center: (f32, f32, f32),
-    pub voxels: Vec<VOXELS>,
+    pub voxels: palette::VoxelStorage,
+    pub light: light::LightMap,
     pub status: ChunkStatus,
+    pub modified: bool,
 }
 
 impl Chunk {
@@ -18,15 +23,17 @@ impl Chunk {
                 (y * CHUNK_SIZE + H_CHUNK_SIZE) as f32, 
                 (z * CHUNK_SIZE + H_CHUNK_SIZE) as f32
             ),
-            voxels:vec![VOXELS::EMPTY; CHUNK_VOL as usize], 
+            voxels: palette::VoxelStorage::new(VOXELS::EMPTY), 
+            light: light::LightMap::Unlit,
             status:ChunkStatus::Empty,
+            modified: false,
         }
     }
 
 
     #[cfg(debug_assertions)]
     pub fn filled(x:i32, y:i32, z:i32) -> Chunk {
-        let voxels = vec![VOXELS::WOOD;CHUNK_VOL as usize];
+        let voxels = palette::VoxelStorage::new(VOXELS::WOOD);
         Chunk{
             pos:(x, y, z), 
             center: (
@@ -35,22 +42,30 @@ impl Chunk {
                 (z * CHUNK_SIZE + H_CHUNK_SIZE) as f32
             ),
             voxels,
+            light: light::LightMap::Unlit,
             status:ChunkStatus::Dirty,
+            modified: false,
         }
     }
 
 
     #[inline(always)]
     pub fn get_voxel(&self, x:i32, y:i32, z:i32) -> VOXELS {
-        self.voxels[(x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize]
+        self.voxels.get((x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize)
+    }
+
+
+    #[inline(always)]
+    pub fn get_light(&self, x:i32, y:i32, z:i32) -> u8 {
+        self.light.get((x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize)
     }
 
 
     pub fn set_voxel(&mut self, x:i32, y:i32, z:i32, voxel:VOXELS) -> Result<(), ()> {
         self.status = ChunkStatus::Dirty;
-        if x >= 0 && x < CHUNK_SIZE && y >= 0 && y < CHUNK_SIZE && z >= 0 && z < CHUNK_SIZE 
+        if (0..CHUNK_SIZE).contains(&x) && (0..CHUNK_SIZE).contains(&y) && (0..CHUNK_SIZE).contains(&z) 
         {
-            self.voxels[(x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize] = voxel;
+            self.voxels.set((x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize, voxel);
             Ok(())
         } 
         else 
@@ -60,7 +75,7 @@ impl Chunk {
     }
 
 
-    pub fn build_voxels(&mut self, noise:&util::Noise) 
+    pub fn build_voxels(&mut self, noise:&util::Noise, heightmap:&biome::Heightmap) 
     {
         let global_pos = (
             self.pos.0*CHUNK_SIZE, self.pos.1*CHUNK_SIZE, self.pos.2*CHUNK_SIZE);
@@ -69,22 +84,22 @@ impl Chunk {
         {
             for z in 0..CHUNK_SIZE 
             {
-                let mut global_height: i32 = noise.get_height(
-                    (x + global_pos.0) as f64, 
-                    (z + global_pos.2) as f64);
-
-                global_height = if global_height > 0 {global_height} else {1};
-                
-                for y in 
-                    std::cmp::max(-global_pos.1, 0)..
-                    std::cmp::min(global_height-global_pos.1, CHUNK_SIZE)
-                {
-                    self.generate_terrain((x, y, z), global_pos, global_height)
-                        .expect("failed to generate terrain");
-                }
+                self.generate_terrain((x, z), global_pos, heightmap.column(x, z), noise)
+                    .expect("failed to generate terrain");
             }
         }
-        self.status = if self.voxels.iter().all(|v| *v == VOXELS::EMPTY) 
+
+        //chunks that stayed untouched are all air, no worm can carve anything there
+        if self.voxels.uniform() != Some(VOXELS::EMPTY)
+        {
+            for worm in cave::Worm::near(noise, self.pos)
+            {
+                worm.carve(self);
+            }
+        }
+
+        self.voxels.compact();
+        self.status = if self.voxels.uniform() == Some(VOXELS::EMPTY) 
         {
             ChunkStatus::Empty
         } 
@@ -95,40 +110,58 @@ impl Chunk {
     }
 
 
+    //walks a column top down through the density field so the first solid voxel under air
+    //becomes the surface, water only fills air that is still open to the sky
     pub fn generate_terrain(
-        &mut self, (x,y,z):(i32, i32, i32), (cx,cy,cz):(i32, i32, i32),
-        global_height:i32
+        &mut self, (x,z):(i32, i32), (cx,cy,cz):(i32, i32, i32),
+        column:&biome::Column, noise:&util::Noise
     ) -> Result<(), ()> 
     {
-        let (_, gy, _) = (cx+x,cy+y,cz+z);
-        let voxel = 
-            if cy >= 50 && y > 5 {VOXELS::SNOW}
-            else if gy >= 35 && gy < 50 {VOXELS::COBBLESTONE}
-            else if gy >= 10 && gy < 35 && gy == global_height-1 {VOXELS::GRASS}
-            else if gy >= 7 && gy < 10 {VOXELS::DIRT}
-            else {VOXELS::SAND};
-
-        match voxel 
+        let (gx, gz) = ((cx+x) as f64, (cz+z) as f64);
+        let band = 2*OVERHANG_RANGE;
+        let bottom = std::cmp::max(cy, 0);
+        if cy + CHUNK_SIZE <= bottom
+        {
+            //nothing is generated below the world
+            return Ok(());
+        }
+
+        //solid voxels since the last air and whether that air could see the sky
+        let (mut start, mut run, mut sky) = (std::cmp::max(column.height + band, SEA_LEVEL), 0, true);
+        if cy + CHUNK_SIZE + SUBSURFACE_DEPTH < column.height - band
+        {
+            //everything above is guaranteed solid, skip straight to the chunk
+            (start, run, sky) = (cy + CHUNK_SIZE, SUBSURFACE_DEPTH, false);
+        }
+
+        for gy in (bottom..start).rev()
         {
-            VOXELS::SAND => 
+            let solid = gy == 0 || gy < column.height - band 
+                || (gy < column.height + band && noise.get_density(gx, gy as f64, gz, column.height) > 0.0);
+
+            let voxel = if solid
             {
-                if gy == 0 
-                {
-                    self.set_voxel(x, y+1, z, VOXELS::WATER)?;
-                    self.set_voxel(x, y+2, z, VOXELS::WATER)?;
-                    self.set_voxel(x, y+3, z, VOXELS::WATER)?;
-                    self.set_voxel(x, y+4, z, VOXELS::WATER)?;
-                }
+                let voxel = column.voxel_at(gy, run);
+                run += 1;
+                sky = false;
+                voxel
             }
-            _ => {}
-        }
+            else
+            {
+                run = 0;
+                if sky && gy < SEA_LEVEL {VOXELS::WATER} else {VOXELS::EMPTY}
+            };
 
-        self.set_voxel(x, y, z, voxel)?;
+            if gy < cy + CHUNK_SIZE && voxel != VOXELS::EMPTY
+            {
+                self.set_voxel(x, gy - cy, z, voxel)?;
+            }
+        }
         Ok(())
     }
 
 
-    pub fn generate_entities(&self) -> Vec<(i32,i32,i32,ENTITIES)> 
+    pub fn generate_entities(&self, noise:&util::Noise) -> Vec<(i32,i32,i32,ENTITIES)> 
     {
         let mut entities:Vec<(i32, i32, i32, ENTITIES)> = Vec::new();
         let (cx,cy,cz) = (self.pos.0*CHUNK_SIZE,self.pos.1*CHUNK_SIZE,self.pos.2*CHUNK_SIZE);
@@ -139,89 +172,140 @@ impl Chunk {
                 for z in 0..CHUNK_SIZE 
                 {
                     let voxel_global_pos = (cx+x,cy+y,cz+z);
-                    let voxel = self.voxels[(x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize];
-                    self.add_entity(voxel_global_pos, voxel, &mut entities);
+                    let voxel = self.get_voxel(x, y, z);
+                    self.add_entity(noise, voxel_global_pos, voxel, &mut entities);
                 }
             }
         }
+        self.add_scattered(noise, &mut entities);
         entities
     }
 
 
+    //surface features root on a voxel with air above, the layer above the chunk is assumed open
     pub fn add_entity(
-        &self, (x,y,z):(i32,i32,i32), voxel:VOXELS, entities:&mut Vec<(i32,i32,i32,ENTITIES)>
+        &self, noise:&util::Noise, (x,y,z):(i32,i32,i32), voxel:VOXELS, 
+        entities:&mut Vec<(i32,i32,i32,ENTITIES)>
     ) {
-        match voxel {
-            VOXELS::GRASS => {
-                if math::HASH[((x*3+z*17) as usize)%math::HASH.len()] < 1 {
-                    entities.push((x,y,z,ENTITIES::SEED));
+        if matches!(voxel, VOXELS::EMPTY | VOXELS::WATER) {
+            return;
+        }
+
+        let ly = y - self.pos.1*CHUNK_SIZE;
+        if ly + 1 < CHUNK_SIZE {
+            let above = self.get_voxel(x - self.pos.0*CHUNK_SIZE, ly + 1, z - self.pos.2*CHUNK_SIZE);
+            if above != VOXELS::EMPTY {
+                return;
+            }
+        }
+
+        if let Some(feature) = feature::pick_surface(noise, (x,y,z), voxel) {
+            entities.push((x,y,z,ENTITIES::FEATURE(feature as u8)));
+        }
+    }
+
+
+    //scattered starts are rolled from the chunk seed so the same chunk always gets the same ones
+    pub fn add_scattered(&self, noise:&util::Noise, entities:&mut Vec<(i32,i32,i32,ENTITIES)>) {
+        let (cx,cy,cz) = (self.pos.0*CHUNK_SIZE,self.pos.1*CHUNK_SIZE,self.pos.2*CHUNK_SIZE);
+        let mut rng = util::Rng::new(util::hash(noise.seed.wrapping_add(FEATURE_SEED_OFFSET), self.pos));
+        for (i, feature) in feature::FEATURES.iter().enumerate() {
+            let feature::Placement::Scatter{min_y, max_y, per_chunk, host} = feature.placement else {
+                continue;
+            };
+            for _ in 0..per_chunk {
+                let (x, y, z) = (rng.range(0, CHUNK_SIZE), rng.range(0, CHUNK_SIZE), rng.range(0, CHUNK_SIZE));
+                if cy+y >= min_y && cy+y < max_y && self.get_voxel(x, y, z) == host {
+                    entities.push((cx+x, cy+y, cz+z, ENTITIES::FEATURE(i as u8)));
                 }
             }
-            _ => {}
         }
     }
 
 
-    pub fn get_mesh(&self, chunk_cluster: ChunkCluster) -> ChunkMesh {
+    //lod 0 is full resolution, every level above halves it along each axis
+    pub fn get_mesh(&self, chunk_cluster: ChunkCluster, levels: lod::Levels) -> ChunkMesh {
+        let mut mesh = if levels.lod == 0 {
+            self.mesh_source(&chunk_cluster)
+        } else {
+            self.mesh_source(&lod::LodCluster::new(&chunk_cluster, levels))
+        };
+        mesh.lod = levels.lod;
+        mesh
+    }
+
+
+    //lod 0 mesh of whatever the source holds, the chunk only gives its position. Only opaque
+    //blocks hide the faces next to them, leaves and the like show what's behind them
+    pub fn mesh_source(&self, source: &impl MeshSource) -> ChunkMesh {
+        let opaque_mask = self.build_masks(source, |v| block::get(v).opaque);
         let mut general_mesh = self.get_vertices_greedy(
-            self.build_masks(&chunk_cluster, |v| v != VOXELS::EMPTY && v != VOXELS::WATER)
+            self.build_masks(source, |v| v != VOXELS::EMPTY && !block::get(v).translucent),
+            &opaque_mask,
+            source
         );
-        let water_mesh = self.get_vertices_water(
-            self.build_masks(&chunk_cluster, |v| v == VOXELS::WATER)
+        let translucent_mesh = self.get_vertices_translucent(
+            self.build_masks(source, |v| block::get(v).translucent),
+            &opaque_mask,
+            source
         );
 
+        general_mesh.translucent = translucent_mesh.quads;
         general_mesh
     }
 
 
+    //rows of size+2 bits, the outer two are the cells on either side of the chunk
     fn build_masks(
         &self, 
-        chunk_cluster: &ChunkCluster, 
+        source: &impl MeshSource, 
         condition: impl Fn(VOXELS) -> bool
     ) -> [u64; 3*CHUNK_AREA as usize] {
         let mut solid_mask = [0u64; 3*CHUNK_AREA as usize];
-
-        for z in 0..CHUNK_SIZE {
-            for y in 0..CHUNK_SIZE {
-                for x in 0..CHUNK_SIZE {
-                    if condition(self.get_voxel(x,y,z)) {
-                        solid_mask[(x+z*CHUNK_SIZE) as usize] |= 1<<y+1;
-                        solid_mask[(y+z*CHUNK_SIZE + CHUNK_AREA) as usize] |= 1<<x+1;
-                        solid_mask[(x+y*CHUNK_SIZE + 2*CHUNK_AREA) as usize] |= 1<<z+1;
+        let size = source.size();
+        let area = size * size;
+
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    if condition(source.get_voxel(x,y,z)) {
+                        solid_mask[(x+z*size) as usize] |= 1 << (y+1);
+                        solid_mask[(y+z*size + area) as usize] |= 1 << (x+1);
+                        solid_mask[(x+y*size + 2*area) as usize] |= 1 << (z+1);
                     }
                 }
             }
         }
 
-        for b in 0..CHUNK_SIZE {
-            for a in 0..CHUNK_SIZE {
-                if condition(chunk_cluster.get_voxel(a, -1, b)) {
-                    solid_mask[(a+b*CHUNK_SIZE) as usize] |= 1;
+        for b in 0..size {
+            for a in 0..size {
+                if condition(source.get_voxel(a, -1, b)) {
+                    solid_mask[(a+b*size) as usize] |= 1;
                 }
-                if condition(chunk_cluster.get_voxel(a, CHUNK_SIZE, b)) {
-                    solid_mask[(a+b*CHUNK_SIZE) as usize] |= 1<<33;
+                if condition(source.get_voxel(a, size, b)) {
+                    solid_mask[(a+b*size) as usize] |= 1<<(size+1);
                 }
             }
         }
 
-        for b in 0..CHUNK_SIZE {
-            for a in 0..CHUNK_SIZE {
-                if condition(chunk_cluster.get_voxel(-1, a, b)) {
-                    solid_mask[(a+b*CHUNK_SIZE + CHUNK_AREA) as usize] |= 1;
+        for b in 0..size {
+            for a in 0..size {
+                if condition(source.get_voxel(-1, a, b)) {
+                    solid_mask[(a+b*size + area) as usize] |= 1;
                 }
-                if condition(chunk_cluster.get_voxel(CHUNK_SIZE, a, b)) {
-                    solid_mask[(a+b*CHUNK_SIZE + CHUNK_AREA) as usize] |= 1<<33;
+                if condition(source.get_voxel(size, a, b)) {
+                    solid_mask[(a+b*size + area) as usize] |= 1<<(size+1);
                 }
             }
         }
 
-        for b in 0..CHUNK_SIZE {
-            for a in 0..CHUNK_SIZE {
-                if condition(chunk_cluster.get_voxel(a, b, -1)) {
-                    solid_mask[(a+b*CHUNK_SIZE + 2*CHUNK_AREA) as usize] |= 1;
+        for b in 0..size {
+            for a in 0..size {
+                if condition(source.get_voxel(a, b, -1)) {
+                    solid_mask[(a+b*size + 2*area) as usize] |= 1;
                 }
-                if condition(chunk_cluster.get_voxel(a, b, CHUNK_SIZE)) {
-                    solid_mask[(a+b*CHUNK_SIZE + 2*CHUNK_AREA) as usize] |= 1<<33;
+                if condition(source.get_voxel(a, b, size)) {
+                    solid_mask[(a+b*size + 2*area) as usize] |= 1<<(size+1);
                 }
             }
         }
@@ -230,42 +314,52 @@ impl Chunk {
     }
 
 
-    fn get_vertices_water(&self, water_mask: [u64; 3* CHUNK_AREA as usize]) -> ChunkMesh {
+    //translucent blocks behave like liquids, only the surface facing up is drawn
+    fn get_vertices_translucent(
+        &self, 
+        translucent_mask: [u64; 3* CHUNK_AREA as usize], 
+        opaque_mask: &[u64; 3*CHUNK_AREA as usize], 
+        source: &impl MeshSource
+    ) -> ChunkMesh {
         let mut mesh = ChunkMesh::default();
         let mut culled_solid_mask = [0u64; 2*CHUNK_AREA as usize];
-        let mut greedy_meshing_planes: HashMap<u32, [u32; 32]>;
+        let mut greedy_meshing_planes: HashMap<(u32, VOXELS, u32), [u32; 32]>;
         greedy_meshing_planes = HashMap::new();
+        let size = source.size();
+        let inner = (1u64 << size) - 1;
 
-        for i in 0..CHUNK_AREA as usize {
-            //cull row and remove outer bits
-            let row = water_mask[i];
-            let culll  = (row & !(row >> 1)) >> 1 & 0xFFFFFFFF;
+        for i in 0..(size*size) as usize {
+            //cull row against liquid or opaque blocks above and remove outer bits
+            let row = translucent_mask[i];
+            let culll  = (row & !((row | opaque_mask[i]) >> 1)) >> 1 & inner;
             culled_solid_mask[2*i] = culll;
         }
 
-        for a in 0..CHUNK_SIZE {
-            for b in 0..CHUNK_SIZE {
+        for a in 0..size {
+            for b in 0..size {
                 let mut row = culled_solid_mask[(
-                    (a + b * CHUNK_SIZE) * 2 
+                    (a + b * size) * 2 
                     + (Face::Top as i32 & 1)
-                    + ((Face::Top as i32) >> 1) * CHUNK_AREA * 2
+                    + ((Face::Top as i32) >> 1) * size * size * 2
                 ) as usize];
 
                 while row > 0 {
                     let c = row.trailing_zeros() as i32;
 
                     row = row & (row-1);
+                    let voxel_id = source.get_voxel(a, c, b);
+                    let light = Chunk::face_light(source, (a, c, b), Face::Top);
                     let plane = greedy_meshing_planes
-                        .entry(c as u32)
+                        .entry((c as u32, voxel_id, light))
                         .or_default();
                     plane[a as usize] |= 1 << b as u32;
                 }
             }
         }
 
-        for (&axis_pos, plane) in greedy_meshing_planes.iter_mut() {
-            let new_data = Chunk::greedy_mesh_plane(plane, axis_pos, VOXELS::WATER, Face::Top);
-            mesh.vertices.extend(new_data);
+        for (&(axis_pos, voxel_id, light), plane) in greedy_meshing_planes.iter_mut() {
+            let new_data = Chunk::greedy_mesh_plane(plane, axis_pos, voxel_id, Face::Top, [0; 4], light);
+            mesh.quads.extend(new_data);
         }
 
         mesh.pos = self.pos;
@@ -273,10 +367,16 @@ impl Chunk {
     }
 
 
-    pub fn get_vertices_greedy(&self, solid_mask: [u64; 3*CHUNK_AREA as usize]) -> ChunkMesh {
+    pub fn get_vertices_greedy(
+        &self, 
+        solid_mask: [u64; 3*CHUNK_AREA as usize], 
+        opaque_mask: &[u64; 3*CHUNK_AREA as usize], 
+        source: &impl MeshSource
+    ) -> ChunkMesh {
         let mut mesh = ChunkMesh::default();
         let mut culled_solid_mask = [0u64; 6 * CHUNK_AREA as usize];
-        let mut greedy_meshing_planes: [HashMap<VOXELS, HashMap<u32, [u32; 32]>>; 6];
+        //faces are only merged with faces of the same voxel, corner occlusion and light
+        let mut greedy_meshing_planes: [GreedyPlanes; 6];
         greedy_meshing_planes = [
             HashMap::new(),
             HashMap::new(),
@@ -286,21 +386,24 @@ impl Chunk {
             HashMap::new()
         ];
 
-        for (i, &row) in solid_mask.iter().enumerate() {
-            //cull row and remove outer bits
-            let cullr  = (row & !(row << 1)) >> 1 & 0xFFFFFFFF;
-            let culll  = (row & !(row >> 1)) >> 1 & 0xFFFFFFFF;
+        let size = source.size();
+        let inner = (1u64 << size) - 1;
+
+        for (i, (&row, &opaque)) in solid_mask.iter().zip(opaque_mask).take(3*(size*size) as usize).enumerate() {
+            //cull row against opaque neighbours and remove outer bits
+            let cullr  = (row & !(opaque << 1)) >> 1 & inner;
+            let culll  = (row & !(opaque >> 1)) >> 1 & inner;
             culled_solid_mask[2*i] = culll;
             culled_solid_mask[2*i+1] = cullr;
         }
 
         for face in Face::iter() {
-            for a in 0..CHUNK_SIZE {
-                for b in 0..CHUNK_SIZE {
+            for a in 0..size {
+                for b in 0..size {
                     let mut row = culled_solid_mask[(
-                        (a + b * CHUNK_SIZE) * 2 
+                        (a + b * size) * 2 
                         + (face as i32 & 1)
-                        + ((face as i32) >> 1) * CHUNK_AREA * 2
+                        + ((face as i32) >> 1) * size * size * 2
                     ) as usize];
 
                     while row > 0 {
@@ -312,10 +415,12 @@ impl Chunk {
                             Face::Front | Face::Back => (a, b, c)
                         };
 
-                        let voxel_id = self.get_voxel(x,y,z);
+                        let voxel_id = source.get_voxel(x,y,z);
+                        let ao = Chunk::voxel_ao(source, (x, y, z), face);
+                        let light = Chunk::face_light(source, (x, y, z), face);
                         row = row & (row-1);
                         let plane = greedy_meshing_planes[face as usize]
-                            .entry(voxel_id)
+                            .entry((voxel_id, ao, light))
                             .or_default()
                             .entry(c as u32)
                             .or_default();
@@ -326,10 +431,10 @@ impl Chunk {
         }
 
         for face in Face::iter() {
-            for (&voxel_id, planes) in &mut greedy_meshing_planes[face as usize] {
+            for (&(voxel_id, ao, light), planes) in &mut greedy_meshing_planes[face as usize] {
                 for (&axis_pos, plane) in planes {
-                    let new_data = Chunk::greedy_mesh_plane(plane, axis_pos, voxel_id, face);
-                    mesh.vertices.extend(new_data);
+                    let new_data = Chunk::greedy_mesh_plane(plane, axis_pos, voxel_id, face, ao, light);
+                    mesh.quads.extend(new_data);
                 }
             }
         }
@@ -343,9 +448,10 @@ impl Chunk {
         plane: &mut [u32; 32], 
         depth: u32, 
         voxel_id: VOXELS, 
-        face: Face
-    ) -> Vec<u32> {
-        let mut vertices = Vec::<u32>::new();
+        face: Face,
+        ao: [u32; 4],
+        light: u32
+    ) -> Vec<vertex::Quad> {
         let mut quads = Vec::<[u32; 4]>::new();
         for x in 0..plane.len() {
             let mut y = plane[x].trailing_zeros();
@@ -359,348 +465,77 @@ impl Chunk {
                     plane[x + width] &= !mask;
                     width += 1;
                 }
-                quads.push([x as u32, (x + width) as u32, y as u32, (y + height) as u32]);
+                quads.push([x as u32, (x + width) as u32, y, y + height]);
                 y = plane[x].trailing_zeros();
             }
         }
 
-        match face {
-            Face::Top => {
-                for [u0, u1, v0, v1] in quads {
-                    vertices.extend([
-                        Chunk::compress_data(u0, depth+1, v1, face, voxel_id), 
-                        Chunk::compress_data(u1, depth+1, v1, face, voxel_id),
-                        Chunk::compress_data(u1, depth+1, v0, face, voxel_id),
-                        Chunk::compress_data(u1, depth+1, v0, face, voxel_id),
-                        Chunk::compress_data(u0, depth+1, v0, face, voxel_id),
-                        Chunk::compress_data(u0, depth+1, v1, face, voxel_id)
-                    ])
-                }
-            }
-            Face::Bottom => {
-                for [u0, u1, v0, v1] in quads {
-                    vertices.extend([
-                        Chunk::compress_data(u0, depth, v0, face, voxel_id),
-                        Chunk::compress_data(u1, depth, v0, face, voxel_id),
-                        Chunk::compress_data(u1, depth, v1, face, voxel_id),
-                        Chunk::compress_data(u1, depth, v1, face, voxel_id),
-                        Chunk::compress_data(u0, depth, v1, face, voxel_id),
-                        Chunk::compress_data(u0, depth, v0, face, voxel_id)
-                    ])
-                }
-           }
-            Face::Right => {
-                for [u0, u1, v0, v1] in quads {
-                    vertices.extend([
-                        Chunk::compress_data(depth+1, u0, v1, face, voxel_id),
-                        Chunk::compress_data(depth+1, u0, v0, face, voxel_id),
-                        Chunk::compress_data(depth+1, u1, v0, face, voxel_id),
-                        Chunk::compress_data(depth+1, u1, v0, face, voxel_id),
-                        Chunk::compress_data(depth+1, u1, v1, face, voxel_id),
-                        Chunk::compress_data(depth+1, u0, v1, face, voxel_id),
-                    ])
-                }
-            }
-            Face::Left => {
-                for [u0, u1, v0, v1] in quads {
-                    vertices.extend([
-                        Chunk::compress_data(depth, u0, v0, face, voxel_id),
-                        Chunk::compress_data(depth, u0, v1, face, voxel_id),
-                        Chunk::compress_data(depth, u1, v1, face, voxel_id),
-                        Chunk::compress_data(depth, u1, v1, face, voxel_id),
-                        Chunk::compress_data(depth, u1, v0, face, voxel_id),
-                        Chunk::compress_data(depth, u0, v0, face, voxel_id)
-                    ])
-                }
-            }
-            Face::Front => {
-                for [u0, u1, v0, v1] in quads {
-                    vertices.extend([
-                        Chunk::compress_data(u0, v0, depth+1, face, voxel_id),
-                        Chunk::compress_data(u1, v0, depth+1, face, voxel_id),
-                        Chunk::compress_data(u1, v1, depth+1, face, voxel_id),
-                        Chunk::compress_data(u1, v1, depth+1, face, voxel_id),
-                        Chunk::compress_data(u0, v1, depth+1, face, voxel_id),
-                        Chunk::compress_data(u0, v0, depth+1, face, voxel_id)
-                    ])
-                }
-            }
-            Face::Back => {
-                for [u0, u1, v0, v1] in quads {
-                    vertices.extend([
-                        Chunk::compress_data(u1, v0, depth, face, voxel_id),
-                        Chunk::compress_data(u0, v0, depth, face, voxel_id),
-                        Chunk::compress_data(u0, v1, depth, face, voxel_id),
-                        Chunk::compress_data(u0, v1, depth, face, voxel_id),
-                        Chunk::compress_data(u1, v1, depth, face, voxel_id),
-                        Chunk::compress_data(u1, v0, depth, face, voxel_id),
-                    ])
-                }
-            }
-        }
-        vertices
-    }
-
-
-    pub fn get_vertex_data(&self, chunk_cluster:ChunkCluster) -> ChunkMesh {
-        let mut mesh = ChunkMesh::default();
-        if self.status == ChunkStatus::Empty {
-            return mesh;
-        }
-        let mut entities = vec![Vec::<u32>::new(); NUM_ENTITIES];
-        let mut masks: Vec<Vec<u64>> = vec![
-            vec![0;CHUNK_AREA as usize],
-            vec![0;CHUNK_AREA as usize],
-            vec![0;CHUNK_AREA as usize],
-            vec![0;CHUNK_AREA as usize],
-            vec![0;CHUNK_AREA as usize],
-            vec![0;CHUNK_AREA as usize]
-        ];
-
-        fn compress_position((x, y, z):(i32, i32, i32)) -> u32 {
-            x as u32 | (y as u32) << 8 | (z as u32) << 16
-        }
+        //split along the diagonal whose corners are least occluded so a single dark
+        //corner doesn't bleed across the whole quad
+        let flip = ao[0] + ao[2] > ao[1] + ao[3];
 
-        (0..CHUNK_SIZE).flat_map(|x| {
-            (0..CHUNK_SIZE).flat_map(move |y| {
-                (0..CHUNK_SIZE).map(move |z| {
-                    (x,y,z)
-                })
+        quads.into_iter().map(|[u0, u1, v0, v1]| {
+            vertex::Quad::pack(vertex::QuadData{
+                u: u0, v: v0, depth, face: face as u32,
+                width: u1 - u0, height: v1 - v0,
+                flip, rotation: 0,
+                voxel_id, light, ao
             })
-        }).for_each(|(x, y, z)| {
-            let voxel_id = self.get_voxel(x, y, z);
-            if voxel_id == VOXELS::EMPTY {return}
-            entities[voxel_id as usize].push(compress_position((x,y,z)));
-        });
-
-
-        VOXELS::iter().map(|voxel_id| {
-            (voxel_id, entities[voxel_id as usize].iter())
-        }).for_each(|(voxel_id, data)| {
-
-            self.generate_masks(&mut masks, &chunk_cluster, voxel_id, data);
-
-            for i in [0, 2, 4] {
-                for row in masks[i].iter_mut() {
-                    *row = (!*row >> 1) & *row;
-                    *row >>= 1;
-                    *row &= 0xFFFFFFFF;
-                }
-            }
+        }).collect()
+    }
 
-            for i in [1, 3, 5] {
-                for row in masks[i].iter_mut() {
-                    *row = (!*row << 1) & *row;
-                    *row >>= 1;
-                    *row &= 0xFFFFFFFF;
-                }
-            }
 
-            mesh.vertices.extend(masks[0].iter_mut().enumerate().flat_map(|(i, row)| {
-                let mut res = Vec::<u32>::new();
-                let (mut x, mut y, mut z); y = 0;
-                while *row > 0 {
-                    x = i as u32 & 0x1F;
-                    let r = row.trailing_zeros();
-                    y += r;
-                    z = i as u32 >> 5;
-                    res.push(Chunk::compress_data(x, y+1, z+1, Face::Top, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z+1, Face::Top, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z, Face::Top, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z, Face::Top, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z, Face::Top, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z+1, Face::Top, voxel_id));
-                    *row >>= r; *row >>= 1; y += 1;
-                }
-                res
-            }));
-
-            mesh.vertices.extend(masks[1].iter_mut().enumerate().flat_map(|(i, row)| {
-                let mut res = Vec::<u32>::new();
-                let (mut x, mut y, mut z); y = 0;
-                while *row > 0 {
-                    x = i as u32 & 0x1F;
-                    let r = row.trailing_zeros();
-                    y += r;
-                    z = i as u32 >> 5;
-                    res.push(Chunk::compress_data(x, y, z, Face::Bottom, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y, z, Face::Bottom, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y, z+1, Face::Bottom, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y, z+1, Face::Bottom, voxel_id));
-                    res.push(Chunk::compress_data(x, y, z+1, Face::Bottom, voxel_id));
-                    res.push(Chunk::compress_data(x, y, z, Face::Bottom, voxel_id));
-                    *row >>= r; *row >>= 1; y += 1;
-                }
-                res
-            }));
-
-            mesh.vertices.extend(masks[2].iter_mut().enumerate().flat_map(|(i, row)| {
-                let mut res = Vec::<u32>::new();
-                let (mut x, mut y, mut z); x = 0;
-                while *row > 0 {
-                    let r = row.trailing_zeros();
-                    x += r;
-                    y = i as u32 & 0x1F;
-                    z = i as u32 >> 5;
-                    res.push(Chunk::compress_data(x+1, y, z+1, Face::Right, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y, z, Face::Right, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z, Face::Right, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z, Face::Right, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z+1, Face::Right, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y, z+1, Face::Right, voxel_id));
-                    *row >>= r; *row >>= 1; x+=1;
-                }
-                res
-            }));
-
-            mesh.vertices.extend(masks[3].iter_mut().enumerate().flat_map(|(i, row)| {
-                let mut res = Vec::<u32>::new();
-                let (mut x, mut y, mut z); x = 0;
-                while *row > 0 {
-                    let r = row.trailing_zeros();
-                    x += r;
-                    y = i as u32 & 0x1F;
-                    z = i as u32 >> 5;
-                    res.push(Chunk::compress_data(x, y, z, Face::Left, voxel_id));
-                    res.push(Chunk::compress_data(x, y, z+1, Face::Left, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z+1, Face::Left, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z+1, Face::Left, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z, Face::Left, voxel_id));
-                    res.push(Chunk::compress_data(x, y, z, Face::Left, voxel_id));
-                    *row >>= r; *row >>= 1; x+=1;
-                }
-                res
-            }));
-
-            mesh.vertices.extend(masks[4].iter_mut().enumerate().flat_map(|(i, row)| {
-                let mut res = Vec::<u32>::new();
-                let (mut x, mut y, mut z); z = 0;
-                while *row > 0 {
-                    let r = row.trailing_zeros();
-                    x = i as u32 & 0x1F;
-                    y = i as u32 >> 5;
-                    z += r;
-                    res.push(Chunk::compress_data(x, y, z+1, Face::Front, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y, z+1, Face::Front, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z+1, Face::Front, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z+1, Face::Front, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z+1, Face::Front, voxel_id));
-                    res.push(Chunk::compress_data(x, y, z+1, Face::Front, voxel_id));
-                    *row >>= r; *row >>= 1; z+=1;
-                }
-                res
-            }));
-
-            mesh.vertices.extend(masks[5].iter_mut().enumerate().flat_map(|(i, row)| {
-                let mut res = Vec::<u32>::new();
-                let (mut x, mut y, mut z); z = 0;
-                while *row > 0 {
-                    let r = row.trailing_zeros();
-                    x = i as u32 & 0x1F;
-                    y = i as u32 >> 5;
-                    z += r;
-                    res.push(Chunk::compress_data(x+1, y, z, Face::Back, voxel_id));
-                    res.push(Chunk::compress_data(x, y, z, Face::Back, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z, Face::Back, voxel_id));
-                    res.push(Chunk::compress_data(x, y+1, z, Face::Back, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y+1, z, Face::Back, voxel_id));
-                    res.push(Chunk::compress_data(x+1, y, z, Face::Back, voxel_id));
-                    *row >>= r; *row >>= 1; z+=1;
-                }
-                res
-            }));
-        });
-
-        //face id order
-        
-        mesh.pos = self.pos;
-        mesh
+    //quad corners as (u, v) offsets in counter clockwise order seen from outside the face,
+    //chunk.vert keeps a copy to expand quads with
+    fn quad_corners(face: Face) -> [(u32, u32); 4] {
+        match face {
+            Face::Top => [(0,1), (1,1), (1,0), (0,0)],
+            Face::Bottom => [(0,0), (1,0), (1,1), (0,1)],
+            Face::Right => [(0,1), (0,0), (1,0), (1,1)],
+            Face::Left => [(0,0), (0,1), (1,1), (1,0)],
+            Face::Front => [(0,0), (1,0), (1,1), (0,1)],
+            Face::Back => [(1,0), (0,0), (0,1), (1,1)]
+        }
     }
 
-    fn generate_masks<'a>(
-        &self, 
-        masks: &mut Vec<Vec<u64>>, 
-        chunk_cluster: &ChunkCluster, 
-        voxel_id: VOXELS, 
-        data: impl Iterator<Item=&'a u32>
-    ) {
-        Face::iter().flat_map(|f| {
-            (0..CHUNK_SIZE).flat_map(move |a| {
-                (0..CHUNK_SIZE).map(move |b| {
-                    (f,a,b)
-                })
-            })
-        }).for_each(|(f,a,b)| {
-            match f {
-                Face::Top => {
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= (chunk_cluster.get_voxel(a,-1,b) == voxel_id) as u64;
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= ((chunk_cluster.get_voxel(a,CHUNK_SIZE,b) == voxel_id) as u64) << 33;
-                },
-                Face::Bottom => {
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= (chunk_cluster.get_voxel(a,-1,b) == voxel_id) as u64;
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= ((chunk_cluster.get_voxel(a,CHUNK_SIZE,b) == voxel_id) as u64) << 33;
-                }
-                Face::Right => {
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= (chunk_cluster.get_voxel(-1,a,b) == voxel_id) as u64;
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= ((chunk_cluster.get_voxel(CHUNK_SIZE,a,b) == voxel_id) as u64) << 33;
-                }
-                Face::Left => {
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= (chunk_cluster.get_voxel(-1,a,b) == voxel_id) as u64;
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= ((chunk_cluster.get_voxel(CHUNK_SIZE,a,b) == voxel_id) as u64) << 33;
-                }
-                Face::Front => {
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= (chunk_cluster.get_voxel(a,b,-1) == voxel_id) as u64;
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= ((chunk_cluster.get_voxel(a,b,CHUNK_SIZE) == voxel_id) as u64) << 33;
-                }
-                Face::Back => {
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= (chunk_cluster.get_voxel(a,b,-1) == voxel_id) as u64;
-                    masks[f as usize][(a + b * CHUNK_SIZE) as usize] 
-                        |= ((chunk_cluster.get_voxel(a,b,CHUNK_SIZE) == voxel_id) as u64) << 33;
-                }
-            }
-        });
 
-        data.for_each(|&pos| {
-            let (x, y, z) = (
-                (pos & 0xFF) as i32, 
-                ((pos & 0xFF00) >> 8) as i32, 
-                ((pos & 0xFF0000) >> 16) as i32
-            );
-            masks[0][(x + z * CHUNK_SIZE) as usize] |= 1 << y+1;
-            masks[1][(x + z * CHUNK_SIZE) as usize] |= 1 << y+1;
-            masks[2][(y + z * CHUNK_SIZE) as usize] |= 1 << x+1;
-            masks[3][(y + z * CHUNK_SIZE) as usize] |= 1 << x+1;
-            masks[4][(x + y * CHUNK_SIZE) as usize] |= 1 << z+1;
-            masks[5][(x + y * CHUNK_SIZE) as usize] |= 1 << z+1;
-        });
+    //world axes the plane's u and v coordinates run along
+    fn face_axes(face: Face) -> ((i32, i32, i32), (i32, i32, i32)) {
+        match face {
+            Face::Top | Face::Bottom => ((1,0,0), (0,0,1)),
+            Face::Right | Face::Left => ((0,1,0), (0,0,1)),
+            Face::Front | Face::Back => ((1,0,0), (0,1,0))
+        }
     }
 
 
-    pub fn compress_data(x:u32, y:u32, z:u32, face:Face, voxel_id:VOXELS) -> u32 {
-        static COORD_STRIDE:u32 = 6;
-        //static FACE_ID_STRIDE:usize = 3;
-        static VOXEL_ID_STRIDE:u32 = 4;
+    //occlusion level (0 = open, 3 = fully occluded) of each corner of a voxel face,
+    //in quad_corners order
+    fn voxel_ao(source: &impl MeshSource, (x, y, z): (i32, i32, i32), face: Face) -> [u32; 4] {
+        let (nx, ny, nz) = face.offset();
+        let ((ux, uy, uz), (vx, vy, vz)) = Chunk::face_axes(face);
+        let (ax, ay, az) = (x+nx, y+ny, z+nz);
 
-        let mut res = 0;
+        let occludes = |du: i32, dv: i32| {
+            let voxel = source.get_voxel(
+                ax + du*ux + dv*vx, 
+                ay + du*uy + dv*vy, 
+                az + du*uz + dv*vz);
+            block::get(voxel).opaque as u32
+        };
+
+        Chunk::quad_corners(face).map(|(cu, cv)| {
+            let (su, sv) = (2*cu as i32 - 1, 2*cv as i32 - 1);
+            let (side1, side2, corner) = (occludes(su, 0), occludes(0, sv), occludes(su, sv));
+            if side1 == 1 && side2 == 1 {3} else {side1 + side2 + corner}
+        })
+    }
 
-        res |= face as u32;
-        res <<= COORD_STRIDE; res |= x; 
-        res <<= COORD_STRIDE; res |= y; 
-        res <<= COORD_STRIDE; res |= z; 
-        res <<= VOXEL_ID_STRIDE; res |= voxel_id as u32;
 
-        res as u32
+    //a face is lit by the voxel in front of it, both channels go to the shader
+    fn face_light(source: &impl MeshSource, (x, y, z): (i32, i32, i32), face: Face) -> u32 {
+        let (nx, ny, nz) = face.offset();
+        source.get_light(x+nx, y+ny, z+nz) as u32
     }
 }
 
@@ -734,9 +569,10 @@ impl Chunk
 
     pub fn with_set_voxel(mut self, x:i32, y:i32, z:i32, voxel:VOXELS) -> Result<Chunk, Chunk>
     {
-        if x >= 0 && x < CHUNK_SIZE && y >= 0 && y < CHUNK_SIZE && z >= 0 && z < CHUNK_SIZE 
+        if (0..CHUNK_SIZE).contains(&x) && (0..CHUNK_SIZE).contains(&y) && (0..CHUNK_SIZE).contains(&z) 
         {
-            self.voxels[(x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize] = voxel;
+            self.voxels.set((x+z*CHUNK_SIZE+y*CHUNK_AREA) as usize, voxel);
+            self.modified = true;
             Ok(self.with_status(ChunkStatus::Dirty))
         } 
         else 
@@ -752,31 +588,13 @@ impl Chunk
        self
     }
 
-    pub fn with_build_voxels(mut self, noise:&util::Noise) -> Self
-    {
-        let global_pos = (
-            self.pos.0*CHUNK_SIZE, self.pos.1*CHUNK_SIZE, self.pos.2*CHUNK_SIZE);
 
-        for x in 0..CHUNK_SIZE 
-        {
-            for z in 0..CHUNK_SIZE 
-            {
-                let mut global_height: i32 = noise.get_height(
-                    (x + global_pos.0) as f64, 
-                    (z + global_pos.2) as f64);
-
-                global_height = if global_height > 0 {global_height} else {1};
-                
-                for y in 
-                    std::cmp::max(-global_pos.1, 0)..
-                    std::cmp::min(global_height-global_pos.1, CHUNK_SIZE)
-                {
-                    self.generate_terrain((x, y, z), global_pos, global_height)
-                        .expect("failed to generate terrain");
-                }
-            }
-        }
-        if self.voxels.iter().all(|v| *v == VOXELS::EMPTY) 
+    pub fn with_voxels(mut self, voxels: Vec<VOXELS>) -> Self
+    {
+        assert_eq!(voxels.len(), CHUNK_VOL as usize, "chunk voxel data has the wrong size");
+        self.voxels = palette::VoxelStorage::from_slice(&voxels);
+        self.voxels.compact();
+        if self.voxels.uniform() == Some(VOXELS::EMPTY) 
         {
             self.with_status(ChunkStatus::Empty)
         } 
@@ -785,21 +603,55 @@ impl Chunk
             self.with_status(ChunkStatus::Dirty)
         }
     }
+
+    pub fn with_build_voxels(mut self, noise:&util::Noise, heightmap:&biome::Heightmap) -> Self
+    {
+        self.build_voxels(noise, heightmap);
+        self
+    }
 }
 
 
 #[derive(Default)]
 pub struct ChunkMesh {
     pub pos:(i32, i32, i32),
-    pub vertices: Vec<u32>
+    //quad coordinates are in cells of 1 << lod voxels
+    pub lod: u32,
+    pub quads: Vec<vertex::Quad>,
+    //faces drawn in the blended pass after every opaque chunk, currently just water
+    pub translucent: Vec<vertex::Quad>
 }
 
 impl ChunkMesh {
     pub fn new(
         pos: (i32, i32, i32), 
-        vertices: Vec<u32>, 
+        quads: Vec<vertex::Quad>, 
+        translucent: Vec<vertex::Quad>, 
     ) -> ChunkMesh {
-        ChunkMesh{pos, vertices}
+        ChunkMesh{pos, lod: 0, quads, translucent}
+    }
+}
+
+//what the mesher reads from, coordinates are cells of the mesh's lod and reach one cell past
+//the chunk on every side
+pub trait MeshSource {
+    //cells along each axis of the chunk
+    fn size(&self) -> i32;
+    fn get_voxel(&self, x:i32, y:i32, z:i32) -> VOXELS;
+    fn get_light(&self, x:i32, y:i32, z:i32) -> u8;
+}
+
+impl MeshSource for ChunkCluster {
+    fn size(&self) -> i32 {
+        CHUNK_SIZE
+    }
+
+    fn get_voxel(&self, x:i32, y:i32, z:i32) -> VOXELS {
+        ChunkCluster::get_voxel(self, x, y, z)
+    }
+
+    fn get_light(&self, x:i32, y:i32, z:i32) -> u8 {
+        ChunkCluster::get_light(self, x, y, z)
     }
 }
 
@@ -811,3 +663,57 @@ pub enum ChunkStatus {
     Dirty,
     Clean,
 }
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn mesh(voxels: &[((i32, i32, i32), VOXELS)]) -> ChunkMesh
+    {
+        let mut chunk = Chunk::new(0, 0, 0);
+        for &((x, y, z), voxel) in voxels
+        {
+            chunk.set_voxel(x, y, z, voxel).unwrap();
+        }
+        let mut world = world::World::new().without_store();
+        world.chunks.insert((0, 0, 0), Arc::new(chunk));
+        world.light_chunks(&[(0, 0, 0)]);
+        let cluster = world::ChunkCluster::new(&world, 0, 0, 0);
+        world.chunks[&(0, 0, 0)].mesh_source(&cluster)
+    }
+
+
+    //leaves aren't opaque, the stone keeps its face towards them while the leaf's face
+    //towards the stone is hidden
+    #[test]
+    fn only_opaque_blocks_cull_faces()
+    {
+        assert_eq!(mesh(&[((8, 8, 8), VOXELS::COBBLESTONE)]).quads.len(), 6);
+        //two stones merge into one box
+        assert_eq!(mesh(&[((8, 8, 8), VOXELS::COBBLESTONE), ((9, 8, 8), VOXELS::COBBLESTONE)]).quads.len(), 6);
+        assert_eq!(mesh(&[((8, 8, 8), VOXELS::COBBLESTONE), ((9, 8, 8), VOXELS::LEAF)]).quads.len(), 11);
+
+        //water under a stone roof has no surface to draw
+        assert_eq!(mesh(&[((8, 8, 8), VOXELS::WATER)]).translucent.len(), 1);
+        assert_eq!(mesh(&[((8, 8, 8), VOXELS::WATER), ((8, 9, 8), VOXELS::COBBLESTONE)]).translucent.len(), 0);
+    }
+
+
+    //only opaque neighbours darken the corners of a face
+    #[test]
+    fn only_opaque_blocks_occlude_corners()
+    {
+        let ao = |voxel| {
+            let mut world = world::World::new().without_store();
+            let mut chunk = Chunk::new(0, 0, 0);
+            chunk.set_voxel(8, 8, 8, VOXELS::COBBLESTONE).unwrap();
+            chunk.set_voxel(9, 9, 8, voxel).unwrap();
+            world.chunks.insert((0, 0, 0), Arc::new(chunk));
+            Chunk::voxel_ao(&world::ChunkCluster::new(&world, 0, 0, 0), (8, 8, 8), Face::Top)
+        };
+        assert_eq!(ao(VOXELS::LEAF), [0; 4]);
+        assert_ne!(ao(VOXELS::COBBLESTONE), [0; 4]);
+    }
+}
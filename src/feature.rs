@@ -0,0 +1,412 @@
+use crate::*;
+
+// Features are everything placed on top of the terrain: trees, boulders, ore veins and
+// buildings. Each one pairs a placement rule deciding where origins go with a shape that
+// turns an origin into voxel edits. Shapes are either procedural generators or fixed
+// templates. Edits are handed to World::apply_edits, writes into chunks that aren't loaded
+// yet wait in World::pending until that chunk is generated.
+pub struct FeatureInfo {
+    pub placement: Placement,
+    pub shape: Shape,
+}
+
+pub enum Placement {
+    //on top of exposed voxels of the given kinds inside the given biomes,
+    //chance is out of FEATURE_ROLL per candidate voxel
+    Surface{on: &'static [VOXELS], biomes: &'static [biome::Biome], chance: i32},
+    //per_chunk random points between min_y and max_y, kept only where host is
+    Scatter{min_y: i32, max_y: i32, per_chunk: i32, host: VOXELS},
+}
+
+pub enum Shape {
+    //straight trunk under a stepped square canopy
+    Oak{trunk: VOXELS, leaves: VOXELS, min_height: i32, max_height: i32},
+    //tall trunk with a canopy narrowing towards the top
+    Pine{trunk: VOXELS, leaves: VOXELS, min_height: i32, max_height: i32},
+    //rough ball half sunk into the ground
+    Boulder{voxel: VOXELS, max_radius: i32},
+    //random walk through stone
+    Vein{voxel: VOXELS, size: i32},
+    Template(&'static Template),
+}
+
+// Fixed layout, layers go bottom to top, rows along z and characters along x.
+// '.' leaves the world untouched, everything else is looked up in the palette.
+pub struct Template {
+    pub layers: &'static [&'static [&'static str]],
+    pub palette: &'static [(char, VOXELS)],
+}
+
+//what an edit is allowed to overwrite
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Replace {
+    Any,
+    Air,
+    Stone
+}
+
+impl Replace {
+    pub fn allows(&self, voxel:VOXELS) -> bool
+    {
+        match self {
+            Replace::Any => true,
+            Replace::Air => matches!(voxel, VOXELS::EMPTY | VOXELS::WATER),
+            Replace::Stone => voxel == VOXELS::COBBLESTONE
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Edit {
+    pub pos: (i32, i32, i32),
+    pub voxel: VOXELS,
+    pub replace: Replace,
+}
+
+const HUT: Template = Template {
+    layers: &[
+        &["ccccc", "ccccc", "ccccc", "ccccc", "ccccc"],
+        &["wcccw", "caaac", "caaac", "caaac", "wcacw"],
+        &["wcccw", "caaac", "aaaaa", "caaac", "wcacw"],
+        &["wcccw", "caaac", "caaac", "caaac", "wcccw"],
+        &["wwwww", "wwwww", "wwwww", "wwwww", "wwwww"],
+        &[".....", ".www.", ".www.", ".www.", "....."],
+    ],
+    palette: &[('c', VOXELS::COBBLESTONE), ('w', VOXELS::WOOD), ('a', VOXELS::EMPTY)],
+};
+
+pub const FEATURES: [FeatureInfo; 10] = [
+    FeatureInfo{
+        placement: Placement::Surface{on: &[VOXELS::GRASS], biomes: &[biome::Biome::Plains], chance: 256},
+        shape: Shape::Oak{trunk: VOXELS::WOOD, leaves: VOXELS::LEAF, min_height: 4, max_height: 8},
+    },
+    FeatureInfo{
+        placement: Placement::Surface{on: &[VOXELS::GRASS], biomes: &[biome::Biome::Forest], chance: 1536},
+        shape: Shape::Oak{trunk: VOXELS::WOOD, leaves: VOXELS::LEAF, min_height: 5, max_height: 9},
+    },
+    FeatureInfo{
+        placement: Placement::Surface{on: &[VOXELS::GRASS], biomes: &[biome::Biome::Forest], chance: 512},
+        shape: Shape::Pine{trunk: VOXELS::WOOD, leaves: VOXELS::LEAF, min_height: 7, max_height: 11},
+    },
+    FeatureInfo{
+        placement: Placement::Surface{on: &[VOXELS::SNOW], biomes: &[biome::Biome::Tundra], chance: 256},
+        shape: Shape::Pine{trunk: VOXELS::WOOD, leaves: VOXELS::LEAF, min_height: 6, max_height: 10},
+    },
+    FeatureInfo{
+        placement: Placement::Surface{
+            on: &[VOXELS::GRASS, VOXELS::SNOW, VOXELS::COBBLESTONE],
+            biomes: &[biome::Biome::Plains, biome::Biome::Tundra, biome::Biome::Mountains],
+            chance: 96
+        },
+        shape: Shape::Boulder{voxel: VOXELS::COBBLESTONE, max_radius: 2},
+    },
+    FeatureInfo{
+        placement: Placement::Surface{on: &[VOXELS::GRASS], biomes: &[biome::Biome::Plains], chance: 4},
+        shape: Shape::Template(&HUT),
+    },
+    FeatureInfo{
+        placement: Placement::Scatter{min_y: 1, max_y: 64, per_chunk: 8, host: VOXELS::COBBLESTONE},
+        shape: Shape::Vein{voxel: VOXELS::COAL, size: 14},
+    },
+    FeatureInfo{
+        placement: Placement::Scatter{min_y: 1, max_y: 40, per_chunk: 6, host: VOXELS::COBBLESTONE},
+        shape: Shape::Vein{voxel: VOXELS::IRON, size: 9},
+    },
+    FeatureInfo{
+        placement: Placement::Scatter{min_y: 1, max_y: 20, per_chunk: 3, host: VOXELS::COBBLESTONE},
+        shape: Shape::Vein{voxel: VOXELS::GOLD, size: 7},
+    },
+    FeatureInfo{
+        placement: Placement::Scatter{min_y: 1, max_y: 12, per_chunk: 1, host: VOXELS::COBBLESTONE},
+        shape: Shape::Vein{voxel: VOXELS::DIAMOND, size: 4},
+    },
+];
+
+impl FeatureInfo {
+    //the same origin and seed always produce the same edits, whichever thread asks
+    pub fn generate(&self, seed:u32, origin:(i32, i32, i32)) -> Vec<Edit>
+    {
+        let mut rng = util::Rng::new(util::hash(seed, origin));
+        let mut edits = Vec::new();
+        let (x, y, z) = origin;
+        let mut push = |pos, voxel, replace| edits.push(Edit{pos, voxel, replace});
+
+        match self.shape {
+            Shape::Oak{trunk, leaves, min_height, max_height} => {
+                let height = rng.range(min_height, max_height);
+                for ty in 0..height
+                {
+                    push((x, y+ty, z), trunk, Replace::Any);
+                }
+
+                for ty in 0..=3
+                {
+                    let stride = (3-ty) + rng.range(0, 3);
+                    for tx in -stride..=stride
+                    {
+                        for tz in -stride..=stride
+                        {
+                            push((x+tx, y+height+ty, z+tz), leaves, Replace::Air);
+                        }
+                    }
+                }
+            }
+
+            Shape::Pine{trunk, leaves, min_height, max_height} => {
+                let height = rng.range(min_height, max_height);
+                for ty in 0..height
+                {
+                    push((x, y+ty, z), trunk, Replace::Any);
+                }
+
+                for ty in 2..=height
+                {
+                    //leaves never replace the trunk so the diamond can cover the center
+                    let radius = std::cmp::min((height - ty + 2) / 3, 3);
+                    for tx in -radius..=radius
+                    {
+                        for tz in -radius..=radius
+                        {
+                            if tx.abs() + tz.abs() <= radius
+                            {
+                                push((x+tx, y+ty, z+tz), leaves, Replace::Air);
+                            }
+                        }
+                    }
+                }
+            }
+
+            Shape::Boulder{voxel, max_radius} => {
+                let radius = rng.range(1, max_radius + 1);
+                for tx in -radius..=radius
+                {
+                    for ty in -radius..=radius
+                    {
+                        for tz in -radius..=radius
+                        {
+                            //jitter the edge so boulders don't come out as perfect balls
+                            let jitter = rng.range(0, 2);
+                            if tx*tx + ty*ty + tz*tz <= radius*radius + jitter
+                            {
+                                push((x+tx, y+ty, z+tz), voxel, Replace::Any);
+                            }
+                        }
+                    }
+                }
+            }
+
+            Shape::Vein{voxel, size} => {
+                let (mut vx, mut vy, mut vz) = origin;
+                for _ in 0..size
+                {
+                    push((vx, vy, vz), voxel, Replace::Stone);
+                    match rng.range(0, 6) {
+                        0 => vx += 1,
+                        1 => vx -= 1,
+                        2 => vy += 1,
+                        3 => vy -= 1,
+                        4 => vz += 1,
+                        _ => vz -= 1
+                    }
+                }
+            }
+
+            Shape::Template(template) => {
+                for (ty, layer) in template.layers.iter().enumerate()
+                {
+                    for (tz, row) in layer.iter().enumerate()
+                    {
+                        for (tx, c) in row.chars().enumerate()
+                        {
+                            if let Some(&(_, voxel)) = template.palette.iter().find(|(p, _)| *p == c)
+                            {
+                                //centered on the origin, the bottom layer replaces the ground
+                                let (ox, oz) = ((row.len() / 2) as i32, (layer.len() / 2) as i32);
+                                let pos = (x + tx as i32 - ox, y + ty as i32, z + tz as i32 - oz);
+                                push(pos, voxel, Replace::Any);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        edits
+    }
+}
+
+//surface feature rooted at a candidate voxel, at most one is picked per voxel
+pub fn pick_surface(noise:&util::Noise, pos:(i32, i32, i32), voxel:VOXELS) -> Option<usize>
+{
+    let roll = (util::hash(noise.seed.wrapping_add(FEATURE_SEED_OFFSET), pos) % FEATURE_ROLL as u64) as i32;
+
+    //most candidates roll past every chance, those never pay for sampling the column
+    let total: i32 = FEATURES.iter()
+        .filter_map(|feature| match feature.placement {
+            Placement::Surface{on, chance, ..} if on.contains(&voxel) => Some(chance),
+            _ => None
+        })
+        .sum();
+    if roll >= total
+    {
+        return None;
+    }
+
+    let biome = biome::Column::sample(noise, pos.0, pos.2).biome;
+    let mut cumulative = 0;
+    for (i, feature) in FEATURES.iter().enumerate()
+    {
+        if let Placement::Surface{on, biomes, chance} = feature.placement 
+            && on.contains(&voxel) && biomes.contains(&biome)
+        {
+            cumulative += chance;
+            if roll < cumulative
+            {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const VEIN: FeatureInfo = FeatureInfo{
+        placement: Placement::Scatter{min_y: 1, max_y: 64, per_chunk: 1, host: VOXELS::COBBLESTONE},
+        shape: Shape::Vein{voxel: VOXELS::COAL, size: 40},
+    };
+
+    fn key(edits: &[Edit]) -> Vec<((i32, i32, i32), VOXELS, Replace)>
+    {
+        edits.iter().map(|edit| (edit.pos, edit.voxel, edit.replace)).collect()
+    }
+
+
+    //an unlit chunk filled from a function of global voxel coordinates
+    fn insert(world: &mut world::World, pos: (i32, i32, i32), voxel: impl Fn(i32, i32, i32) -> VOXELS)
+    {
+        let voxels = (0..CHUNK_VOL)
+            .map(|i| {
+                let (x, y, z) = (i % CHUNK_SIZE, i / CHUNK_AREA, i / CHUNK_SIZE % CHUNK_SIZE);
+                voxel(pos.0*CHUNK_SIZE + x, pos.1*CHUNK_SIZE + y, pos.2*CHUNK_SIZE + z)
+            })
+            .collect();
+        world.chunks.insert(pos, Arc::new(chunk::Chunk::new(pos.0, pos.1, pos.2).with_voxels(voxels)));
+    }
+
+
+    //a vein from the border of chunks (0,0,0) and (1,0,0) that ends up in both of them
+    fn border_vein(seed: u32) -> Vec<Edit>
+    {
+        (0..)
+            .map(|y| VEIN.generate(seed, (31, 8 + y % 16, 16)))
+            .find(|edits| {
+                let inside = |edit: &&Edit| edit.pos.1 >= 0 && edit.pos.1 < CHUNK_SIZE && edit.pos.2 >= 0 && edit.pos.2 < CHUNK_SIZE;
+                edits.iter().filter(inside).any(|edit| edit.pos.0 < CHUNK_SIZE)
+                    && edits.iter().filter(inside).any(|edit| edit.pos.0 >= CHUNK_SIZE)
+            })
+            .unwrap()
+    }
+
+
+    #[test]
+    fn generating_is_deterministic()
+    {
+        for feature in FEATURES.iter()
+        {
+            for origin in [(0, 0, 0), (31, 40, -7), (-1000, 12, 530)]
+            {
+                let edits = feature.generate(7, origin);
+                assert!(!edits.is_empty());
+                assert_eq!(key(&edits), key(&feature.generate(7, origin)));
+            }
+        }
+        //but the seed and the origin both matter
+        let oak = &FEATURES[0];
+        assert_ne!(key(&oak.generate(7, (0, 0, 0))), key(&oak.generate(8, (0, 0, 0))));
+        assert_ne!(key(&oak.generate(7, (0, 0, 0))), key(&oak.generate(7, (1, 0, 0))));
+    }
+
+
+    #[test]
+    fn veins_only_replace_their_host()
+    {
+        //stone on even x, air on odd x
+        let mut world = world::World::new().without_store();
+        let host = |x: i32, _, _| if x % 2 == 0 {VOXELS::COBBLESTONE} else {VOXELS::EMPTY};
+        insert(&mut world, (0, 0, 0), host);
+        let edits: Vec<_> = VEIN.generate(3, (16, 16, 16)).into_iter()
+            .filter(|edit| [edit.pos.0, edit.pos.1, edit.pos.2].iter().all(|c| (0..CHUNK_SIZE).contains(c)))
+            .collect();
+        world.apply_edits(edits.clone()).unwrap();
+
+        for edit in edits
+        {
+            let (x, y, z) = edit.pos;
+            let expected = if x % 2 == 0 {VOXELS::COAL} else {VOXELS::EMPTY};
+            assert_eq!(world.get_voxel(x, y, z), expected, "{:?}", edit.pos);
+        }
+        assert!(Replace::Stone.allows(VOXELS::COBBLESTONE));
+        assert!(!Replace::Stone.allows(VOXELS::GRASS));
+        assert!(!Replace::Air.allows(VOXELS::WOOD));
+    }
+
+
+    //the vein comes out the same whether both chunks are there when it's placed, or the far
+    //one loads later and takes its half from the pending edits
+    #[test]
+    fn veins_cross_chunk_borders_in_any_load_order()
+    {
+        let stone = |_, _, _| VOXELS::COBBLESTONE;
+        let edits = border_vein(11);
+        let voxels = |world: &world::World| -> Vec<VOXELS> {
+            (0..2 * CHUNK_SIZE)
+                .flat_map(|x| (0..CHUNK_SIZE).flat_map(move |y| (0..CHUNK_SIZE).map(move |z| (x, y, z))))
+                .map(|(x, y, z)| world.get_voxel(x, y, z))
+                .collect()
+        };
+
+        let mut both = world::World::new().without_store();
+        insert(&mut both, (0, 0, 0), stone);
+        insert(&mut both, (1, 0, 0), stone);
+        both.apply_edits(edits.clone()).unwrap();
+
+        for (first, second) in [((0, 0, 0), (1, 0, 0)), ((1, 0, 0), (0, 0, 0))]
+        {
+            let mut world = world::World::new().without_store();
+            insert(&mut world, first, stone);
+            world.apply_edits(edits.clone()).unwrap();
+            assert!(world.pending.contains_key(&second));
+
+            insert(&mut world, second, stone);
+            world.apply_pending(second, Vec::new()).unwrap();
+            assert!(!world.pending.contains_key(&second));
+            assert!(voxels(&world) == voxels(&both), "loading {:?} first", first);
+        }
+        assert!(voxels(&both).contains(&VOXELS::COAL));
+    }
+
+
+    //edits saved for a chunk that was evicted before it loaded are put down after the ones
+    //still in memory
+    #[test]
+    fn saved_pending_edits_land_when_the_chunk_loads()
+    {
+        let mut world = world::World::new().without_store();
+        let edit = |x, voxel| Edit{pos: (x, 5, 5), voxel, replace: Replace::Any};
+        world.apply_edits(vec![edit(40, VOXELS::WOOD), edit(41, VOXELS::WOOD)]).unwrap();
+        assert_eq!(world.pending[&(1, 0, 0)].len(), 2);
+        assert!(!world.chunks.contains_key(&(1, 0, 0)));
+
+        insert(&mut world, (1, 0, 0), |_, _, _| VOXELS::EMPTY);
+        world.apply_pending((1, 0, 0), vec![edit(40, VOXELS::LEAF), edit(42, VOXELS::LEAF)]).unwrap();
+        assert_eq!(world.get_voxel(40, 5, 5), VOXELS::WOOD);
+        assert_eq!(world.get_voxel(41, 5, 5), VOXELS::WOOD);
+        assert_eq!(world.get_voxel(42, 5, 5), VOXELS::LEAF);
+        assert!(world.pending.is_empty());
+    }
+}
@@ -0,0 +1,375 @@
+use crate::*;
+use std::{cmp::Ordering, collections::{BinaryHeap, HashSet}, sync::Condvar, thread::JoinHandle};
+
+// Chunk work runs on a pool of worker threads instead of the main thread. Queued jobs are
+// ordered by how soon the player is going to see their chunk: the distance to it, stretched
+// up to three times for chunks behind the view direction. Results come back over a channel
+// tagged with the job's id. Jobs still in the queue can be cancelled, the ones already running
+// finish and it's up to the caller to ignore their results. A job that panics comes back as
+// Output::Failed and the worker carries on with the next one. Dropping the scheduler throws away
+// whatever is still queued and joins the workers once they're done with their current job.
+pub type JobId = u64;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Stage {
+    Heightmap,
+    Generate,
+    Decorate,
+    Light,
+    Mesh,
+}
+
+pub enum Output {
+    Heightmap{pos: (i32, i32), heightmap: biome::Heightmap},
+    //built chunks still have to be decorated, loaded ones come back as they were saved. Pending
+    //are edits saved for the chunk while it wasn't loaded
+    Generated{chunk: chunk::Chunk, built: bool, pending: Vec<feature::Edit>},
+    Decorated{pos: (i32, i32, i32), edits: Vec<feature::Edit>},
+    //light of the chunk on its own, see World::apply_light
+    Lit{lone: light::LoneChunk, light: light::LightMap},
+    Meshed(chunk::ChunkMesh),
+    //the job panicked, pos is the one it was submitted with
+    Failed{pos: (i32, i32, i32)},
+}
+
+type Work = Box<dyn FnOnce() -> Output + Send>;
+
+struct Job {
+    priority: f32,
+    id: JobId,
+    pos: (i32, i32, i32),
+    work: Work,
+}
+
+//the heap pops the lowest priority first and the oldest job of equal ones
+impl Ord for Job {
+    fn cmp(&self, other: &Self) -> Ordering
+    {
+        other.priority.total_cmp(&self.priority)
+            .then(other.id.cmp(&self.id))
+    }
+}
+
+impl PartialOrd for Job {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering>
+    {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Job {
+    fn eq(&self, other: &Self) -> bool
+    {
+        self.id == other.id
+    }
+}
+
+impl Eq for Job {}
+
+#[derive(Clone, Copy)]
+struct Focus {
+    eye: [f32; 3],
+    dir: [f32; 3],
+    chunk: (i32, i32, i32),
+}
+
+impl Focus {
+    fn priority(&self, pos: (i32, i32, i32)) -> f32
+    {
+        let center = util::chunk_center_from_global_index(pos);
+        let d = [center.0 - self.eye[0], center.1 - self.eye[1], center.2 - self.eye[2]];
+        let dist = (d[0]*d[0] + d[1]*d[1] + d[2]*d[2]).sqrt();
+        if dist < f32::EPSILON
+        {
+            return 0.0;
+        }
+        let cos = (d[0]*self.dir[0] + d[1]*self.dir[1] + d[2]*self.dir[2]) / dist;
+        dist * (2.0 - cos)
+    }
+}
+
+struct Queue {
+    jobs: BinaryHeap<Job>,
+    focus: Focus,
+    shutdown: bool,
+}
+
+pub struct Scheduler {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    results: mpsc::Receiver<(JobId, Output)>,
+    workers: Vec<JoinHandle<()>>,
+    next_id: JobId,
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self)
+    {
+        let (lock, ready) = &*self.queue;
+        {
+            let mut queue = lock.lock().unwrap();
+            queue.shutdown = true;
+            queue.jobs.clear();
+        }
+        ready.notify_all();
+
+        for worker in self.workers.drain(..)
+        {
+            if worker.join().is_err()
+            {
+                eprintln!("a job worker panicked");
+            }
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn new(workers: usize) -> Scheduler
+    {
+        let focus = Focus{eye: [0.0; 3], dir: [0.0, 0.0, 1.0], chunk: (0, 0, 0)};
+        let queue = Arc::new((Mutex::new(Queue{jobs: BinaryHeap::new(), focus, shutdown: false}), Condvar::new()));
+        let (result_tx, results) = mpsc::channel();
+
+        let workers = (0..workers)
+            .map(|idx| {
+                let queue = queue.clone();
+                let result_tx = result_tx.clone();
+                std::thread::Builder::new()
+                    .name(format!("job worker {}", idx))
+                    .spawn(move || Scheduler::work(&queue, result_tx))
+                    .expect("failed to spawn job worker")
+            })
+            .collect();
+
+        Scheduler{queue, results, workers, next_id: 0}
+    }
+
+
+    //one worker per core, the main thread keeps the last one
+    pub fn with_available_workers() -> Scheduler
+    {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Scheduler::new(cores.saturating_sub(1).max(1))
+    }
+
+
+    fn work(queue: &(Mutex<Queue>, Condvar), result_tx: mpsc::Sender<(JobId, Output)>)
+    {
+        let (lock, ready) = queue;
+        loop
+        {
+            let job = {
+                let mut queue = ready.wait_while(lock.lock().unwrap(), |queue| {
+                    queue.jobs.is_empty() && !queue.shutdown
+                }).unwrap();
+                if queue.shutdown
+                {
+                    return;
+                }
+                queue.jobs.pop().unwrap()
+            };
+
+            let output = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job.work))
+                .unwrap_or(Output::Failed{pos: job.pos});
+            if result_tx.send((job.id, output)).is_err()
+            {
+                return;
+            }
+        }
+    }
+
+
+    pub fn submit(&mut self, pos: (i32, i32, i32), work: impl FnOnce() -> Output + Send + 'static) -> JobId
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (lock, ready) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+        let priority = queue.focus.priority(pos);
+        queue.jobs.push(Job{priority, id, pos, work: Box::new(work)});
+        ready.notify_one();
+        id
+    }
+
+
+    //re-keys the queue when the player changed chunk or looked somewhere else
+    pub fn focus(&self, player: &camera::Player)
+    {
+        let (eye, dir) = player.view_ray();
+        let chunk = (player.chunk_x, player.chunk_y, player.chunk_z);
+
+        let mut queue = self.queue.0.lock().unwrap();
+        let last = queue.focus;
+        let turn = last.dir[0]*dir[0] + last.dir[1]*dir[1] + last.dir[2]*dir[2];
+        if chunk == last.chunk && turn > 0.95
+        {
+            return;
+        }
+
+        queue.focus = Focus{eye, dir, chunk};
+        let focus = queue.focus;
+        let mut jobs = std::mem::take(&mut queue.jobs).into_vec();
+        for job in jobs.iter_mut()
+        {
+            job.priority = focus.priority(job.pos);
+        }
+        queue.jobs = BinaryHeap::from(jobs);
+    }
+
+
+    //drops the jobs that haven't started yet
+    pub fn cancel(&self, ids: &HashSet<JobId>)
+    {
+        if ids.is_empty()
+        {
+            return;
+        }
+        self.queue.0.lock().unwrap().jobs.retain(|job| !ids.contains(&job.id));
+    }
+
+
+    pub fn try_recv(&self) -> Option<(JobId, Output)>
+    {
+        self.results.try_recv().ok()
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::{sync::atomic::{AtomicUsize, Ordering as AtomicOrdering}, time::Duration};
+
+    //any job will do, only the scheduling is looked at
+    fn output(pos: (i32, i32, i32)) -> Output
+    {
+        Output::Decorated{pos, edits: Vec::new()}
+    }
+
+
+    #[test]
+    fn drop_joins_every_worker()
+    {
+        let mut scheduler = Scheduler::new(4);
+        let queue = Arc::downgrade(&scheduler.queue);
+        let finished = Arc::new(AtomicUsize::new(0));
+        let (started_tx, started) = mpsc::channel();
+        for i in 0..4
+        {
+            let finished = finished.clone();
+            let started_tx = started_tx.clone();
+            scheduler.submit((i, 0, 0), move || {
+                started_tx.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+                finished.fetch_add(1, AtomicOrdering::SeqCst);
+                output((i, 0, 0))
+            });
+        }
+
+        //every worker has picked a job up before shutting down
+        for _ in 0..4
+        {
+            started.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        drop(scheduler);
+
+        //each worker holds the queue until it returns, so nothing can be left running
+        assert!(queue.upgrade().is_none());
+        assert_eq!(finished.load(AtomicOrdering::SeqCst), 4);
+    }
+
+
+    #[test]
+    fn drop_discards_queued_jobs()
+    {
+        let mut scheduler = Scheduler::new(1);
+        let started = Arc::new(AtomicUsize::new(0));
+        let (first_tx, first) = mpsc::channel();
+        for i in 0..16
+        {
+            let started = started.clone();
+            let first_tx = first_tx.clone();
+            let queue = scheduler.queue.clone();
+            scheduler.submit((i, 0, 0), move || {
+                started.fetch_add(1, AtomicOrdering::SeqCst);
+                //the worker stays on the first job until the scheduler is being dropped
+                let _ = first_tx.send(());
+                let (lock, ready) = &*queue;
+                drop(ready.wait_while(lock.lock().unwrap(), |queue| !queue.shutdown).unwrap());
+                output((i, 0, 0))
+            });
+        }
+
+        first.recv_timeout(Duration::from_secs(5)).unwrap();
+        drop(scheduler);
+        assert_eq!(started.load(AtomicOrdering::SeqCst), 1);
+    }
+
+
+    #[test]
+    fn drop_with_unread_results()
+    {
+        let mut scheduler = Scheduler::new(2);
+        let (done_tx, done) = mpsc::channel();
+        for i in 0..8
+        {
+            let done_tx = done_tx.clone();
+            scheduler.submit((i, 0, 0), move || {
+                done_tx.send(()).unwrap();
+                output((i, 0, 0))
+            });
+        }
+        for _ in 0..8
+        {
+            done.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        drop(scheduler);
+    }
+
+
+    #[test]
+    fn nearest_jobs_run_first()
+    {
+        let mut scheduler = Scheduler::new(1);
+        //keeps the worker busy until everything is queued
+        let (gate_tx, gate) = mpsc::channel::<()>();
+        scheduler.submit((0, 0, 0), move || {
+            gate.recv().unwrap();
+            output((0, 0, 0))
+        });
+        for x in [9, 3, 6]
+        {
+            scheduler.submit((x, 0, 0), move || output((x, 0, 0)));
+        }
+        gate_tx.send(()).unwrap();
+
+        let mut order = Vec::new();
+        while order.len() < 4
+        {
+            match scheduler.results.recv_timeout(Duration::from_secs(5)).unwrap()
+            {
+                (_, Output::Decorated{pos, ..}) => order.push(pos.0),
+                _ => unreachable!()
+            }
+        }
+        assert_eq!(order, [0, 3, 6, 9]);
+    }
+
+
+    #[test]
+    fn panicking_jobs_are_reported()
+    {
+        let mut scheduler = Scheduler::new(1);
+        let failed = scheduler.submit((1, 2, 3), || panic!("job failed on purpose"));
+        let after = scheduler.submit((4, 0, 0), || output((4, 0, 0)));
+
+        let mut results = (0..2)
+            .map(|_| scheduler.results.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect::<Vec<_>>();
+        results.sort_by_key(|&(id, _)| id);
+        assert!(matches!(results[0], (id, Output::Failed{pos: (1, 2, 3)}) if id == failed));
+        //the same worker is still around for the next one
+        assert!(matches!(results[1], (id, Output::Decorated{pos: (4, 0, 0), ..}) if id == after));
+    }
+}
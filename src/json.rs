@@ -0,0 +1,366 @@
+// Just enough JSON for the data files in assets. Numbers are always read as f64 and objects
+// keep their keys in file order, duplicate keys and all.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value>
+    {
+        match self {
+            Value::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str>
+    {
+        if let Value::String(s) = self {Some(s)} else {None}
+    }
+
+    pub fn as_f64(&self) -> Option<f64>
+    {
+        if let Value::Number(n) = self {Some(*n)} else {None}
+    }
+
+    pub fn as_bool(&self) -> Option<bool>
+    {
+        if let Value::Bool(b) = self {Some(*b)} else {None}
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]>
+    {
+        if let Value::Array(a) = self {Some(a)} else {None}
+    }
+}
+
+
+pub fn parse(text: &str) -> Result<Value, String>
+{
+    let mut parser = Parser{bytes: text.as_bytes(), at: 0};
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.at != parser.bytes.len()
+    {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    at: usize
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> String
+    {
+        let line = self.bytes[..self.at.min(self.bytes.len())].iter().filter(|&&b| b == b'\n').count() + 1;
+        format!("{} on line {}", msg, line)
+    }
+
+    fn skip_whitespace(&mut self)
+    {
+        while self.at < self.bytes.len() && self.bytes[self.at].is_ascii_whitespace()
+        {
+            self.at += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8>
+    {
+        self.skip_whitespace();
+        self.bytes.get(self.at).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String>
+    {
+        if self.peek() == Some(byte)
+        {
+            self.at += 1;
+            Ok(())
+        }
+        else
+        {
+            Err(self.error(&format!("expected '{}'", byte as char)))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String>
+    {
+        if self.bytes[self.at..].starts_with(word.as_bytes())
+        {
+            self.at += word.len();
+            Ok(value)
+        }
+        else
+        {
+            Err(self.error("unknown literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String>
+    {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input"))
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String>
+    {
+        self.expect(b'{')?;
+        let mut entries = Vec::new();
+        if self.peek() == Some(b'}')
+        {
+            self.at += 1;
+            return Ok(Value::Object(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            entries.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.at += 1,
+                Some(b'}') => {self.at += 1; return Ok(Value::Object(entries));}
+                _ => return Err(self.error("expected ',' or '}'"))
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String>
+    {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        if self.peek() == Some(b']')
+        {
+            self.at += 1;
+            return Ok(Value::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.at += 1,
+                Some(b']') => {self.at += 1; return Ok(Value::Array(values));}
+                _ => return Err(self.error("expected ',' or ']'"))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String>
+    {
+        self.expect(b'"')?;
+        let mut res = Vec::new();
+        while let Some(&byte) = self.bytes.get(self.at)
+        {
+            self.at += 1;
+            match byte {
+                b'"' => return String::from_utf8(res).map_err(|_| self.error("invalid utf-8")),
+                b'\\' => {
+                    let escaped = self.bytes.get(self.at).copied();
+                    self.at += 1;
+                    match escaped {
+                        Some(b'"') => res.push(b'"'),
+                        Some(b'\\') => res.push(b'\\'),
+                        Some(b'/') => res.push(b'/'),
+                        Some(b'b') => res.push(0x08),
+                        Some(b'f') => res.push(0x0c),
+                        Some(b'n') => res.push(b'\n'),
+                        Some(b'r') => res.push(b'\r'),
+                        Some(b't') => res.push(b'\t'),
+                        Some(b'u') => {
+                            let c = self.unicode_escape()?;
+                            res.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        _ => return Err(self.error("unsupported escape"))
+                    }
+                }
+                0x00..0x20 => return Err(self.error("control character in string")),
+                _ => res.push(byte)
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    //the four hex digits after \u, surrogate pairs take two escapes in a row
+    fn unicode_escape(&mut self) -> Result<char, String>
+    {
+        let high = self.hex4()?;
+        let code = match high {
+            0xd800..0xdc00 => {
+                if !self.bytes[self.at..].starts_with(b"\\u")
+                {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                self.at += 2;
+                let low = self.hex4()?;
+                if !(0xdc00..0xe000).contains(&low)
+                {
+                    return Err(self.error("unpaired surrogate"));
+                }
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            }
+            0xdc00..0xe000 => return Err(self.error("unpaired surrogate")),
+            _ => high
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String>
+    {
+        let digits = self.bytes.get(self.at..self.at + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.at += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    //-?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?, checked here since str::parse takes more
+    fn number(&mut self) -> Result<Value, String>
+    {
+        let start = self.at;
+        let digits = |parser: &mut Self| {
+            let from = parser.at;
+            while parser.bytes.get(parser.at).is_some_and(|b| b.is_ascii_digit())
+            {
+                parser.at += 1;
+            }
+            parser.at - from
+        };
+
+        if self.bytes.get(self.at) == Some(&b'-')
+        {
+            self.at += 1;
+        }
+        let leading_zero = self.bytes.get(self.at) == Some(&b'0');
+        let int = digits(self);
+        let mut valid = int == 1 || (int > 1 && !leading_zero);
+        if self.bytes.get(self.at) == Some(&b'.')
+        {
+            self.at += 1;
+            valid &= digits(self) > 0;
+        }
+        if matches!(self.bytes.get(self.at), Some(b'e' | b'E'))
+        {
+            self.at += 1;
+            if matches!(self.bytes.get(self.at), Some(b'+' | b'-'))
+            {
+                self.at += 1;
+            }
+            valid &= digits(self) > 0;
+        }
+
+        std::str::from_utf8(&self.bytes[start..self.at]).ok()
+            .filter(|_| valid)
+            .and_then(|s| s.parse().ok())
+            .map(Value::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn string(text: &str) -> Result<String, String>
+    {
+        parse(text).map(|value| value.as_str().unwrap().to_string())
+    }
+
+
+    #[test]
+    fn strings()
+    {
+        assert_eq!(string(r#""plain""#).unwrap(), "plain");
+        assert_eq!(string(r#""""#).unwrap(), "");
+        assert_eq!(string(r#""\" \\ \/ \b \f \n \r \t""#).unwrap(), "\" \\ / \u{8} \u{c} \n \r \t");
+        assert_eq!(string(r#""\u0041\u00e9\u4E2D""#).unwrap(), "Aé中");
+        assert_eq!(string(r#""\ud83d\ude00""#).unwrap(), "😀");
+        assert_eq!(string("\"grüße\"").unwrap(), "grüße");
+    }
+
+
+    #[test]
+    fn bad_strings()
+    {
+        for text in [r#""open"#, r#""\x""#, r#""\u12""#, r#""\u12g4""#, r#""\ud83d""#, r#""\ude00""#, "\"a\nb\""]
+        {
+            assert!(parse(text).is_err(), "{} should not parse", text);
+        }
+    }
+
+
+    #[test]
+    fn numbers()
+    {
+        for (text, number) in [("0", 0.0), ("-0", 0.0), ("12", 12.0), ("-3.25", -3.25), ("1e3", 1000.0), ("2.5E-1", 0.25), ("1e+2", 100.0)]
+        {
+            assert_eq!(parse(text).unwrap(), Value::Number(number), "{}", text);
+        }
+
+        for text in ["01", "-", "1.", ".5", "+1", "1e", "1e+", "--1", "0x10", "1.2.3"]
+        {
+            assert!(parse(text).is_err(), "{} should not parse", text);
+        }
+    }
+
+
+    #[test]
+    fn nesting()
+    {
+        let value = parse(r#" { "a": [1, {"b": [true, false, null]}, []], "c": {}, "a": "again" } "#).unwrap();
+        assert_eq!(value, Value::Object(vec![
+            ("a".to_string(), Value::Array(vec![
+                Value::Number(1.0),
+                Value::Object(vec![("b".to_string(), Value::Array(vec![Value::Bool(true), Value::Bool(false), Value::Null]))]),
+                Value::Array(vec![])
+            ])),
+            ("c".to_string(), Value::Object(vec![])),
+            ("a".to_string(), Value::String("again".to_string())),
+        ]));
+        //the first of duplicate keys wins
+        assert_eq!(value.get("a").and_then(|a| a.as_array()).map(|a| a.len()), Some(3));
+    }
+
+
+    #[test]
+    fn malformed()
+    {
+        let cases = [
+            "", "   ", "{", "}", "[1, 2", "[1 2]", "[1,]", "{\"a\" 1}", "{\"a\": 1,}", "{a: 1}",
+            "tru", "nul", "[] []", "{\"a\": }", "\"a\" \"b\"",
+        ];
+        for text in cases
+        {
+            assert!(parse(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+
+
+    #[test]
+    fn errors_name_the_line()
+    {
+        let err = parse("{\n  \"a\": 1,\n  \"b\": ?\n}").unwrap_err();
+        assert!(err.ends_with("on line 3"), "{}", err);
+    }
+}
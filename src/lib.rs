@@ -0,0 +1,38 @@
+// Everything but the entry point lives in the library so benches can get at it.
+
+//the renderer's errors are (), callers only need to know that it failed
+#![allow(clippy::result_unit_err)]
+
+pub mod window;
+pub mod camera;
+pub mod math;
+pub mod settings;
+pub mod shader_program;
+pub mod scene;
+pub mod chunk;
+pub mod vertex;
+pub mod mesh_pool;
+pub mod palette;
+pub mod light;
+pub mod lod;
+pub mod world;
+pub mod jobs;
+pub mod biome;
+pub mod cave;
+pub mod feature;
+pub mod block;
+pub mod json;
+pub mod physics;
+pub mod screenshot;
+pub mod region;
+pub mod util;
+pub mod renderer;
+pub mod opengl;
+pub mod vk;
+
+pub use settings::*;
+pub use std::ptr;
+pub use std::sync::{Mutex, Arc, Weak, mpsc};
+pub use shader_program::{ShaderProgram, UniformFn};
+pub use camera::Camera;
+
@@ -0,0 +1,778 @@
+use crate::{*, world::Face};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Per voxel light levels. Each byte holds the sky light in the high nibble and block
+// light in the low nibble. Chunks that were never lit read as open sky so meshes built
+// next to unloaded terrain don't come out black.
+pub const MAX_LIGHT: u8 = 15;
+const UNLIT: u8 = MAX_LIGHT << 4;
+
+#[derive(Clone, Debug)]
+pub enum LightMap {
+    Unlit,
+    Uniform(u8),
+    Full(Vec<u8>)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Channel {
+    Sky,
+    Block
+}
+
+impl Channel
+{
+    #[inline(always)]
+    pub fn get(self, light: u8) -> u8
+    {
+        match self
+        {
+            Channel::Sky => light >> 4,
+            Channel::Block => light & 0xF
+        }
+    }
+
+
+    #[inline(always)]
+    pub fn set(self, light: u8, level: u8) -> u8
+    {
+        match self
+        {
+            Channel::Sky => (light & 0x0F) | (level << 4),
+            Channel::Block => (light & 0xF0) | level
+        }
+    }
+}
+
+impl LightMap
+{
+    pub fn is_lit(&self) -> bool
+    {
+        !matches!(self, LightMap::Unlit)
+    }
+
+
+    #[inline(always)]
+    pub fn get(&self, idx: usize) -> u8
+    {
+        match self
+        {
+            LightMap::Unlit => UNLIT,
+            LightMap::Uniform(light) => *light,
+            LightMap::Full(light) => light[idx]
+        }
+    }
+
+
+    pub fn set(&mut self, idx: usize, light: u8)
+    {
+        if let LightMap::Full(data) = self
+        {
+            data[idx] = light;
+        }
+        else if !(self.is_lit() && self.get(idx) == light)
+        {
+            let mut data = vec![self.get(idx); CHUNK_VOL as usize];
+            data[idx] = light;
+            *self = LightMap::Full(data);
+        }
+    }
+
+
+    //collapses fully lit or fully dark chunks so open sky doesn't cost 32kb a chunk
+    pub fn from_vec(data: Vec<u8>) -> LightMap
+    {
+        if data.iter().all(|&light| light == data[0])
+        {
+            LightMap::Uniform(data[0])
+        }
+        else
+        {
+            LightMap::Full(data)
+        }
+    }
+}
+
+
+pub fn is_transparent(voxel: VOXELS) -> bool
+{
+    !block::get(voxel).opaque
+}
+
+
+//block light emitted by a voxel
+pub fn emission(voxel: VOXELS) -> u8
+{
+    block::get(voxel).emission
+}
+
+
+#[inline(always)]
+fn local_index(x: i32, y: i32, z: i32) -> usize
+{
+    (x + z*CHUNK_SIZE + y*CHUNK_AREA) as usize
+}
+
+
+fn split((x, y, z): (i32, i32, i32)) -> ((i32, i32, i32), usize)
+{
+    (
+        (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE)),
+        local_index(x.rem_euclid(CHUNK_SIZE), y.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE))
+    )
+}
+
+
+// Flood fill over loaded, lit chunks in global voxel coordinates. Every chunk whose light
+// changes (or whose neighbour's border light changes) is remembered so it can be remeshed.
+pub struct Lighting<'w>
+{
+    chunks: &'w mut HashMap<(i32, i32, i32), Arc<chunk::Chunk>>,
+    //buried chunks aren't loaded but keep the sky out of whatever is under them
+    heightmaps: &'w HashMap<(i32, i32), Arc<biome::Heightmap>>,
+    pub touched: HashSet<(i32, i32, i32)>,
+}
+
+impl<'w> Lighting<'w>
+{
+    pub fn new(
+        chunks: &'w mut HashMap<(i32, i32, i32), Arc<chunk::Chunk>>,
+        heightmaps: &'w HashMap<(i32, i32), Arc<biome::Heightmap>>
+    ) -> Lighting<'w>
+    {
+        Lighting{chunks, heightmaps, touched: HashSet::new()}
+    }
+
+
+    //None for voxels in chunks that are missing or not lit yet
+    fn voxel(&self, pos: (i32, i32, i32)) -> Option<VOXELS>
+    {
+        let (chunk_pos, idx) = split(pos);
+        self.chunks.get(&chunk_pos)
+            .filter(|chunk| chunk.light.is_lit())
+            .map(|chunk| chunk.voxels.get(idx))
+    }
+
+
+    fn light(&self, pos: (i32, i32, i32), channel: Channel) -> u8
+    {
+        let (chunk_pos, idx) = split(pos);
+        self.chunks.get(&chunk_pos)
+            .map_or(0, |chunk| channel.get(chunk.light.get(idx)))
+    }
+
+
+    fn set_light(&mut self, pos: (i32, i32, i32), channel: Channel, level: u8)
+    {
+        let (chunk_pos, idx) = split(pos);
+        let Some(chunk) = self.chunks.get_mut(&chunk_pos) else {
+            return;
+        };
+
+        let chunk = Arc::make_mut(chunk);
+        let light = chunk.light.get(idx);
+        chunk.light.set(idx, channel.set(light, level));
+
+        self.touched.insert(chunk_pos);
+        let (x, y, z) = (pos.0.rem_euclid(CHUNK_SIZE), pos.1.rem_euclid(CHUNK_SIZE), pos.2.rem_euclid(CHUNK_SIZE));
+        for (local, offset) in [(x, (1,0,0)), (y, (0,1,0)), (z, (0,0,1))]
+        {
+            if local == 0
+            {
+                self.touched.insert((chunk_pos.0 - offset.0, chunk_pos.1 - offset.1, chunk_pos.2 - offset.2));
+            }
+            else if local == CHUNK_SIZE - 1
+            {
+                self.touched.insert((chunk_pos.0 + offset.0, chunk_pos.1 + offset.1, chunk_pos.2 + offset.2));
+            }
+        }
+    }
+
+
+    pub fn propagate(&mut self, channel: Channel, mut queue: VecDeque<(i32, i32, i32)>)
+    {
+        while let Some(pos) = queue.pop_front()
+        {
+            let level = self.light(pos, channel);
+            if level <= 1
+            {
+                continue;
+            }
+
+            for face in Face::iter()
+            {
+                let (dx, dy, dz) = face.offset();
+                let next = (pos.0 + dx, pos.1 + dy, pos.2 + dz);
+                if !self.voxel(next).is_some_and(is_transparent)
+                {
+                    continue;
+                }
+
+                //sunlight falls straight down without fading
+                let new_level = if channel == Channel::Sky && level == MAX_LIGHT && matches!(face, Face::Bottom)
+                {
+                    MAX_LIGHT
+                }
+                else
+                {
+                    level - 1
+                };
+
+                if self.light(next, channel) < new_level
+                {
+                    self.set_light(next, channel, new_level);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+
+    //darkens everything lit through the given voxels and refills it from the surrounding light
+    pub fn remove(&mut self, channel: Channel, positions: &[(i32, i32, i32)])
+    {
+        let mut removal = VecDeque::new();
+        let mut refill = VecDeque::new();
+
+        for &pos in positions
+        {
+            removal.push_back((pos, self.light(pos, channel)));
+            self.set_light(pos, channel, 0);
+        }
+
+        while let Some((pos, level)) = removal.pop_front()
+        {
+            for face in Face::iter()
+            {
+                let (dx, dy, dz) = face.offset();
+                let next = (pos.0 + dx, pos.1 + dy, pos.2 + dz);
+                if self.voxel(next).is_none()
+                {
+                    continue;
+                }
+
+                let next_level = self.light(next, channel);
+                let fed_by_pos = next_level < level
+                    || (channel == Channel::Sky && level == MAX_LIGHT && matches!(face, Face::Bottom));
+
+                if next_level != 0 && fed_by_pos
+                {
+                    self.set_light(next, channel, 0);
+                    removal.push_back((next, next_level));
+                }
+                else if next_level != 0
+                {
+                    refill.push_back(next);
+                }
+            }
+        }
+
+        self.propagate(channel, refill);
+    }
+
+
+    //relights around voxels that just changed from old to new, all darkening goes first so
+    //light from one change doesn't refill what another one blocks
+    pub fn update_voxels(&mut self, changes: &[((i32, i32, i32), VOXELS, VOXELS)])
+    {
+        let changes: Vec<_> = changes.iter()
+            .filter(|(pos, _, _)| self.voxel(*pos).is_some())
+            .collect();
+
+        let blocked: Vec<_> = changes.iter()
+            .filter(|(_, old, new)| is_transparent(*old) && !is_transparent(*new))
+            .map(|(pos, _, _)| *pos)
+            .collect();
+        let darkened: Vec<_> = changes.iter()
+            .filter(|(_, old, new)| emission(*old) > 0 || (is_transparent(*old) && !is_transparent(*new)))
+            .map(|(pos, _, _)| *pos)
+            .collect();
+        if !blocked.is_empty()
+        {
+            self.remove(Channel::Sky, &blocked);
+        }
+        if !darkened.is_empty()
+        {
+            self.remove(Channel::Block, &darkened);
+        }
+
+        let opened: VecDeque<_> = changes.iter()
+            .filter(|(_, old, new)| !is_transparent(*old) && is_transparent(*new))
+            .flat_map(|(pos, _, _)| Face::iter().map(move |face| {
+                let (dx, dy, dz) = face.offset();
+                (pos.0 + dx, pos.1 + dy, pos.2 + dz)
+            }))
+            .collect();
+        self.propagate(Channel::Sky, opened.clone());
+        self.propagate(Channel::Block, opened);
+
+        let mut emitters = VecDeque::new();
+        for &&(pos, _, new) in changes.iter().filter(|(_, _, new)| emission(*new) > 0)
+        {
+            self.set_light(pos, Channel::Block, emission(new).max(self.light(pos, Channel::Block)));
+            emitters.push_back(pos);
+        }
+        self.propagate(Channel::Block, emitters);
+    }
+
+
+    // Lights a chunk that has just been generated or loaded, first on its own and then
+    // together with its lit neighbours.
+    pub fn light_chunk(&mut self, pos: (i32, i32, i32))
+    {
+        if let Some(lone) = LoneChunk::new(self.chunks, self.heightmaps, pos)
+        {
+            self.install(pos, lone.light());
+        }
+    }
+
+
+    // Hands a chunk the light it got on its own. The chunk above may have been lit since and
+    // shade some of it, the chunk below may have been lit as open sky before this chunk
+    // existed. Then light from lit neighbours is allowed to flow in and this chunk's light
+    // flows out to them.
+    pub fn install(&mut self, pos: (i32, i32, i32), light: LightMap)
+    {
+        let Some(chunk) = self.chunks.get_mut(&pos) else {
+            return;
+        };
+        Arc::make_mut(chunk).light = light;
+        self.touched.insert(pos);
+
+        let mut neighbour_lit = [false; 6];
+        for face in Face::iter()
+        {
+            let (dx, dy, dz) = face.offset();
+            neighbour_lit[face as usize] = self.chunks.get(&(pos.0 + dx, pos.1 + dy, pos.2 + dz))
+                .is_some_and(|n| n.light.is_lit());
+        }
+
+        //columns whose sky stops at the border, removed together so columns that are still
+        //lit don't refill ones being cleared
+        let (gx, gy, gz) = (pos.0*CHUNK_SIZE, pos.1*CHUNK_SIZE, pos.2*CHUNK_SIZE);
+        let mut shaded = Vec::new();
+        for x in 0..CHUNK_SIZE
+        {
+            for z in 0..CHUNK_SIZE
+            {
+                for (lit, top, under) in [
+                    (Face::Top, (gx + x, gy + CHUNK_SIZE, gz + z), (gx + x, gy + CHUNK_SIZE - 1, gz + z)),
+                    (Face::Bottom, (gx + x, gy, gz + z), (gx + x, gy - 1, gz + z))]
+                {
+                    if neighbour_lit[lit as usize]
+                        && self.light(under, Channel::Sky) == MAX_LIGHT && self.light(top, Channel::Sky) != MAX_LIGHT
+                    {
+                        shaded.push(under);
+                    }
+                }
+            }
+        }
+        if !shaded.is_empty()
+        {
+            self.remove(Channel::Sky, &shaded);
+        }
+
+        //both sides of every face with a lit neighbour
+        let mut sky_seeds = VecDeque::new();
+        let mut block_seeds = VecDeque::new();
+        for face in Face::iter()
+        {
+            if !neighbour_lit[face as usize]
+            {
+                continue;
+            }
+
+            for a in 0..CHUNK_SIZE
+            {
+                for b in 0..CHUNK_SIZE
+                {
+                    let (x, y, z) = match face
+                    {
+                        Face::Top => (a, CHUNK_SIZE, b),
+                        Face::Bottom => (a, -1, b),
+                        Face::Right => (CHUNK_SIZE, a, b),
+                        Face::Left => (-1, a, b),
+                        Face::Front => (a, b, CHUNK_SIZE),
+                        Face::Back => (a, b, -1)
+                    };
+                    let inside = |v: i32| v.clamp(0, CHUNK_SIZE - 1);
+                    for cell in [(gx + x, gy + y, gz + z), (gx + inside(x), gy + inside(y), gz + inside(z))]
+                    {
+                        if self.light(cell, Channel::Sky) > 1
+                        {
+                            sky_seeds.push_back(cell);
+                        }
+                        if self.light(cell, Channel::Block) > 1
+                        {
+                            block_seeds.push_back(cell);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.propagate(Channel::Sky, sky_seeds);
+        self.propagate(Channel::Block, block_seeds);
+    }
+}
+
+
+// A chunk and what it needs to be lit without its neighbours: the sky light along the bottom
+// of the chunk above if that is lit, whether the heightmap buries it otherwise. Cloned out of
+// the world so it can be lit on a job thread, the result goes through Lighting::install.
+pub struct LoneChunk
+{
+    pub chunk: Arc<chunk::Chunk>,
+    above: Option<Vec<u8>>,
+    buried: bool,
+}
+
+impl LoneChunk
+{
+    pub fn new(
+        chunks: &HashMap<(i32, i32, i32), Arc<chunk::Chunk>>,
+        heightmaps: &HashMap<(i32, i32), Arc<biome::Heightmap>>,
+        pos: (i32, i32, i32)
+    ) -> Option<LoneChunk>
+    {
+        let chunk = chunks.get(&pos)?.clone();
+        let above_pos = (pos.0, pos.1 + 1, pos.2);
+        let above = chunks.get(&above_pos)
+            .filter(|above| above.light.is_lit())
+            .map(|above| (0..CHUNK_AREA).map(|i| Channel::Sky.get(above.light.get(i as usize))).collect());
+        let buried = !chunks.contains_key(&above_pos) && world::is_buried(heightmaps, above_pos);
+        Some(LoneChunk{chunk, above, buried})
+    }
+
+
+    pub fn light(&self) -> LightMap
+    {
+        let chunk = &self.chunk;
+        let mut data = vec![0u8; CHUNK_VOL as usize];
+        let mut sky_seeds = VecDeque::new();
+        let mut block_seeds = VecDeque::new();
+
+        for x in 0..CHUNK_SIZE
+        {
+            for z in 0..CHUNK_SIZE
+            {
+                if self.buried
+                {
+                    break;
+                }
+                let top = self.above.as_ref().map_or(MAX_LIGHT, |above| above[local_index(x, 0, z)]);
+                if top != MAX_LIGHT
+                {
+                    continue;
+                }
+
+                let mut y = CHUNK_SIZE - 1;
+                while y >= 0 && is_transparent(chunk.get_voxel(x, y, z))
+                {
+                    data[local_index(x, y, z)] = Channel::Sky.set(0, MAX_LIGHT);
+                    y -= 1;
+                }
+            }
+        }
+
+        for y in 0..CHUNK_SIZE
+        {
+            for z in 0..CHUNK_SIZE
+            {
+                for x in 0..CHUNK_SIZE
+                {
+                    let idx = local_index(x, y, z);
+                    let voxel = chunk.get_voxel(x, y, z);
+                    if emission(voxel) > 0
+                    {
+                        data[idx] = Channel::Block.set(data[idx], emission(voxel));
+                        block_seeds.push_back((x, y, z));
+                    }
+
+                    //only sky cells next to something darker need to spread
+                    let spreads = Channel::Sky.get(data[idx]) == MAX_LIGHT
+                        && [Face::Right, Face::Left, Face::Front, Face::Back].iter().any(|&face| {
+                            let (dx, _, dz) = face.offset();
+                            let (nx, nz) = (x + dx, z + dz);
+                            (0..CHUNK_SIZE).contains(&nx) && (0..CHUNK_SIZE).contains(&nz)
+                                && is_transparent(chunk.get_voxel(nx, y, nz))
+                                && Channel::Sky.get(data[local_index(nx, y, nz)]) != MAX_LIGHT
+                        });
+                    if spreads
+                    {
+                        sky_seeds.push_back((x, y, z));
+                    }
+                }
+            }
+        }
+
+        self.propagate(&mut data, Channel::Sky, sky_seeds);
+        self.propagate(&mut data, Channel::Block, block_seeds);
+        LightMap::from_vec(data)
+    }
+
+
+    //Lighting::propagate in local coordinates, stopping at the chunk's border
+    fn propagate(&self, data: &mut [u8], channel: Channel, mut queue: VecDeque<(i32, i32, i32)>)
+    {
+        while let Some((x, y, z)) = queue.pop_front()
+        {
+            let level = channel.get(data[local_index(x, y, z)]);
+            if level <= 1
+            {
+                continue;
+            }
+
+            for face in Face::iter()
+            {
+                let (dx, dy, dz) = face.offset();
+                let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                let inside = [nx, ny, nz].iter().all(|v| (0..CHUNK_SIZE).contains(v));
+                if !inside || !is_transparent(self.chunk.get_voxel(nx, ny, nz))
+                {
+                    continue;
+                }
+
+                let new_level = if channel == Channel::Sky && level == MAX_LIGHT && matches!(face, Face::Bottom)
+                {
+                    MAX_LIGHT
+                }
+                else
+                {
+                    level - 1
+                };
+
+                let idx = local_index(nx, ny, nz);
+                if channel.get(data[idx]) < new_level
+                {
+                    data[idx] = channel.set(data[idx], new_level);
+                    queue.push_back((nx, ny, nz));
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn lamp() -> VOXELS
+    {
+        let id = block::registry().blocks.iter().position(|block| block.name == "lamp").unwrap();
+        VOXELS(id as u16)
+    }
+
+
+    //chunks built from a function of global voxel coordinates, lit together
+    fn world(chunks: &[(i32, i32, i32)], voxel: impl Fn(i32, i32, i32) -> VOXELS) -> world::World
+    {
+        let mut world = unlit(chunks, voxel);
+        world.light_chunks(chunks);
+        world
+    }
+
+
+    fn unlit(chunks: &[(i32, i32, i32)], voxel: impl Fn(i32, i32, i32) -> VOXELS) -> world::World
+    {
+        let mut world = world::World::new().without_store();
+        for &pos in chunks
+        {
+            let voxels = (0..CHUNK_VOL)
+                .map(|i| {
+                    let (x, y, z) = (i % CHUNK_SIZE, i / CHUNK_AREA, i / CHUNK_SIZE % CHUNK_SIZE);
+                    voxel(pos.0*CHUNK_SIZE + x, pos.1*CHUNK_SIZE + y, pos.2*CHUNK_SIZE + z)
+                })
+                .collect();
+            let mut chunk = chunk::Chunk::new(pos.0, pos.1, pos.2).with_voxels(voxels);
+            chunk.status = chunk::ChunkStatus::Clean;
+            world.chunks.insert(pos, Arc::new(chunk));
+        }
+        world
+    }
+
+
+    //hills with overhangs, caves and a few lamps, over a column of chunks and its neighbours
+    fn terrain(x: i32, y: i32, z: i32) -> VOXELS
+    {
+        let height = 20 + ((x as f32 * 0.3).sin() * 6.0 + (z as f32 * 0.2).cos() * 5.0) as i32;
+        let cave = (x - 20).pow(2) + (y - 5).pow(2) + (z - 10).pow(2) < 40;
+        let ledge = y == 35 && (10..30).contains(&x);
+        if (x * 7 + y * 13 + z * 3) % 97 == 0 && y < height
+        {
+            lamp()
+        }
+        else if (y < height && !cave) || ledge
+        {
+            VOXELS::COBBLESTONE
+        }
+        else
+        {
+            VOXELS::EMPTY
+        }
+    }
+
+
+    fn all_light(world: &world::World, chunks: &[(i32, i32, i32)]) -> Vec<u8>
+    {
+        chunks.iter()
+            .flat_map(|pos| (0..CHUNK_VOL).map(|i| world.chunks[pos].light.get(i as usize)))
+            .collect()
+    }
+
+
+    fn light(world: &world::World, (x, y, z): (i32, i32, i32), channel: Channel) -> u8
+    {
+        let chunk = &world.chunks[&(x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE))];
+        channel.get(chunk.get_light(x.rem_euclid(CHUNK_SIZE), y.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE)))
+    }
+
+
+    #[test]
+    fn sky_light_under_an_overhang()
+    {
+        //a roof over the x < 16 half of the chunk, open to the sky past it
+        let world = world(&[(0, 0, 0)], |x, y, _| {
+            if y == 20 && x < 16 {VOXELS::COBBLESTONE} else {VOXELS::EMPTY}
+        });
+
+        assert_eq!(light(&world, (8, 25, 8), Channel::Sky), MAX_LIGHT);
+        assert_eq!(light(&world, (20, 10, 8), Channel::Sky), MAX_LIGHT);
+        //fading in sideways from the open column at x 16
+        assert_eq!(light(&world, (15, 10, 8), Channel::Sky), MAX_LIGHT - 1);
+        assert_eq!(light(&world, (8, 10, 8), Channel::Sky), MAX_LIGHT - 8);
+        assert_eq!(light(&world, (0, 10, 8), Channel::Sky), 0);
+        assert_eq!(light(&world, (0, 19, 8), Channel::Block), 0);
+    }
+
+
+    #[test]
+    fn sealed_caves_stay_dark()
+    {
+        //stone with a hollow room, no way in for the sky
+        let world = world(&[(0, 0, 0)], |x, y, z| {
+            let room = (8..16).contains(&x) && (4..10).contains(&y) && (8..16).contains(&z);
+            if room || y > 24 {VOXELS::EMPTY} else {VOXELS::COBBLESTONE}
+        });
+
+        assert_eq!(light(&world, (8, 30, 8), Channel::Sky), MAX_LIGHT);
+        assert_eq!(light(&world, (12, 6, 12), Channel::Sky), 0);
+        assert_eq!(light(&world, (12, 6, 12), Channel::Block), 0);
+    }
+
+
+    #[test]
+    fn lamp_light_crosses_a_chunk_border()
+    {
+        let mut world = world(&[(0, 0, 0), (1, 0, 0)], |_, _, _| VOXELS::EMPTY);
+        world.set_voxel(31, 5, 5, lamp()).unwrap();
+
+        let emitted = emission(lamp());
+        assert_eq!(light(&world, (31, 5, 5), Channel::Block), emitted);
+        assert_eq!(light(&world, (30, 5, 5), Channel::Block), emitted - 1);
+        assert_eq!(light(&world, (32, 5, 5), Channel::Block), emitted - 1);
+        assert_eq!(light(&world, (35, 5, 7), Channel::Block), emitted - 6);
+        //the neighbour's light changed so it has to be remeshed
+        assert_eq!(world.chunks[&(1, 0, 0)].status, chunk::ChunkStatus::Dirty);
+
+        world.set_voxel(31, 5, 5, VOXELS::EMPTY).unwrap();
+        for x in 25..40
+        {
+            assert_eq!(light(&world, (x, 5, 5), Channel::Block), 0, "{}", x);
+        }
+        //the sky is left alone
+        assert_eq!(light(&world, (32, 5, 5), Channel::Sky), MAX_LIGHT);
+    }
+
+
+    #[test]
+    fn breaking_a_roof_lets_the_sky_in()
+    {
+        //a closed roof over the whole chunk
+        let mut world = world(&[(0, 0, 0)], |_, y, _| {
+            if y == 20 {VOXELS::COBBLESTONE} else {VOXELS::EMPTY}
+        });
+        assert_eq!(light(&world, (8, 10, 8), Channel::Sky), 0);
+
+        world.set_voxel(8, 20, 8, VOXELS::EMPTY).unwrap();
+        assert_eq!(light(&world, (8, 20, 8), Channel::Sky), MAX_LIGHT);
+        assert_eq!(light(&world, (8, 0, 8), Channel::Sky), MAX_LIGHT);
+        assert_eq!(light(&world, (10, 10, 8), Channel::Sky), MAX_LIGHT - 2);
+
+        //and closing it again takes it all back
+        world.set_voxel(8, 20, 8, VOXELS::COBBLESTONE).unwrap();
+        for pos in [(8, 19, 8), (8, 0, 8), (10, 10, 8)]
+        {
+            assert_eq!(light(&world, pos, Channel::Sky), 0, "{:?}", pos);
+        }
+    }
+
+
+    //what the scene does: every chunk lit on its own from whatever was lit when its job
+    //started, in an order that has the sky come in from below, then merged
+    #[test]
+    fn lighting_chunks_alone_matches_lighting_them_together()
+    {
+        let chunks: Vec<_> = (-1..=1)
+            .flat_map(|x| (0..2).flat_map(move |y| (-1..=1).map(move |z| (x, y, z))))
+            .collect();
+        let expected = all_light(&world(&chunks, terrain), &chunks);
+
+        let mut world = unlit(&chunks, terrain);
+        let mut order = chunks.clone();
+        order.sort_by_key(|pos| (pos.1, pos.0 * 3 + pos.2));
+        for batch in order.chunks(5)
+        {
+            let jobs: Vec<_> = batch.iter()
+                .map(|&pos| LoneChunk::new(&world.chunks, &world.heightmaps, pos).unwrap())
+                .map(|lone| {
+                    let light = lone.light();
+                    (lone, light)
+                })
+                .collect();
+            for (lone, light) in jobs
+            {
+                assert!(world.apply_light(&lone, light));
+            }
+        }
+        assert!(all_light(&world, &chunks) == expected);
+
+        //a chunk edited while its job ran has to be lit again
+        let mut world = unlit(&[(0, 0, 0)], terrain);
+        let lone = LoneChunk::new(&world.chunks, &world.heightmaps, (0, 0, 0)).unwrap();
+        world.apply_edits(vec![feature::Edit{pos: (1, 1, 1), voxel: VOXELS::EMPTY, replace: feature::Replace::Any}]).unwrap();
+        let light = lone.light();
+        assert!(!world.apply_light(&lone, light));
+        assert!(!world.chunks[&(0, 0, 0)].light.is_lit());
+    }
+
+
+    //feature edits on lit chunks are written and relit per chunk, it comes out the same as
+    //one set_voxel after another
+    #[test]
+    fn batched_edits_light_like_single_ones()
+    {
+        let chunks = [(0, 0, 0), (1, 0, 0)];
+        let edits: Vec<_> = (0..40)
+            .map(|i| feature::Edit{
+                pos: (20 + i, 20 + i % 7, 10 + i % 5),
+                voxel: [lamp(), VOXELS::EMPTY, VOXELS::COBBLESTONE][i as usize % 3],
+                replace: feature::Replace::Any
+            })
+            .collect();
+
+        let mut single = world(&chunks, terrain);
+        for edit in edits.iter()
+        {
+            single.set_voxel(edit.pos.0, edit.pos.1, edit.pos.2, edit.voxel).unwrap();
+        }
+        let mut batched = world(&chunks, terrain);
+        batched.apply_edits(edits).unwrap();
+
+        assert!(all_light(&batched, &chunks) == all_light(&single, &chunks));
+        assert_eq!(batched.get_voxel(20, 20, 10), lamp());
+        assert!(!batched.chunks[&(0, 0, 0)].modified);
+    }
+}
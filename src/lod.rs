@@ -0,0 +1,370 @@
+use crate::{*, chunk::MeshSource, world::{ChunkCluster, Face}};
+
+// Distant chunks are meshed from a coarser copy of themselves where every cell stands for a
+// cube of 1 << lod voxels. A cell takes the most common voxel of its cube unless at least half
+// of the cube is empty, and the brightest light in it. Two neighbours of the same lod read each
+// other's cells the same way, so their shared side culls like inside a chunk.
+//
+// Next to a finer neighbour the coarse chunk stitches the border itself. Its outer layer of
+// cells is filled as soon as one voxel of the cube is, preferring opaque ones, so it never ends
+// up below the finer surface and every side face the finer chunk culled against it is covered.
+// Its side faces towards the finer chunk form a skirt hanging past the coarse surface down to
+// where the finer one is, only culled where the finer cube next to it is opaque all the way.
+pub struct LodCluster {
+    size: i32,
+    //(size+2)^3 cells starting at -1 on every axis, x fastest then z then y like a chunk
+    voxels: Vec<VOXELS>,
+    light: Vec<u8>,
+}
+
+//lod a chunk is meshed at and the lod of its neighbour on each side in Face order. Only finer
+//neighbours change the mesh, coarser ones are kept at the chunk's own lod
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Levels {
+    pub lod: u32,
+    pub faces: [u32; 6],
+}
+
+impl Levels {
+    pub fn new(pos: (i32, i32, i32), center: (i32, i32, i32)) -> Levels
+    {
+        let lod = level(util::chunk_distance(pos, center));
+        let mut faces = [lod; 6];
+        for face in Face::iter()
+        {
+            let (dx, dy, dz) = face.offset();
+            let neighbour = (pos.0 + dx, pos.1 + dy, pos.2 + dz);
+            faces[face as usize] = level(util::chunk_distance(neighbour, center)).min(lod);
+        }
+        Levels{lod, faces}
+    }
+
+
+    //every neighbour at the chunk's own lod
+    pub fn uniform(lod: u32) -> Levels
+    {
+        Levels{lod, faces: [lod; 6]}
+    }
+}
+
+impl LodCluster {
+    pub fn new(chunk_cluster: &ChunkCluster, levels: Levels) -> LodCluster
+    {
+        let size = CHUNK_SIZE >> levels.lod;
+        let scale = 1 << levels.lod;
+        let padded = (size + 2) as usize;
+        let mut voxels = Vec::with_capacity(padded * padded * padded);
+        let mut light = Vec::with_capacity(padded * padded * padded);
+        //(voxel, count) of the cube, a cube holds at most 1 << 3*lod different ones
+        let mut counts: Vec<(VOXELS, u32)> = Vec::new();
+        let finer = |face: Face| levels.faces[face as usize] < levels.lod;
+
+        for y in -1..=size
+        {
+            for z in -1..=size
+            {
+                for x in -1..=size
+                {
+                    counts.clear();
+                    let (mut sky, mut block) = (0, 0);
+                    for (dx, dy, dz) in LodCluster::cube(scale)
+                    {
+                        let (vx, vy, vz) = (x*scale + dx, y*scale + dy, z*scale + dz);
+                        let voxel = chunk_cluster.get_voxel(vx, vy, vz);
+                        match counts.iter_mut().find(|(v, _)| *v == voxel) {
+                            Some((_, count)) => *count += 1,
+                            None => counts.push((voxel, 1))
+                        }
+                        let level = chunk_cluster.get_light(vx, vy, vz);
+                        sky = sky.max(light::Channel::Sky.get(level));
+                        block = block.max(light::Channel::Block.get(level));
+                    }
+
+                    let voxel = match LodCluster::side(size, x, y, z) {
+                        Some(face) if finer(face) => LodCluster::skirt(&counts),
+                        Some(_) => LodCluster::downsample(&counts),
+                        None if LodCluster::layer(size, x, y, z).any(finer) => LodCluster::covering(&counts),
+                        None => LodCluster::downsample(&counts)
+                    };
+                    voxels.push(voxel);
+                    light.push(light::Channel::Sky.set(light::Channel::Block.set(0, block), sky));
+                }
+            }
+        }
+
+        LodCluster{size, voxels, light}
+    }
+
+
+    fn cube(scale: i32) -> impl Iterator<Item = (i32, i32, i32)>
+    {
+        (0..scale).flat_map(move |dy| {
+            (0..scale).flat_map(move |dz| {
+                (0..scale).map(move |dx| (dx, dy, dz))
+            })
+        })
+    }
+
+
+    //the neighbour a cell past the chunk belongs to, the mesher only reads the ones off a side
+    fn side(size: i32, x: i32, y: i32, z: i32) -> Option<Face>
+    {
+        match (x, y, z) {
+            (x, _, _) if x < 0 => Some(Face::Left),
+            (x, _, _) if x >= size => Some(Face::Right),
+            (_, y, _) if y < 0 => Some(Face::Bottom),
+            (_, y, _) if y >= size => Some(Face::Top),
+            (_, _, z) if z < 0 => Some(Face::Back),
+            (_, _, z) if z >= size => Some(Face::Front),
+            _ => None
+        }
+    }
+
+
+    //sides of the chunk a cell inside it touches
+    fn layer(size: i32, x: i32, y: i32, z: i32) -> impl Iterator<Item = Face>
+    {
+        Face::iter().filter(move |face| match face {
+            Face::Left => x == 0,
+            Face::Right => x == size - 1,
+            Face::Bottom => y == 0,
+            Face::Top => y == size - 1,
+            Face::Back => z == 0,
+            Face::Front => z == size - 1
+        })
+    }
+
+
+    //ties go to the higher id so remeshing the same voxels always picks the same one
+    fn downsample(counts: &[(VOXELS, u32)]) -> VOXELS
+    {
+        let filled = counts.iter().filter(|(voxel, _)| *voxel != VOXELS::EMPTY);
+        let total: u32 = counts.iter().map(|(_, count)| count).sum();
+        if filled.clone().map(|(_, count)| count).sum::<u32>() * 2 < total
+        {
+            return VOXELS::EMPTY;
+        }
+        LodCluster::most_common(filled)
+    }
+
+
+    //border cells next to a finer chunk, opaque if anything in the cube is so they hide
+    //whatever the finer chunk culled against them. Where downsample picks an opaque voxel this
+    //picks the same one, a neighbour of this lod reading it culls the same faces
+    fn covering(counts: &[(VOXELS, u32)]) -> VOXELS
+    {
+        let mut opaque = counts.iter().filter(|(voxel, _)| block::get(*voxel).opaque).peekable();
+        if opaque.peek().is_some()
+        {
+            return LodCluster::most_common(opaque);
+        }
+        LodCluster::downsample(counts)
+    }
+
+
+    //cells of a finer neighbour only hide the skirt when the whole cube is opaque
+    fn skirt(counts: &[(VOXELS, u32)]) -> VOXELS
+    {
+        if counts.iter().all(|(voxel, _)| block::get(*voxel).opaque)
+        {
+            return LodCluster::downsample(counts);
+        }
+        let voxel = LodCluster::downsample(counts);
+        if block::get(voxel).translucent {voxel} else {VOXELS::EMPTY}
+    }
+
+
+    fn most_common<'a>(counts: impl Iterator<Item = &'a (VOXELS, u32)>) -> VOXELS
+    {
+        counts.max_by_key(|&&(voxel, count)| (count, voxel.0))
+            .map_or(VOXELS::EMPTY, |&(voxel, _)| voxel)
+    }
+
+
+    #[inline(always)]
+    fn index(&self, x:i32, y:i32, z:i32) -> usize
+    {
+        let padded = self.size + 2;
+        ((x+1) + (z+1)*padded + (y+1)*padded*padded) as usize
+    }
+}
+
+impl MeshSource for LodCluster {
+    fn size(&self) -> i32
+    {
+        self.size
+    }
+
+    fn get_voxel(&self, x:i32, y:i32, z:i32) -> VOXELS
+    {
+        self.voxels[self.index(x, y, z)]
+    }
+
+    fn get_light(&self, x:i32, y:i32, z:i32) -> u8
+    {
+        self.light[self.index(x, y, z)]
+    }
+}
+
+
+//lod a chunk this many chunks away from the player is meshed at
+pub fn level(distance: i32) -> u32
+{
+    LOD_DISTANCES.iter().filter(|&&start| distance >= start).count() as u32
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::world::World;
+    use std::{collections::HashSet, sync::Arc};
+
+    //chunks (0, 0, 0) and (1, 0, 0) filled with stone where solid says so, x is global
+    fn world(solid: impl Fn(i32, i32, i32) -> bool) -> World
+    {
+        let mut world = World::new().without_store();
+        for cx in 0..2
+        {
+            let mut chunk = chunk::Chunk::new(cx, 0, 0);
+            for y in 0..CHUNK_SIZE
+            {
+                for z in 0..CHUNK_SIZE
+                {
+                    for x in 0..CHUNK_SIZE
+                    {
+                        if solid(cx*CHUNK_SIZE + x, y, z)
+                        {
+                            chunk.set_voxel(x, y, z, VOXELS::COBBLESTONE).unwrap();
+                        }
+                    }
+                }
+            }
+            world.chunks.insert((cx, 0, 0), Arc::new(chunk));
+        }
+        world.light_chunks(&[(0, 0, 0), (1, 0, 0)]);
+        world
+    }
+
+
+    //bumpy ground with loose voxels over it, so coarse cells often end up above or below it
+    fn terrain() -> World
+    {
+        world(|x, y, z| {
+            let height = 8 + (x*7 + z*13) % 11 + (x/5 + z/3) % 4;
+            y < height || (x*31 + y*17 + z*11) % 29 == 0
+        })
+    }
+
+
+    fn levels(lod: u32, finer: &[(Face, u32)]) -> Levels
+    {
+        let mut levels = Levels::uniform(lod);
+        for &(face, level) in finer
+        {
+            levels.faces[face as usize] = level;
+        }
+        levels
+    }
+
+
+    //the voxel a chunk meshed with these levels shows at a voxel of it
+    fn shown(world: &World, pos: (i32, i32, i32), levels: Levels) -> Box<dyn Fn(i32, i32, i32) -> VOXELS>
+    {
+        let cluster = ChunkCluster::new(world, pos.0, pos.1, pos.2);
+        if levels.lod == 0
+        {
+            return Box::new(move |x, y, z| cluster.get_voxel(x, y, z));
+        }
+        let lod = LodCluster::new(&cluster, levels);
+        Box::new(move |x, y, z| lod.get_voxel(x >> levels.lod, y >> levels.lod, z >> levels.lod))
+    }
+
+
+    //(y, z) of every voxel the mesh has a face on towards x, depth is the layer of cells it's on
+    fn sides(world: &World, pos: (i32, i32, i32), levels: Levels, face: Face, depth: u32) -> HashSet<(i32, i32)>
+    {
+        let cluster = ChunkCluster::new(world, pos.0, pos.1, pos.2);
+        let mesh = world.chunks[&pos].get_mesh(cluster, levels);
+        let scale = 1 << levels.lod;
+        let mut covered = HashSet::new();
+        for data in mesh.quads.iter().map(|quad| quad.unpack())
+        {
+            if data.face != face as u32 || data.depth != depth
+            {
+                continue;
+            }
+            for u in data.u * scale..(data.u + data.width) * scale
+            {
+                for v in data.v * scale..(data.v + data.height) * scale
+                {
+                    covered.insert((u as i32, v as i32));
+                }
+            }
+        }
+        covered
+    }
+
+
+    //wherever one side of the border between the chunks shows a voxel and the other doesn't,
+    //the one showing it has a face there
+    fn assert_closed(world: &World, fine: Levels, coarse: Levels)
+    {
+        let depth = ((CHUNK_SIZE >> fine.lod) - 1) as u32;
+        let fine_sides = sides(world, (0, 0, 0), fine, Face::Right, depth);
+        let coarse_sides = sides(world, (1, 0, 0), coarse, Face::Left, 0);
+        let fine_shown = shown(world, (0, 0, 0), fine);
+        let coarse_shown = shown(world, (1, 0, 0), coarse);
+
+        for y in 0..CHUNK_SIZE
+        {
+            for z in 0..CHUNK_SIZE
+            {
+                let a = fine_shown(CHUNK_SIZE - 1, y, z) != VOXELS::EMPTY;
+                let b = coarse_shown(0, y, z) != VOXELS::EMPTY;
+                assert!(!a || b || fine_sides.contains(&(y, z)), "gap on the fine side at y {} z {}", y, z);
+                assert!(!b || a || coarse_sides.contains(&(y, z)), "gap on the coarse side at y {} z {}", y, z);
+            }
+        }
+    }
+
+
+    #[test]
+    fn borders_between_two_levels_have_no_gaps()
+    {
+        let world = terrain();
+        assert_closed(&world, Levels::uniform(0), levels(1, &[(Face::Left, 0)]));
+        assert_closed(&world, Levels::uniform(1), levels(2, &[(Face::Left, 1)]));
+        assert_closed(&world, Levels::uniform(1), Levels::uniform(1));
+    }
+
+
+    #[test]
+    fn skirts_are_culled_against_solid_and_same_lod_neighbours()
+    {
+        //next to a chunk of the same lod there's only a face where that chunk shows nothing
+        let bumpy = terrain();
+        let neighbour = shown(&bumpy, (0, 0, 0), Levels::uniform(1));
+        let coarse_sides = sides(&bumpy, (1, 0, 0), Levels::uniform(1), Face::Left, 0);
+        assert!(!coarse_sides.is_empty());
+        assert!(coarse_sides.iter().all(|&(y, z)| neighbour(CHUNK_SIZE - 1, y, z) == VOXELS::EMPTY));
+
+        //a solid finer chunk hides the whole side
+        let solid = world(|x, y, _| x < CHUNK_SIZE || y < 20);
+        assert!(sides(&solid, (1, 0, 0), levels(1, &[(Face::Left, 0)]), Face::Left, 0).is_empty());
+    }
+
+
+    #[test]
+    fn levels_only_keep_finer_neighbours()
+    {
+        let levels = Levels::new((3, 0, 0), (0, 0, 0));
+        assert_eq!(levels.lod, 1);
+        assert_eq!(levels.faces, [1, 1, 1, 0, 1, 1]);
+
+        //coarser neighbours are kept at the chunk's own lod
+        assert_eq!(Levels::new((2, 0, 0), (0, 0, 0)), Levels::uniform(0));
+        assert_eq!(Levels::new((5, 0, 0), (0, 0, 0)).faces[Face::Right as usize], 2);
+    }
+}
@@ -1,24 +1,25 @@
-mod window;
-mod camera;
-mod math;
-mod settings;
-mod shader_program;
-mod scene;
-mod chunk;
-mod world;
-mod util;
-mod renderer;
-mod opengl;
-mod vk;
-
-pub use settings::*;
-pub use std::ptr;
-pub use std::sync::{Mutex, Arc, Weak, mpsc};
-pub use shader_program::{ShaderProgram, UniformFn};
-pub use camera::Camera;
-
+use rust_engine::{screenshot, window};
 
 fn main() {
-    let mut app = window::VoxelEngine::new();
-    app.run();
+    match screenshot::ScreenshotInfo::from_args(std::env::args().skip(1))
+    {
+        Ok(Some(info)) => 
+        {
+            if let Err(err) = screenshot::run(info) 
+            {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        Ok(None) => 
+        {
+            let mut app = window::VoxelEngine::new();
+            app.run();
+        }
+        Err(err) => 
+        {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 }
\ No newline at end of file
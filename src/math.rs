@@ -1,11 +1,5 @@
-use std::process::Output;
-
-use image::codecs::webp;
-
-use crate::settings::CHUNK_SIZE;
-
 pub const H_PI:f32 = 1.57;
-pub const ROOT_3: f32 = 1.7320508076;
+pub const ROOT_3: f32 = 1.732_050_8;
 pub const IDENTITY:[f32;16] = [
     1.0,0.0,0.0,0.0,
     0.0,1.0,0.0,0.0,
@@ -54,12 +48,45 @@ pub fn normalize(v:&mut [f32;3]) -> [f32;3] {
     *v
 }
 
+//row major, same layout as the camera matrices
 #[inline(always)]
-pub fn get_model(pos:(i32, i32, i32)) ->[f32;16] {
-    [
-        1.0, 0.0, 0.0, (pos.0 * CHUNK_SIZE) as f32,
-        0.0, 1.0, 0.0, (pos.1 * CHUNK_SIZE) as f32,
-        0.0, 0.0, 1.0, (pos.2 * CHUNK_SIZE) as f32,
-        0.0, 0.0, 0.0,          1.0
-    ]
-}
\ No newline at end of file
+pub fn mat_mul(a:&[f32;16], b:&[f32;16]) -> [f32;16] {
+    let mut res = [0.0; 16];
+    for row in 0..4 {
+        for col in 0..4 {
+            res[row*4+col] = (0..4).map(|i| a[row*4+i]*b[i*4+col]).sum();
+        }
+    }
+    res
+}
+
+
+//planes as (a, b, c, d) with a*x + b*y + c*z + d >= 0 on the inside
+pub struct Frustum {
+    planes: [[f32; 4]; 6]
+}
+
+impl Frustum {
+    //gribb/hartmann extraction, clip space z in -w..w
+    pub fn from_view_proj(m:&[f32;16]) -> Frustum {
+        let row = |i:usize| [m[i*4], m[i*4+1], m[i*4+2], m[i*4+3]];
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let add = |a:[f32;4], b:[f32;4]| [a[0]+b[0], a[1]+b[1], a[2]+b[2], a[3]+b[3]];
+        let sub = |a:[f32;4], b:[f32;4]| [a[0]-b[0], a[1]-b[1], a[2]-b[2], a[3]-b[3]];
+        Frustum{planes: [
+            add(r3, r0), sub(r3, r0),
+            add(r3, r1), sub(r3, r1),
+            add(r3, r2), sub(r3, r2)
+        ]}
+    }
+
+    //tests the corner furthest along each plane normal, boxes that straddle a plane are kept
+    pub fn intersects_aabb(&self, min:[f32;3], max:[f32;3]) -> bool {
+        self.planes.iter().all(|[a, b, c, d]| {
+            let x = if *a >= 0.0 {max[0]} else {min[0]};
+            let y = if *b >= 0.0 {max[1]} else {min[1]};
+            let z = if *c >= 0.0 {max[2]} else {min[2]};
+            a*x + b*y + c*z + d >= 0.0
+        })
+    }
+}
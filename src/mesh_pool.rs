@@ -0,0 +1,118 @@
+use crate::*;
+use std::collections::VecDeque;
+
+// Every chunk's quads live in one storage buffer that the chunk shaders pull from, a mesh is
+// just a range of quads in it plus a slot in a second buffer holding the chunk's origin.
+// Quads go in through Api::upload, a mesh can be drawn once uploads_ready reaches its ticket.
+// A mesh draws with its slot as the instance index so a whole pass fits in one indirect draw.
+// Ranges come first fit out of a util::FreeList. Frames still in flight may be
+// drawing a freed mesh, so it only becomes free again MESH_RETIRE_FRAMES frames later and
+// once its own upload is done.
+pub struct MeshPool {
+    pub buffer: Box<dyn renderer::Buffer>,
+    //ivec4 per slot, the chunk's origin and its lod in w
+    pub origins: Box<dyn renderer::Buffer>,
+    //in quads
+    free: util::FreeList,
+    free_slots: Vec<u32>,
+    retired: VecDeque<(u64, Mesh)>,
+    frame: u64,
+    pub used: u32,
+    //quads of freed meshes that aren't free again yet
+    pub retiring: u32,
+    api: Arc<renderer::ApiHandle>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Mesh {
+    pub start: u32,
+    pub len: u32,
+    pub slot: u32,
+    //upload ticket of the quads
+    pub ready: u64,
+}
+
+impl Mesh {
+    pub fn draw(&self) -> renderer::DrawIndirect
+    {
+        renderer::DrawIndirect{
+            vertex_count: self.len * vertex::QUAD_VERTICES,
+            instance_count: 1,
+            first_vertex: self.start * vertex::QUAD_VERTICES,
+            first_instance: self.slot
+        }
+    }
+}
+
+impl MeshPool {
+    pub fn new(api: Arc<renderer::ApiHandle>) -> MeshPool
+    {
+        let buffer = api.inner.create_buffer(
+            renderer::BufferCreateInfo::DeviceStorage(QUAD_POOL_SIZE as usize * size_of::<vertex::Quad>()))
+            .expect("failed to create quad buffer");
+
+        let origins = api.inner.create_buffer(
+            renderer::BufferCreateInfo::Storage(MESH_SLOTS as usize * size_of::<[i32; 4]>()))
+            .expect("failed to create chunk origin buffer");
+
+        MeshPool{
+            buffer,
+            origins,
+            free: util::FreeList::new(QUAD_POOL_SIZE as u64),
+            free_slots: (0..MESH_SLOTS).rev().collect(),
+            retired: VecDeque::new(),
+            frame: 0,
+            used: 0,
+            retiring: 0,
+            api
+        }
+    }
+
+
+    //copies the quads in, Err when there is no free slot or range big enough
+    pub fn alloc(&mut self, pos: (i32, i32, i32), lod: u32, quads: &[vertex::Quad]) -> Result<Mesh, ()>
+    {
+        let len = quads.len() as u32;
+        let &slot = self.free_slots.last().ok_or(())?;
+        let start = self.free.alloc(len as u64, 1).ok_or(())? as u32;
+        self.free_slots.pop();
+
+        let ready = self.api.inner.upload(
+            self.buffer.as_ref(), 
+            vertex::Quad::as_bytes(quads), 
+            (start as usize * size_of::<vertex::Quad>()) as i32);
+        let origin = [pos.0 * CHUNK_SIZE, pos.1 * CHUNK_SIZE, pos.2 * CHUNK_SIZE, lod as i32];
+        let origin: Vec<u8> = origin.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        self.origins.sub_data(&origin, (slot as usize * origin.len()) as i32);
+
+        self.used += len;
+        Ok(Mesh{start, len, slot, ready})
+    }
+
+
+    pub fn free(&mut self, mesh: Mesh)
+    {
+        self.retiring += mesh.len;
+        self.retired.push_back((self.frame, mesh));
+    }
+
+
+    //call once per submitted frame
+    pub fn end_frame(&mut self)
+    {
+        self.frame += 1;
+        let ready = self.api.inner.uploads_ready();
+        while let Some(&(frame, mesh)) = self.retired.front()
+        {
+            if frame + MESH_RETIRE_FRAMES > self.frame || mesh.ready > ready
+            {
+                break;
+            }
+            self.retired.pop_front();
+            self.free_slots.push(mesh.slot);
+            self.free.free(mesh.start as u64, mesh.len as u64);
+            self.used -= mesh.len;
+            self.retiring -= mesh.len;
+        }
+    }
+}
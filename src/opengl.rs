@@ -4,10 +4,18 @@ use glow::HasContext;
 use crate::*;
 use crate::renderer::*;
 
+//glMultiDrawArraysIndirect, glow doesn't wrap it
+type MultiDrawArraysIndirect = unsafe extern "system" fn(mode: u32, indirect: *const std::ffi::c_void, count: i32, stride: i32);
+
 pub struct GLinner 
 {
     gl: Arc<glow::Context>,
-    pwindow: *mut glfw::ffi::GLFWwindow
+    pwindow: *mut glfw::ffi::GLFWwindow,
+    uploads: std::cell::Cell<u64>,
+    //core since 4.3, drivers that don't expose it fall back to one draw per command
+    multi_draw: Option<MultiDrawArraysIndirect>,
+    //the never shown window a headless context lives in, goes after the context
+    hidden: Option<(glfw::PWindow, glfw::Glfw)>,
 }
 
 impl GLinner 
@@ -34,11 +42,22 @@ impl GLinner
             }
 
             let pwindow = <glfw::Window as glfw::Context>::window_ptr(window);
+            let multi_draw = window.get_proc_address("glMultiDrawArraysIndirect")
+                .map(|ptr| std::mem::transmute::<_, MultiDrawArraysIndirect>(ptr));
 
-            GLinner{gl: Arc::new(gl), pwindow}
+            GLinner{gl: Arc::new(gl), pwindow, uploads: std::cell::Cell::new(0), multi_draw, hidden: None}
 
         }
     }
+
+
+    //the window has to have the current context
+    pub fn headless(mut window: glfw::PWindow, glfw: glfw::Glfw) -> GLinner 
+    {
+        let mut inner = GLinner::new(&mut window);
+        inner.hidden = Some((window, glfw));
+        inner
+    }
 }
 
 impl Api for GLinner 
@@ -46,6 +65,7 @@ impl Api for GLinner
     fn create_pipeline(&self, pipeline_info: PipelineInfo) -> Result<Box<dyn Pipeline>, ()> 
     {
         let mut pipeline = GLPipeline::new(self.gl.clone());
+        pipeline.blend_mode = pipeline_info.blend_mode;
         pipeline.add_shader_program(pipeline_info.shader_info);
         pipeline.add_vertex_description(pipeline_info.vbo_layout);
         for descriptor_layout in pipeline_info.descriptor_layouts 
@@ -57,7 +77,7 @@ impl Api for GLinner
 
     fn create_command_buffer<'a>(&self) -> Result<Box<dyn CommandBuffer<'a> + 'a>, ()>
     {
-        Ok(Box::new(GLCommandBuffer::new(&self)))
+        Ok(Box::new(GLCommandBuffer::new(self)))
     }
 
     fn create_buffer(&self, buffer_memory: BufferCreateInfo) -> Result<Box<dyn Buffer>, ()> 
@@ -67,8 +87,58 @@ impl Api for GLinner
 
     fn create_texture(&self, texture_info: TextureCreateInfo) -> Result<Box<dyn Texture>, ()> 
     {
-        let res = Ok(Box::new(GLTexture::new(self.gl.clone(), texture_info)) as _);
-        res
+        Ok(Box::new(GLTexture::new(self.gl.clone(), texture_info)) as _)
+    }
+
+    fn resize(&self, width: u32, height: u32) 
+    {
+        unsafe 
+        {
+            self.gl.viewport(0, 0, width as i32, height as i32);
+        }
+    }
+
+    //reads the default framebuffer, works for hidden windows too
+    fn read_frame(&self) -> Result<Vec<u8>, ()> 
+    {
+        let (mut width, mut height) = (0, 0);
+        unsafe 
+        {
+            glfw::ffi::glfwGetFramebufferSize(self.pwindow, &mut width, &mut height);
+        }
+
+        let row = width as usize * 4;
+        let mut pixels = vec![0u8; row * height as usize];
+        unsafe 
+        {
+            self.gl.finish();
+            self.gl.read_pixels(0, 0, width, height, glow::RGBA, glow::UNSIGNED_BYTE, 
+                glow::PixelPackData::Slice(Some(pixels.as_mut_slice())));
+        }
+
+        //gl rows start at the bottom
+        let flipped = pixels.chunks_exact(row).rev().flatten().copied().collect();
+        Ok(flipped)
+    }
+
+    //gl orders buffer writes against draws itself, uploads are done as soon as they are made
+    fn upload(&self, buffer: &dyn Buffer, data: &[u8], offset: i32) -> u64 
+    {
+        buffer.sub_data(data, offset);
+        self.uploads.set(self.uploads.get() + 1);
+        self.uploads.get()
+    }
+
+    fn flush_uploads(&self) {}
+
+    fn uploads_ready(&self) -> u64 
+    {
+        self.uploads.get()
+    }
+
+    fn memory_stats(&self) -> Result<MemoryStats, ()> 
+    {
+        Err(())
     }
 }
 
@@ -168,7 +238,8 @@ impl Texture for GLTexture
 #[repr(u8)]
 enum GLBufferType {
     Uniform,
-    Vertex
+    Vertex,
+    Storage
 }
 
 pub struct GLBuffer{
@@ -201,6 +272,14 @@ impl GLBuffer
 
                 buffer
             }
+
+            BufferCreateInfo::Storage(size) | BufferCreateInfo::DeviceStorage(size) => 
+            {
+                let buffer = GLBuffer{gl, buf, ty: GLBufferType::Storage};
+                buffer.allocate(size as i32);
+
+                buffer
+            }
         }
     }
 
@@ -210,7 +289,7 @@ impl GLBuffer
         {
             match self.ty 
             {
-                GLBufferType::Uniform =>
+                GLBufferType::Uniform | GLBufferType::Storage =>
                 {
                     self.gl.named_buffer_data_u8_slice(self.buf, data, glow::DYNAMIC_DRAW);
                 }
@@ -229,7 +308,7 @@ impl GLBuffer
         {
             match self.ty 
             {
-                GLBufferType::Uniform =>
+                GLBufferType::Uniform | GLBufferType::Storage =>
                 {
                     self.gl.named_buffer_data_size(self.buf, size, glow::DYNAMIC_DRAW);
                 }
@@ -277,7 +356,8 @@ struct GLPipeline{
     vao: glow::NativeVertexArray,
     program: glow::NativeProgram,
     descriptors: Vec<DescriptorInfo>,
-    vertex_descriptor: DescriptorInfo
+    vertex_descriptor: DescriptorInfo,
+    blend_mode: BlendMode
 }
 
 impl GLPipeline 
@@ -293,7 +373,8 @@ impl GLPipeline
                 vao,
                 program,
                 descriptors: Vec::new(),
-                vertex_descriptor: DescriptorInfo::default()
+                vertex_descriptor: DescriptorInfo::default(),
+                blend_mode: BlendMode::default()
             }
         }
     }
@@ -429,14 +510,31 @@ impl Pipeline for GLPipeline
 struct GLCommandBuffer<'a> {
     gl: Arc<glow::Context>,
     pipeline: Option<&'a GLPipeline>,
-    pwindow: *mut glfw::ffi::GLFWwindow
+    pwindow: *mut glfw::ffi::GLFWwindow,
+    //draw commands for draw_indirect, refilled every call
+    indirect: glow::NativeBuffer,
+    multi_draw: Option<MultiDrawArraysIndirect>,
+}
+
+impl Drop for GLCommandBuffer<'_>
+{
+    fn drop(&mut self) 
+    {
+        unsafe 
+        {
+            self.gl.delete_buffer(self.indirect);
+        }
+    }
 }
 
 impl<'a> GLCommandBuffer<'_> 
 {
     fn new(api: &GLinner) -> GLCommandBuffer<'a>
     {
-        GLCommandBuffer{gl: api.gl.clone(), pipeline: None, pwindow: api.pwindow}
+        let indirect = unsafe {
+            api.gl.create_named_buffer().expect("failed to create indirect buffer")
+        };
+        GLCommandBuffer{gl: api.gl.clone(), pipeline: None, pwindow: api.pwindow, indirect, multi_draw: api.multi_draw}
     }
 
     fn bind_buffer(&self, buf: &dyn Buffer, source_binding: usize)
@@ -458,6 +556,24 @@ impl<'a> GLCommandBuffer<'_>
     }
 
 
+    fn bind_storage(&self, buf: &dyn Buffer, source_binding: usize)
+    {
+        let buffer = buf.as_any().downcast_ref::<GLBuffer>()
+            .expect("attempted to bind non GL buffer to GL command buffer");
+
+        let pipeline = self.pipeline.expect("bind pipeline before binding buffer");
+
+        let DescriptorInfo::Storage{bind_point} = pipeline.descriptors[source_binding] else {
+            panic!("attempted to bind {:?} to Storage Buffer Descriptor", pipeline.descriptors[source_binding]);
+        };
+
+        unsafe 
+        {
+            self.gl.bind_buffer_base(glow::SHADER_STORAGE_BUFFER, bind_point as _, Some(buffer.buf));
+        }
+    }
+
+
     fn bind_texture(&self, tex: &dyn Texture, source_binding: usize) 
     {
         let texture = tex.as_any().downcast_ref::<GLTexture>()
@@ -488,6 +604,22 @@ impl<'a> CommandBuffer<'a> for GLCommandBuffer<'a>
         {
             self.gl.bind_vertex_array(Some(pipeline.vao));
             self.gl.use_program(Some(pipeline.program));
+
+            //gl keeps blend state globally so it has to follow the bound pipeline
+            match pipeline.blend_mode 
+            {
+                BlendMode::Opaque => 
+                {
+                    self.gl.disable(glow::BLEND);
+                    self.gl.depth_mask(true);
+                }
+                BlendMode::Alpha => 
+                {
+                    self.gl.enable(glow::BLEND);
+                    self.gl.blend_func(glow::SRC_ALPHA, glow::ONE_MINUS_SRC_ALPHA);
+                    self.gl.depth_mask(false);
+                }
+            }
         }
 
         self.pipeline = Some(pipeline);
@@ -514,17 +646,22 @@ impl<'a> CommandBuffer<'a> for GLCommandBuffer<'a>
     fn bind_descriptors(&mut self, descriptors: &[DescriptorWriteInfo]) {
 
         for (i, descriptor) in descriptors.iter().enumerate() {
-            match descriptor {
+            match *descriptor {
 
-                &renderer::DescriptorWriteInfo::Texture {handle} => 
+                renderer::DescriptorWriteInfo::Texture {handle} => 
                 {
                     self.bind_texture(handle, i);
                 }
 
-                &renderer::DescriptorWriteInfo::Uniform {handle} => 
+                renderer::DescriptorWriteInfo::Uniform {handle} => 
                 {
                     self.bind_buffer(handle, i);
                 }
+
+                renderer::DescriptorWriteInfo::Storage {handle} => 
+                {
+                    self.bind_storage(handle, i);
+                }
             }
         }
     }
@@ -533,7 +670,34 @@ impl<'a> CommandBuffer<'a> for GLCommandBuffer<'a>
     {
         unsafe 
         {
-            self.gl.draw_arrays(glow::TRIANGLES, start, end);
+            self.gl.draw_arrays(glow::TRIANGLES, start, end - start);
+        }
+    }
+
+
+    //one multi draw over the whole buffer, or one draw per command without it
+    fn draw_indirect(&mut self, draws: &[DrawIndirect]) 
+    {
+        if draws.is_empty() 
+        {
+            return;
+        }
+
+        unsafe 
+        {
+            self.gl.named_buffer_data_u8_slice(self.indirect, DrawIndirect::as_bytes(draws), glow::STREAM_DRAW);
+            self.gl.bind_buffer(glow::DRAW_INDIRECT_BUFFER, Some(self.indirect));
+            if let Some(multi_draw) = self.multi_draw 
+            {
+                //the pointer is an offset into the bound indirect buffer
+                multi_draw(glow::TRIANGLES, std::ptr::null(), draws.len() as i32, size_of::<DrawIndirect>() as i32);
+                return;
+            }
+
+            for i in 0..draws.len() 
+            {
+                self.gl.draw_arrays_indirect_offset(glow::TRIANGLES, (i * size_of::<DrawIndirect>()) as i32);
+            }
         }
     }
 
@@ -551,6 +715,7 @@ impl<'a> CommandBuffer<'a> for GLCommandBuffer<'a>
         unsafe 
         {
             self.gl.clear_color(0.6, 0.8, 0.99, 1.0);
+            self.gl.depth_mask(true);
             self.gl.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
         }
     }
@@ -572,7 +737,7 @@ impl<'a> CommandBuffer<'a> for GLCommandBuffer<'a>
     }
 }
 
-fn debug_message_callback(source: u32, ty: u32, id: u32, severity: u32, msg: &str)
+fn debug_message_callback(_source: u32, _ty: u32, _id: u32, _severity: u32, msg: &str)
 {
     eprintln!("{}", msg);
 }
@@ -0,0 +1,312 @@
+use crate::*;
+
+// Voxel storage for a single chunk. Chunks made of one voxel type (all air, all stone)
+// keep no array at all, everything else keeps a palette of the voxel types it contains
+// and bit packs palette indices into u64 words. Index widths are kept at 1, 2, 4, 8 or 16
+// bits so an entry never straddles two words.
+#[derive(Clone, Debug)]
+pub enum VoxelStorage {
+    Single(VOXELS),
+    Paletted {
+        palette: Vec<VOXELS>,
+        bits: u32,
+        words: Vec<u64>,
+    }
+}
+
+impl VoxelStorage
+{
+    pub fn new(voxel: VOXELS) -> VoxelStorage
+    {
+        VoxelStorage::Single(voxel)
+    }
+
+
+    pub fn from_slice(voxels: &[VOXELS]) -> VoxelStorage
+    {
+        let mut storage = VoxelStorage::new(voxels[0]);
+        for (idx, &voxel) in voxels.iter().enumerate()
+        {
+            storage.set(idx, voxel);
+        }
+        storage
+    }
+
+
+    #[inline(always)]
+    pub fn get(&self, idx: usize) -> VOXELS
+    {
+        match self
+        {
+            VoxelStorage::Single(voxel) => *voxel,
+            VoxelStorage::Paletted{palette, bits, words} =>
+            {
+                let per_word = 64 / *bits as usize;
+                let shift = (idx % per_word) as u32 * bits;
+                let entry = (words[idx / per_word] >> shift) & ((1 << bits) - 1);
+                palette[entry as usize]
+            }
+        }
+    }
+
+
+    pub fn set(&mut self, idx: usize, voxel: VOXELS)
+    {
+        let entry = match self
+        {
+            VoxelStorage::Single(current) if *current == voxel => return,
+            VoxelStorage::Single(current) =>
+            {
+                *self = VoxelStorage::Paletted{
+                    palette: vec![*current],
+                    bits: 1,
+                    words: vec![0; CHUNK_VOL as usize / 64]
+                };
+                self.palette_entry(voxel)
+            }
+            VoxelStorage::Paletted{..} => self.palette_entry(voxel)
+        };
+
+        let VoxelStorage::Paletted{bits, words, ..} = self else {
+            unreachable!()
+        };
+
+        let per_word = 64 / *bits as usize;
+        let shift = (idx % per_word) as u32 * *bits;
+        let mask = ((1u64 << *bits) - 1) << shift;
+        let word = &mut words[idx / per_word];
+        *word = (*word & !mask) | ((entry as u64) << shift);
+    }
+
+
+    //returns the palette index of voxel, adding it and widening the indices if needed
+    fn palette_entry(&mut self, voxel: VOXELS) -> usize
+    {
+        let VoxelStorage::Paletted{palette, bits, ..} = self else {
+            panic!("palette_entry called on single voxel storage");
+        };
+
+        if let Some(entry) = palette.iter().position(|&v| v == voxel)
+        {
+            return entry;
+        }
+
+        palette.push(voxel);
+        let entry = palette.len() - 1;
+        if entry >= 1 << *bits
+        {
+            let new_bits = *bits * 2;
+            self.repack(new_bits);
+        }
+        entry
+    }
+
+
+    fn repack(&mut self, new_bits: u32)
+    {
+        let VoxelStorage::Paletted{bits, words, ..} = self else {
+            return;
+        };
+
+        let old_bits = *bits;
+        let old_per_word = 64 / old_bits as usize;
+        let new_per_word = 64 / new_bits as usize;
+        let mut new_words = vec![0u64; CHUNK_VOL as usize / new_per_word];
+
+        for idx in 0..CHUNK_VOL as usize
+        {
+            let entry = (words[idx / old_per_word] >> ((idx % old_per_word) as u32 * old_bits))
+                & ((1 << old_bits) - 1);
+            new_words[idx / new_per_word] |= entry << ((idx % new_per_word) as u32 * new_bits);
+        }
+
+        *bits = new_bits;
+        *words = new_words;
+    }
+
+
+    //drops palette entries that are no longer referenced and narrows the indices,
+    //collapsing back to a single voxel when possible
+    pub fn compact(&mut self)
+    {
+        if let VoxelStorage::Single(_) = self
+        {
+            return;
+        }
+
+        let mut used: Vec<VOXELS> = Vec::new();
+        for voxel in self.iter()
+        {
+            if !used.contains(&voxel)
+            {
+                used.push(voxel);
+            }
+        }
+
+        if used.len() == 1
+        {
+            *self = VoxelStorage::Single(used[0]);
+            return;
+        }
+
+        let VoxelStorage::Paletted{palette, bits, ..} = self else {
+            unreachable!()
+        };
+
+        let mut needed_bits = 1;
+        while used.len() > 1 << needed_bits
+        {
+            needed_bits *= 2;
+        }
+
+        if used.len() == palette.len() && needed_bits == *bits
+        {
+            return;
+        }
+
+        let voxels: Vec<VOXELS> = self.iter().collect();
+        *self = VoxelStorage::from_slice(&voxels);
+    }
+
+
+    pub fn uniform(&self) -> Option<VOXELS>
+    {
+        match self
+        {
+            VoxelStorage::Single(voxel) => Some(*voxel),
+            VoxelStorage::Paletted{..} => None
+        }
+    }
+
+
+    pub fn iter(&self) -> impl Iterator<Item = VOXELS> + '_
+    {
+        (0..CHUNK_VOL as usize).map(|idx| self.get(idx))
+    }
+
+
+    pub fn heap_size(&self) -> usize
+    {
+        match self
+        {
+            VoxelStorage::Single(_) => 0,
+            VoxelStorage::Paletted{palette, words, ..} =>
+            {
+                palette.capacity() * size_of::<VOXELS>() + words.capacity() * size_of::<u64>()
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn bits(storage: &VoxelStorage) -> u32
+    {
+        match storage
+        {
+            VoxelStorage::Single(_) => 0,
+            VoxelStorage::Paletted{bits, ..} => *bits
+        }
+    }
+
+
+    //the ids don't have to be registered blocks, storage never looks them up
+    fn voxel(id: usize) -> VOXELS
+    {
+        VOXELS(id as u16)
+    }
+
+
+    #[test]
+    fn widths_double_as_the_palette_grows()
+    {
+        let mut storage = VoxelStorage::new(voxel(0));
+        let mut expected = vec![voxel(0); CHUNK_VOL as usize];
+        assert_eq!(bits(&storage), 0);
+
+        //every new voxel at a spread out index so each repack has to move entries of every word
+        for id in 1..=300
+        {
+            let idx = id * 97 % CHUNK_VOL as usize;
+            storage.set(idx, voxel(id));
+            expected[idx] = voxel(id);
+
+            let width = [1, 2, 4, 8, 16].into_iter().find(|&width| id < 1 << width).unwrap();
+            assert_eq!(bits(&storage), width, "{} voxels", id + 1);
+            if [1, 2, 3, 4, 15, 16, 255, 256, 300].contains(&id)
+            {
+                assert!(storage.iter().eq(expected.iter().copied()), "{} voxels", id + 1);
+            }
+        }
+    }
+
+
+    #[test]
+    fn setting_the_same_voxel_keeps_a_single()
+    {
+        let mut storage = VoxelStorage::new(voxel(4));
+        storage.set(123, voxel(4));
+        assert_eq!(storage.uniform(), Some(voxel(4)));
+        assert_eq!(storage.heap_size(), 0);
+    }
+
+
+    #[test]
+    fn compact_narrows_and_drops_unused()
+    {
+        let mut storage = VoxelStorage::new(voxel(0));
+        for id in 1..20
+        {
+            storage.set(id * 1000, voxel(id));
+        }
+        assert_eq!(bits(&storage), 8);
+
+        //two voxels left fit in one bit again
+        for id in 2..20
+        {
+            storage.set(id * 1000, voxel(0));
+        }
+        let before: Vec<_> = storage.iter().collect();
+        storage.compact();
+        assert_eq!(bits(&storage), 1);
+        assert!(storage.iter().eq(before.into_iter()));
+        let VoxelStorage::Paletted{palette, ..} = &storage else {
+            unreachable!()
+        };
+        assert_eq!(palette.len(), 2);
+
+        //nothing left to tell apart collapses to a single voxel
+        storage.set(1000, voxel(0));
+        storage.compact();
+        assert_eq!(storage.uniform(), Some(voxel(0)));
+    }
+
+
+    #[test]
+    fn compact_keeps_a_full_palette()
+    {
+        let mut storage = VoxelStorage::new(voxel(0));
+        for id in 1..4
+        {
+            storage.set(id, voxel(id));
+        }
+        let before = storage.clone();
+        storage.compact();
+        assert_eq!(bits(&storage), 2);
+        assert!(storage.iter().eq(before.iter()));
+    }
+
+
+    #[test]
+    fn from_slice_round_trip()
+    {
+        let voxels: Vec<_> = (0..CHUNK_VOL as usize).map(|i| voxel(i % 7 + (i / 4096) % 3)).collect();
+        let storage = VoxelStorage::from_slice(&voxels);
+        assert_eq!(bits(&storage), 4);
+        assert!(storage.iter().eq(voxels.into_iter()));
+    }
+}
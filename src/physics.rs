@@ -0,0 +1,444 @@
+use crate::*;
+
+// Fixed timestep rigid body movement against the voxel grid. Bodies are axis aligned
+// boxes, collisions are resolved one axis at a time so sliding along walls falls out
+// naturally. The world is only seen through SolidQuery so the simulation can run
+// against any voxel source.
+pub trait SolidQuery {
+    //collision box of the voxel at x, y, z in world space, None when bodies pass through it
+    fn collision(&self, x:i32, y:i32, z:i32) -> Option<Aabb>;
+}
+
+impl SolidQuery for world::World
+{
+    //unloaded chunks count as solid so nothing falls out of the world while it streams in,
+    //except for air that is never loaded at all
+    fn collision(&self, x:i32, y:i32, z:i32) -> Option<Aabb>
+    {
+        let pos = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+        if !self.chunks.contains_key(&pos)
+        {
+            return (!self.is_air(pos)).then(|| Aabb::cell(x, y, z));
+        }
+
+        let origin = [x as f32, y as f32, z as f32];
+        match block::get(self.get_voxel(x, y, z)).collision
+        {
+            block::Collision::None => None,
+            block::Collision::Full => Some(Aabb::cell(x, y, z)),
+            block::Collision::Box{min, max} => Some(Aabb{
+                min: [0, 1, 2].map(|i| origin[i] + min[i]),
+                max: [0, 1, 2].map(|i| origin[i] + max[i])
+            })
+        }
+    }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3]
+}
+
+impl Aabb
+{
+    fn cell(x:i32, y:i32, z:i32) -> Aabb
+    {
+        let min = [x as f32, y as f32, z as f32];
+        Aabb{min, max: min.map(|v| v + 1.0)}
+    }
+
+
+    //touching faces don't count, only overlap deeper than SKIN on every axis but skip
+    fn overlaps(&self, other: &Aabb, skip: Option<usize>) -> bool
+    {
+        (0..3).filter(|&i| Some(i) != skip)
+            .all(|i| self.min[i] < other.max[i] - SKIN && self.max[i] > other.min[i] + SKIN)
+    }
+
+
+    //voxel cells the box overlaps on every axis, max exclusive
+    fn cells(&self) -> ([i32; 3], [i32; 3])
+    {
+        (
+            self.min.map(|v| (v + SKIN).floor() as i32),
+            self.max.map(|v| (v - SKIN).ceil() as i32)
+        )
+    }
+
+
+    fn offset(&self, axis: usize, delta: f32) -> Aabb
+    {
+        let mut res = *self;
+        res.min[axis] += delta;
+        res.max[axis] += delta;
+        res
+    }
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct Body {
+    //center of the bottom face
+    pub pos: [f32; 3],
+    pub vel: [f32; 3],
+    pub half_width: f32,
+    pub height: f32,
+    pub on_ground: bool
+}
+
+impl Body
+{
+    pub fn new(pos: [f32; 3], half_width: f32, height: f32) -> Body
+    {
+        Body{pos, vel: [0.0; 3], half_width, height, on_ground: false}
+    }
+
+
+    pub fn aabb(&self) -> Aabb
+    {
+        Aabb{
+            min: [self.pos[0] - self.half_width, self.pos[1], self.pos[2] - self.half_width],
+            max: [self.pos[0] + self.half_width, self.pos[1] + self.height, self.pos[2] + self.half_width]
+        }
+    }
+
+
+    fn intersects(&self, query: &impl SolidQuery) -> bool
+    {
+        let (min, max) = self.aabb().cells();
+        for x in min[0]..max[0]
+        {
+            for y in min[1]..max[1]
+            {
+                for z in min[2]..max[2]
+                {
+                    if query.collision(x, y, z).is_some_and(|b| b.overlaps(&self.aabb(), None))
+                    {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+
+    //moves along one axis until the first collision box in the way, returns the distance covered
+    fn sweep(&mut self, axis: usize, delta: f32, query: &impl SolidQuery) -> f32
+    {
+        if delta == 0.0
+        {
+            return 0.0;
+        }
+
+        let aabb = self.aabb();
+        let swept = if delta > 0.0
+        {
+            Aabb{min: aabb.min, max: aabb.offset(axis, delta).max}
+        }
+        else
+        {
+            Aabb{min: aabb.offset(axis, delta).min, max: aabb.max}
+        };
+
+        let mut allowed = delta;
+        let (min, max) = swept.cells();
+        for x in min[0]..max[0]
+        {
+            for y in min[1]..max[1]
+            {
+                for z in min[2]..max[2]
+                {
+                    let Some(b) = query.collision(x, y, z) else {
+                        continue;
+                    };
+                    if !b.overlaps(&aabb, Some(axis))
+                    {
+                        continue;
+                    }
+
+                    if delta > 0.0 && b.min[axis] >= aabb.max[axis] - SKIN
+                    {
+                        allowed = allowed.min(b.min[axis] - aabb.max[axis]);
+                    }
+                    else if delta < 0.0 && b.max[axis] <= aabb.min[axis] + SKIN
+                    {
+                        allowed = allowed.max(b.max[axis] - aabb.min[axis]);
+                    }
+                }
+            }
+        }
+
+        //never move backwards out of a contact
+        let allowed = if delta > 0.0 {allowed.max(0.0)} else {allowed.min(0.0)};
+        self.pos[axis] += allowed;
+        allowed
+    }
+
+
+    //horizontal move that climbs onto a single block when walking into its side
+    fn sweep_horizontal(&mut self, axis: usize, delta: f32, query: &impl SolidQuery)
+    {
+        let start = *self;
+        let moved = self.sweep(axis, delta, query);
+        if !self.on_ground || (moved - delta).abs() < SKIN
+        {
+            return;
+        }
+
+        let blocked = *self;
+        *self = start;
+        let lifted = self.sweep(1, STEP_HEIGHT, query);
+        let stepped = self.sweep(axis, delta, query);
+        self.sweep(1, -lifted, query);
+
+        if stepped.abs() <= moved.abs() + SKIN
+        {
+            *self = blocked;
+        }
+    }
+}
+
+
+pub struct Physics {
+    accumulator: f32
+}
+
+impl Default for Physics 
+{
+    fn default() -> Self 
+    {
+        Physics::new()
+    }
+}
+
+impl Physics
+{
+    pub fn new() -> Physics
+    {
+        Physics{accumulator: 0.0}
+    }
+
+
+    //advances in PHYSICS_DT steps, leftover time carries into the next call
+    pub fn update(&mut self, body: &mut Body, query: &impl SolidQuery, dt: f32)
+    {
+        //don't try to catch up after a long hitch
+        self.accumulator = (self.accumulator + dt).min(PHYSICS_DT * 10.0);
+        while self.accumulator >= PHYSICS_DT
+        {
+            Physics::step(body, query, PHYSICS_DT);
+            self.accumulator -= PHYSICS_DT;
+        }
+    }
+
+
+    pub fn step(body: &mut Body, query: &impl SolidQuery, dt: f32)
+    {
+        if body.intersects(query)
+        {
+            //embedded (spawned in terrain or a chunk appeared around us), don't pull further in
+            body.vel = [0.0; 3];
+            return;
+        }
+
+        body.vel[1] = (body.vel[1] - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+
+        let dy = body.vel[1] * dt;
+        let moved = body.sweep(1, dy, query);
+        if moved != dy
+        {
+            body.on_ground = dy < 0.0;
+            body.vel[1] = 0.0;
+        }
+        else
+        {
+            body.on_ground = false;
+        }
+
+        body.sweep_horizontal(0, body.vel[0] * dt, query);
+        body.sweep_horizontal(2, body.vel[2] * dt, query);
+    }
+}
+
+
+const SKIN: f32 = 1e-4;
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::collections::HashSet;
+
+    //solid unit cells, everything else is air
+    struct Cells(HashSet<(i32, i32, i32)>);
+
+    impl SolidQuery for Cells
+    {
+        fn collision(&self, x:i32, y:i32, z:i32) -> Option<Aabb>
+        {
+            self.0.contains(&(x, y, z)).then(|| Aabb::cell(x, y, z))
+        }
+    }
+
+    //ground with its top face at y 1, raised by the given height from x 2 on
+    fn ground(wall: i32) -> Cells
+    {
+        let mut cells = HashSet::new();
+        for x in -8..8
+        {
+            for z in -8..8
+            {
+                cells.insert((x, 0, z));
+                for y in 1..=wall
+                {
+                    cells.insert((x.max(2), y, z));
+                }
+            }
+        }
+        Cells(cells)
+    }
+
+
+    fn run(body: &mut Body, query: &impl SolidQuery, seconds: f32)
+    {
+        for _ in 0..(seconds / PHYSICS_DT) as usize
+        {
+            Physics::step(body, query, PHYSICS_DT);
+        }
+    }
+
+
+    fn standing(x: f32, z: f32, query: &impl SolidQuery) -> Body
+    {
+        let mut body = Body::new([x, 1.0, z], PLAYER_HALF_WIDTH, PLAYER_HEIGHT);
+        run(&mut body, query, 0.1);
+        assert!(body.on_ground);
+        body
+    }
+
+
+    fn assert_near(value: f32, expected: f32)
+    {
+        assert!((value - expected).abs() < 1e-3, "{} isn't {}", value, expected);
+    }
+
+
+    #[test]
+    fn lands_under_gravity()
+    {
+        let cells = ground(0);
+        let mut body = Body::new([0.5, 6.0, 0.5], PLAYER_HALF_WIDTH, PLAYER_HEIGHT);
+        run(&mut body, &cells, 0.2);
+        assert!(!body.on_ground);
+        assert!(body.vel[1] < 0.0);
+
+        run(&mut body, &cells, 2.0);
+        assert!(body.on_ground);
+        assert_near(body.pos[1], 1.0);
+        assert_eq!(body.vel[1], 0.0);
+    }
+
+
+    #[test]
+    fn falls_no_faster_than_terminal_velocity()
+    {
+        let mut body = Body::new([0.5, 1000.0, 0.5], PLAYER_HALF_WIDTH, PLAYER_HEIGHT);
+        run(&mut body, &Cells(HashSet::new()), 5.0);
+        assert_eq!(body.vel[1], -TERMINAL_VELOCITY);
+    }
+
+
+    #[test]
+    fn stops_at_a_wall()
+    {
+        let cells = ground(3);
+        let mut body = standing(0.5, 0.5, &cells);
+        body.vel[0] = WALK_SPEED;
+        run(&mut body, &cells, 1.0);
+        assert_near(body.pos[0], 2.0 - PLAYER_HALF_WIDTH);
+        assert_near(body.pos[1], 1.0);
+    }
+
+
+    #[test]
+    fn slides_along_a_wall()
+    {
+        let cells = ground(3);
+        let mut body = standing(0.5, 0.5, &cells);
+        body.vel = [WALK_SPEED, 0.0, WALK_SPEED];
+        run(&mut body, &cells, 0.5);
+        assert_near(body.pos[0], 2.0 - PLAYER_HALF_WIDTH);
+        assert!(body.pos[2] > 2.5);
+    }
+
+
+    #[test]
+    fn steps_up_one_block()
+    {
+        let cells = ground(1);
+        let mut body = standing(0.5, 0.5, &cells);
+        body.vel[0] = WALK_SPEED;
+        run(&mut body, &cells, 1.0);
+        assert!(body.pos[0] > 3.0, "stuck at {}", body.pos[0]);
+        assert_near(body.pos[1], 2.0);
+        assert!(body.on_ground);
+    }
+
+
+    #[test]
+    fn refuses_a_two_block_step()
+    {
+        let cells = ground(2);
+        let mut body = standing(0.5, 0.5, &cells);
+        body.vel[0] = WALK_SPEED;
+        run(&mut body, &cells, 1.0);
+        assert_near(body.pos[0], 2.0 - PLAYER_HALF_WIDTH);
+        assert_near(body.pos[1], 1.0);
+    }
+
+
+    #[test]
+    fn no_step_up_in_the_air()
+    {
+        let cells = ground(1);
+        //falling past the step's top face right next to it, it has to block instead of lifting the body
+        let mut body = Body::new([1.69, 1.5, 0.5], PLAYER_HALF_WIDTH, PLAYER_HEIGHT);
+        body.vel[0] = WALK_SPEED;
+        Physics::step(&mut body, &cells, PHYSICS_DT);
+        assert!(!body.on_ground);
+        assert_near(body.pos[0], 2.0 - PLAYER_HALF_WIDTH);
+        assert!(body.pos[1] < 1.5);
+    }
+
+
+    //through the world's own query: a synthetic chunk with a floor, and the unloaded one under it
+    #[test]
+    fn world_floor_and_unloaded_chunks()
+    {
+        let mut world = world::World::new().without_store();
+        let mut chunk = chunk::Chunk::new(0, 0, 0);
+        for x in 0..CHUNK_SIZE
+        {
+            for z in 0..CHUNK_SIZE
+            {
+                chunk.set_voxel(x, 4, z, VOXELS::COBBLESTONE).unwrap();
+            }
+        }
+        world.chunks.insert((0, 0, 0), Arc::new(chunk));
+
+        let mut body = Body::new([4.5, 10.0, 4.5], PLAYER_HALF_WIDTH, PLAYER_HEIGHT);
+        run(&mut body, &world, 2.0);
+        assert!(body.on_ground);
+        assert_near(body.pos[1], 5.0);
+
+        //nothing known about the chunk below, so it holds the body up until it's loaded
+        let mut body = Body::new([4.5, 1.0, 4.5], PLAYER_HALF_WIDTH, PLAYER_HEIGHT);
+        run(&mut body, &world, 0.5);
+        assert!(body.on_ground);
+        assert_near(body.pos[1], 0.0);
+    }
+}
@@ -0,0 +1,544 @@
+use crate::*;
+use std::{collections::HashMap, fs, io::{self, Read, Seek, SeekFrom, Write}, path::PathBuf};
+
+// Region file layout (all integers little endian):
+//   magic "VXRG" | version u32 | REGION_VOL * (offset u32, length u32) chunk entries
+//   | REGION_VOL * (offset u32, length u32) pending entries | blobs
+// An offset of 0 marks a chunk that has never been saved. Each chunk blob is the chunk's
+// voxel array run length encoded as (run u8, voxel u16) groups. Pending blobs hold feature
+// edits that landed in a chunk while it wasn't loaded, (x i32, y i32, z i32, voxel u16,
+// replace u8) each, they are dropped once the chunk itself is saved. Files of any other
+// version are rejected as invalid data.
+const REGION_MAGIC: [u8; 4] = *b"VXRG";
+const REGION_VERSION: u32 = 3;
+const ENTRIES: usize = 2 * REGION_VOL as usize;
+const HEADER_SIZE: usize = 8 + ENTRIES * 8;
+const EDIT_SIZE: usize = 12 + 2 + 1;
+
+//edits waiting for the chunk at pos
+type Pending<'a> = ((i32, i32, i32), &'a [feature::Edit]);
+
+#[derive(Clone, Copy, Default)]
+struct RegionEntry
+{
+    offset: u32,
+    length: u32,
+}
+
+//regions without a file have no entries
+struct RegionHeader
+{
+    entries: Vec<RegionEntry>,
+}
+
+pub struct RegionStore
+{
+    dir: PathBuf,
+    headers: Mutex<HashMap<(i32, i32, i32), Arc<RegionHeader>>>,
+}
+
+impl RegionStore
+{
+    pub fn new(dir: impl Into<PathBuf>) -> RegionStore
+    {
+        RegionStore{dir: dir.into(), headers: Mutex::new(HashMap::new())}
+    }
+
+
+    //a saved chunk or edits waiting for it
+    pub fn contains(&self, pos: (i32, i32, i32)) -> bool
+    {
+        let header = self.header(region_pos(pos));
+        let index = region_index(pos);
+        [index, REGION_VOL as usize + index].iter()
+            .any(|&i| header.entries.get(i).is_some_and(|entry| entry.offset != 0))
+    }
+
+
+    pub fn load_chunk(&self, pos: (i32, i32, i32)) -> io::Result<Option<chunk::Chunk>>
+    {
+        let Some(blob) = self.read_blob(pos, region_index(pos))? else {
+            return Ok(None);
+        };
+
+        let voxels = decompress(&blob)?;
+        Ok(Some(chunk::Chunk::new(pos.0, pos.1, pos.2).with_voxels(voxels)))
+    }
+
+
+    //edits saved for the chunk that it hasn't been saved with yet, oldest first
+    pub fn load_pending(&self, pos: (i32, i32, i32)) -> io::Result<Vec<feature::Edit>>
+    {
+        match self.read_blob(pos, REGION_VOL as usize + region_index(pos))?
+        {
+            Some(blob) => decode_edits(&blob),
+            None => Ok(Vec::new())
+        }
+    }
+
+
+    fn read_blob(&self, pos: (i32, i32, i32), index: usize) -> io::Result<Option<Vec<u8>>>
+    {
+        let region = region_pos(pos);
+        let header = self.header(region);
+        let Some(&entry) = header.entries.get(index) else {
+            return Ok(None);
+        };
+
+        if entry.offset == 0
+        {
+            return Ok(None);
+        }
+
+        let mut file = fs::File::open(self.path(region))?;
+        let mut blob = vec![0u8; entry.length as usize];
+        file.seek(SeekFrom::Start(entry.offset as u64))?;
+        file.read_exact(&mut blob)?;
+        Ok(Some(blob))
+    }
+
+
+    pub fn save_chunks<'a>(&self, chunks: impl Iterator<Item = &'a chunk::Chunk>) -> io::Result<()>
+    {
+        let mut regions: HashMap<(i32, i32, i32), Vec<&chunk::Chunk>> = HashMap::new();
+        for chunk in chunks
+        {
+            regions.entry(region_pos(chunk.pos)).or_default().push(chunk);
+        }
+
+        for (region, chunks) in regions
+        {
+            self.save_region(region, &chunks, &[])?;
+        }
+
+        Ok(())
+    }
+
+
+    //appended to whatever the chunks already have waiting
+    pub fn save_pending<'a>(
+        &self, 
+        pending: impl Iterator<Item = Pending<'a>>) -> io::Result<()>
+    {
+        let mut regions: HashMap<(i32, i32, i32), Vec<Pending>> = HashMap::new();
+        for (pos, edits) in pending
+        {
+            regions.entry(region_pos(pos)).or_default().push((pos, edits));
+        }
+
+        for (region, pending) in regions
+        {
+            self.save_region(region, &[], &pending)?;
+        }
+
+        Ok(())
+    }
+
+
+    fn save_region(
+        &self, 
+        region: (i32, i32, i32), 
+        chunks: &[&chunk::Chunk], 
+        pending: &[Pending]) -> io::Result<()>
+    {
+        fs::create_dir_all(&self.dir)?;
+        let mut blobs = self.read_blobs(region)?;
+        for chunk in chunks
+        {
+            let index = region_index(chunk.pos);
+            blobs[index] = Some(compress(chunk.voxels.iter()));
+            blobs[REGION_VOL as usize + index] = None;
+        }
+
+        for (pos, edits) in pending
+        {
+            let blob = blobs[REGION_VOL as usize + region_index(*pos)].get_or_insert_default();
+            blob.extend(encode_edits(edits));
+        }
+
+        let mut entries = vec![RegionEntry::default(); ENTRIES];
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        let mut data = Vec::new();
+
+        for (entry, blob) in entries.iter_mut().zip(blobs.iter())
+        {
+            if let Some(blob) = blob
+            {
+                entry.offset = (HEADER_SIZE + data.len()) as u32;
+                entry.length = blob.len() as u32;
+                data.extend_from_slice(blob);
+            }
+        }
+
+        header.extend_from_slice(&REGION_MAGIC);
+        header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+        for entry in entries.iter()
+        {
+            header.extend_from_slice(&entry.offset.to_le_bytes());
+            header.extend_from_slice(&entry.length.to_le_bytes());
+        }
+
+        //write next to the old file and swap so a crash can't leave a half written region
+        let path = self.path(region);
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(&header)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+
+        self.headers.lock().unwrap().insert(region, Arc::new(RegionHeader{entries}));
+        Ok(())
+    }
+
+
+    fn read_blobs(&self, region: (i32, i32, i32)) -> io::Result<Vec<Option<Vec<u8>>>>
+    {
+        let mut blobs = vec![None; ENTRIES];
+        let header = self.header(region);
+        if header.entries.is_empty()
+        {
+            return Ok(blobs);
+        }
+
+        let bytes = fs::read(self.path(region))?;
+        for (blob, entry) in blobs.iter_mut().zip(header.entries.iter())
+        {
+            if entry.offset == 0
+            {
+                continue;
+            }
+
+            let end = entry.offset.checked_add(entry.length)
+                .ok_or_else(|| invalid_data("region entry reaches past the largest offset"))?;
+            let data = bytes.get(entry.offset as usize..end as usize)
+                .ok_or_else(|| invalid_data("region entry points past the end of the file"))?;
+            *blob = Some(data.to_vec());
+        }
+
+        Ok(blobs)
+    }
+
+
+    fn header(&self, region: (i32, i32, i32)) -> Arc<RegionHeader>
+    {
+        if let Some(header) = self.headers.lock().unwrap().get(&region)
+        {
+            return header.clone();
+        }
+
+        let header = Arc::new(self.read_header(region).unwrap_or_else(|err| {
+            eprintln!("ignoring unreadable region {:?}: {}", region, err);
+            RegionHeader{entries: Vec::new()}
+        }));
+
+        self.headers.lock().unwrap().insert(region, header.clone());
+        header
+    }
+
+
+    fn read_header(&self, region: (i32, i32, i32)) -> io::Result<RegionHeader>
+    {
+        let mut file = match fs::File::open(self.path(region))
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 
+            {
+                return Ok(RegionHeader{entries: Vec::new()});
+            }
+            Err(err) => return Err(err)
+        };
+
+        let mut bytes = vec![0u8; 8];
+        file.read_exact(&mut bytes)?;
+
+        if bytes[0..4] != REGION_MAGIC
+        {
+            return Err(invalid_data("bad region magic"));
+        }
+
+        if read_u32(&bytes, 4) != REGION_VERSION
+        {
+            return Err(invalid_data("unsupported region version"));
+        }
+
+        let mut bytes = vec![0u8; ENTRIES * 8];
+        file.read_exact(&mut bytes)?;
+
+        let entries = (0..ENTRIES).map(|i| RegionEntry{
+            offset: read_u32(&bytes, i * 8),
+            length: read_u32(&bytes, 4 + i * 8),
+        }).collect();
+        Ok(RegionHeader{entries})
+    }
+
+
+    fn path(&self, (x, y, z): (i32, i32, i32)) -> PathBuf
+    {
+        self.dir.join(format!("r.{}.{}.{}.bin", x, y, z))
+    }
+}
+
+
+pub fn region_pos((x, y, z): (i32, i32, i32)) -> (i32, i32, i32)
+{
+    (x.div_euclid(REGION_SIZE), y.div_euclid(REGION_SIZE), z.div_euclid(REGION_SIZE))
+}
+
+
+pub fn region_index((x, y, z): (i32, i32, i32)) -> usize
+{
+    (x.rem_euclid(REGION_SIZE)
+        + z.rem_euclid(REGION_SIZE) * REGION_SIZE
+        + y.rem_euclid(REGION_SIZE) * REGION_AREA) as usize
+}
+
+
+pub fn compress(voxels: impl Iterator<Item = VOXELS>) -> Vec<u8>
+{
+    let mut res = Vec::new();
+    let mut iter = voxels.peekable();
+
+    while let Some(voxel) = iter.next()
+    {
+        let mut run: u8 = 1;
+        while run < u8::MAX && iter.peek() == Some(&voxel)
+        {
+            iter.next();
+            run += 1;
+        }
+        res.push(run);
+        res.extend_from_slice(&voxel.0.to_le_bytes());
+    }
+
+    res
+}
+
+
+pub fn decompress(data: &[u8]) -> io::Result<Vec<VOXELS>>
+{
+    if !data.len().is_multiple_of(3)
+    {
+        return Err(invalid_data("truncated chunk data"));
+    }
+
+    let mut voxels = Vec::with_capacity(CHUNK_VOL as usize);
+    for run in data.chunks_exact(3)
+    {
+        let voxel = VOXELS::try_from(u16::from_le_bytes([run[1], run[2]]))
+            .map_err(|_| invalid_data("unknown voxel id in chunk data"))?;
+        voxels.extend(std::iter::repeat_n(voxel, run[0] as usize));
+    }
+
+    if voxels.len() != CHUNK_VOL as usize
+    {
+        return Err(invalid_data("chunk data has the wrong number of voxels"));
+    }
+
+    Ok(voxels)
+}
+
+
+pub fn encode_edits(edits: &[feature::Edit]) -> Vec<u8>
+{
+    let mut res = Vec::with_capacity(edits.len() * EDIT_SIZE);
+    for edit in edits
+    {
+        let (x, y, z) = edit.pos;
+        res.extend_from_slice(&x.to_le_bytes());
+        res.extend_from_slice(&y.to_le_bytes());
+        res.extend_from_slice(&z.to_le_bytes());
+        res.extend_from_slice(&edit.voxel.0.to_le_bytes());
+        res.push(match edit.replace {
+            feature::Replace::Any => 0,
+            feature::Replace::Air => 1,
+            feature::Replace::Stone => 2
+        });
+    }
+    res
+}
+
+
+pub fn decode_edits(data: &[u8]) -> io::Result<Vec<feature::Edit>>
+{
+    if !data.len().is_multiple_of(EDIT_SIZE)
+    {
+        return Err(invalid_data("truncated pending edits"));
+    }
+
+    data.chunks_exact(EDIT_SIZE)
+        .map(|edit| {
+            let pos = (read_i32(edit, 0), read_i32(edit, 4), read_i32(edit, 8));
+            let voxel = VOXELS::try_from(u16::from_le_bytes([edit[12], edit[13]]))
+                .map_err(|_| invalid_data("unknown voxel id in pending edits"))?;
+            let replace = match edit[14] {
+                0 => feature::Replace::Any,
+                1 => feature::Replace::Air,
+                2 => feature::Replace::Stone,
+                _ => return Err(invalid_data("unknown replace rule in pending edits"))
+            };
+            Ok(feature::Edit{pos, voxel, replace})
+        })
+        .collect()
+}
+
+
+fn read_i32(bytes: &[u8], at: usize) -> i32
+{
+    i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+
+fn read_u32(bytes: &[u8], at: usize) -> u32
+{
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+
+fn invalid_data(msg: &str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn temp_store(name: &str) -> (RegionStore, PathBuf)
+    {
+        let dir = std::env::temp_dir().join(format!("region_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        (RegionStore::new(&dir), dir)
+    }
+
+
+    //a bit of everything: long runs, runs longer than a u8 and single voxels
+    fn test_chunk(pos: (i32, i32, i32)) -> chunk::Chunk
+    {
+        let voxels = (0..CHUNK_VOL)
+            .map(|i| match i {
+                i if i < 1000 => VOXELS::COBBLESTONE,
+                i if i % 7 == 0 => VOXELS::WATER,
+                i if i % 3 == 0 => VOXELS::DIRT,
+                _ => VOXELS::EMPTY
+            })
+            .collect();
+        chunk::Chunk::new(pos.0, pos.1, pos.2).with_voxels(voxels)
+    }
+
+
+    #[test]
+    fn compress_round_trip()
+    {
+        let chunk = test_chunk((0, 0, 0));
+        let voxels = decompress(&compress(chunk.voxels.iter())).unwrap();
+        assert!(voxels.into_iter().eq(chunk.voxels.iter()));
+    }
+
+
+    #[test]
+    fn region_round_trip()
+    {
+        let (store, dir) = temp_store("round_trip");
+        //two regions, one of them with a chunk at a negative position
+        let chunks = [test_chunk((0, 0, 0)), test_chunk((3, 1, 7)), test_chunk((-1, 2, -9))];
+        store.save_chunks(chunks.iter()).unwrap();
+        let edits = [feature::Edit{pos: (5, 40, -3), voxel: VOXELS::LEAF, replace: feature::Replace::Air}];
+        store.save_pending([((0, 1, -1), &edits[..])].into_iter()).unwrap();
+
+        //a fresh store has nothing cached and has to read everything back from disk
+        let loaded = RegionStore::new(&dir);
+        for chunk in chunks.iter()
+        {
+            assert!(loaded.contains(chunk.pos));
+            let back = loaded.load_chunk(chunk.pos).unwrap().unwrap();
+            assert!(back.voxels.iter().eq(chunk.voxels.iter()));
+        }
+        assert!(loaded.load_chunk((1, 0, 0)).unwrap().is_none());
+        let pending = loaded.load_pending((0, 1, -1)).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].pos, pending[0].voxel, pending[0].replace), (edits[0].pos, edits[0].voxel, edits[0].replace));
+
+        //saving what was loaded writes the same bytes again
+        let files = [(0, 0, 0), (-1, 0, -2)].map(|region| fs::read(store.path(region)).unwrap());
+        let (again, again_dir) = temp_store("round_trip_again");
+        let reloaded: Vec<_> = chunks.iter().map(|chunk| loaded.load_chunk(chunk.pos).unwrap().unwrap()).collect();
+        again.save_chunks(reloaded.iter()).unwrap();
+        again.save_pending([((0, 1, -1), &pending[..])].into_iter()).unwrap();
+        for (region, file) in [(0, 0, 0), (-1, 0, -2)].into_iter().zip(files)
+        {
+            assert_eq!(fs::read(again.path(region)).unwrap(), file);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(again_dir).unwrap();
+    }
+
+
+    #[test]
+    fn saving_a_chunk_drops_its_pending_edits()
+    {
+        let (store, dir) = temp_store("pending");
+        let edits = [feature::Edit{pos: (1, 2, 3), voxel: VOXELS::WOOD, replace: feature::Replace::Any}];
+        store.save_pending([((0, 0, 0), &edits[..])].into_iter()).unwrap();
+        store.save_pending([((0, 0, 0), &edits[..])].into_iter()).unwrap();
+        assert_eq!(store.load_pending((0, 0, 0)).unwrap().len(), 2);
+
+        store.save_chunks([test_chunk((0, 0, 0))].iter()).unwrap();
+        assert!(store.load_pending((0, 0, 0)).unwrap().is_empty());
+        assert!(store.contains((0, 0, 0)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+
+    #[test]
+    fn overflowing_entries_are_invalid_data()
+    {
+        let (store, dir) = temp_store("overflow");
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&REGION_MAGIC);
+        file.extend_from_slice(&REGION_VERSION.to_le_bytes());
+        for i in 0..ENTRIES
+        {
+            let entry: (u32, u32) = if i == 0 {(u32::MAX - 4, 16)} else {(0, 0)};
+            file.extend_from_slice(&entry.0.to_le_bytes());
+            file.extend_from_slice(&entry.1.to_le_bytes());
+        }
+        fs::write(store.path((0, 0, 0)), file).unwrap();
+
+        let err = store.save_chunks([test_chunk((1, 0, 0))].iter()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(store.load_chunk((0, 0, 0)).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+
+    #[test]
+    fn other_versions_are_invalid_data()
+    {
+        let (store, dir) = temp_store("version");
+        fs::create_dir_all(&dir).unwrap();
+
+        for version in [0, 1, 2, REGION_VERSION + 1]
+        {
+            let mut file = Vec::new();
+            file.extend_from_slice(&REGION_MAGIC);
+            file.extend_from_slice(&u32::to_le_bytes(version));
+            file.resize(HEADER_SIZE, 0);
+            fs::write(store.path((0, 0, 0)), file).unwrap();
+
+            let err = store.read_header((0, 0, 0)).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "version {}", version);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -28,13 +28,8 @@ impl ApiCreateInfo {
             }
 
             ApiCreateInfo::GL => {
-                glfw.window_hint(glfw::WindowHint::ContextVersion(4, 5));
-                glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
-                glfw.window_hint(glfw::WindowHint::OpenGlForwardCompat(true));
-                glfw.window_hint(glfw::WindowHint::OpenGlDebugContext(false));
+                ApiCreateInfo::gl_hints(glfw);
                 glfw.window_hint(glfw::WindowHint::FocusOnShow(true));
-                #[cfg(debug_assertions)]
-                glfw.window_hint(glfw::WindowHint::OpenGlDebugContext(true));
 
                 let (mut window, events) = glfw.create_window(
                     crate::settings::WIDTH, crate::settings::HEIGHT, "Voxel Engine", glfw::WindowMode::Windowed
@@ -50,6 +45,42 @@ impl ApiCreateInfo {
     }
 }
 
+impl ApiCreateInfo {
+    //no visible window or surface, frames are read back with Api::read_frame
+    pub fn request_headless(&self, width: u32, height: u32) -> Result<ApiHandle, ()> 
+    {
+        match self {
+            ApiCreateInfo::VK => Ok(ApiHandle{inner: Box::new(vulkan::VKInner::headless(width, height)?)}),
+            //a gl context always comes with a window, this one is never shown
+            ApiCreateInfo::GL => {
+                let mut glfw = glfw::init(glfw::log_errors).map_err(|_| ())?;
+                ApiCreateInfo::gl_hints(&mut glfw);
+                glfw.window_hint(glfw::WindowHint::Visible(false));
+                //single buffered so presenting doesn't throw the frame away before it's read
+                glfw.window_hint(glfw::WindowHint::DoubleBuffer(false));
+
+                let (mut window, _) = glfw.create_window(
+                    width, height, "Voxel Engine", glfw::WindowMode::Windowed
+                ).ok_or(())?;
+
+                <glfw::Window as glfw::Context>::make_current(&mut window);
+                Ok(ApiHandle{inner: Box::new(opengl::GLinner::headless(window, glfw))})
+            }
+        }
+    }
+
+
+    fn gl_hints(glfw: &mut glfw::Glfw) 
+    {
+        glfw.window_hint(glfw::WindowHint::ContextVersion(4, 5));
+        glfw.window_hint(glfw::WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
+        glfw.window_hint(glfw::WindowHint::OpenGlForwardCompat(true));
+        glfw.window_hint(glfw::WindowHint::OpenGlDebugContext(false));
+        #[cfg(debug_assertions)]
+        glfw.window_hint(glfw::WindowHint::OpenGlDebugContext(true));
+    }
+}
+
 pub struct ApiHandle {
     pub inner: Box<dyn Api>,
 }
@@ -59,6 +90,33 @@ pub trait Api {
     fn create_command_buffer<'a>(&self) -> Result<Box<dyn CommandBuffer<'a> + 'a>, ()>;
     fn create_buffer(&self, buffer_info: BufferCreateInfo) -> Result<Box<dyn Buffer>, ()>;
     fn create_texture(&self, texture_info: TextureCreateInfo<'_>) -> Result<Box<dyn Texture>, ()>;
+
+    //framebuffer size in pixels, zero when the window is minimized
+    fn resize(&self, width: u32, height: u32);
+
+    //rgba8 rows top to bottom of the last submitted frame, only offscreen targets can be read
+    fn read_frame(&self) -> Result<Vec<u8>, ()>;
+
+    //queues a copy of data into a DeviceStorage buffer without waiting for it, returns the
+    //ticket uploads_ready reaches once shaders can read the copy
+    fn upload(&self, buffer: &dyn Buffer, data: &[u8], offset: i32) -> u64;
+    //sends everything uploaded since the last flush off to the gpu
+    fn flush_uploads(&self);
+    fn uploads_ready(&self) -> u64;
+
+    //Err when the backend leaves memory management to the driver
+    fn memory_stats(&self) -> Result<MemoryStats, ()>;
+}
+
+//device memory in bytes, reserved is what the driver handed out and used what of it is taken
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MemoryStats {
+    pub blocks: u32,
+    pub allocations: u32,
+    pub reserved: u64,
+    pub used: u64,
+    //staging space still waiting on a transfer
+    pub staging: u64,
 }
 
 pub trait Pipeline {
@@ -66,7 +124,10 @@ pub trait Pipeline {
 }
 
 pub trait CommandBuffer<'a> {
+    //vertices start..end, shaders see start as their first vertex index
     fn draw(&mut self, start:i32, end:i32);
+    //every draw in one go where the device allows it, shaders see first_instance as their instance index
+    fn draw_indirect(&mut self, draws: &[DrawIndirect]);
     fn draw_indexed(&mut self, start:i32, end:i32);
     fn bind_pipeline(&mut self, pipeline: &'a dyn Pipeline);
     fn bind_vertex_buffer(&mut self, buf: &dyn Buffer); 
@@ -80,6 +141,23 @@ pub trait CommandBuffer<'a> {
     fn submit(&mut self);
 }
 
+//same layout as VkDrawIndirectCommand and gl's DrawArraysIndirectCommand
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DrawIndirect {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub first_vertex: u32,
+    pub first_instance: u32,
+}
+
+impl DrawIndirect {
+    pub fn as_bytes(draws: &[DrawIndirect]) -> &[u8]
+    {
+        unsafe{std::slice::from_raw_parts(draws.as_ptr() as *const u8, size_of_val(draws))}
+    }
+}
+
 pub trait Buffer {
     fn sub_data(&self, data: &[u8], offset:i32);
     fn as_any(&self) -> &dyn Any;
@@ -110,6 +188,10 @@ pub enum DescriptorInfo {
     Texture{
         bind_point: u8
     },
+    //read only from the vertex shader
+    Storage{
+        bind_point: u8
+    },
     #[default]
     Default
 }
@@ -120,14 +202,18 @@ pub enum DescriptorWriteInfo<'a> {
     },
     Texture{
         handle: &'a dyn Texture
+    },
+    Storage{
+        handle: &'a dyn Buffer
     }
 }
 
 impl std::fmt::Debug for DescriptorWriteInfo<'_> {
      fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-         let name = match self {
-             &DescriptorWriteInfo::Uniform {handle: _} => {"Uniform"},
-             &DescriptorWriteInfo::Texture {handle: _} => {"Texture"}
+         let name = match *self {
+             DescriptorWriteInfo::Uniform {handle: _} => {"Uniform"},
+             DescriptorWriteInfo::Texture {handle: _} => {"Texture"},
+             DescriptorWriteInfo::Storage {handle: _} => {"Storage"}
          };
 
          f.debug_tuple("")
@@ -139,6 +225,10 @@ impl std::fmt::Debug for DescriptorWriteInfo<'_> {
 pub enum BufferCreateInfo<'a>{
     ReadOnly(&'a [u8]),
     Dynamic(usize),
+    //host writable storage buffer of this many bytes, sub_data writes straight into it
+    Storage(usize),
+    //storage buffer of this many bytes in device memory, only written through Api::upload
+    DeviceStorage(usize),
 }
 
 pub struct TextureCreateInfo<'a>{
@@ -148,11 +238,20 @@ pub struct TextureCreateInfo<'a>{
     pub pixels: &'a [u8]
 }
 
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    //standard alpha blending, depth is tested but not written
+    Alpha
+}
+
 #[derive(Default)]
 pub struct PipelineInfo<'a> {
     pub vbo_layout: VertexLayout,
     pub shader_info: ShaderInfo<'a>,
-    pub descriptor_layouts: Vec<DescriptorInfo>
+    pub descriptor_layouts: Vec<DescriptorInfo>,
+    pub blend_mode: BlendMode
 }
 
 #[derive(Default)]
@@ -207,8 +306,6 @@ impl BufferElementType {
     }
 
     pub fn is_integral(&self) -> bool {
-        if let Self::I32 | Self::U32 = self {
-            true
-        } else {false}
+        matches!(self, Self::I32 | Self::U32)
     }
 }
@@ -1,11 +1,13 @@
 use crate::*;
-use std::{collections::HashMap};
-use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 
 
+//chunk origins come from the mesh pool, only the camera is shared by every draw. Only read
+//by the shaders through as_bytes
+#[repr(C)]
+#[allow(dead_code)]
 pub struct Transform 
 {
-    model: [f32; 16],
     view: [f32; 16],
     proj: [f32; 16]
 }
@@ -20,26 +22,48 @@ impl Transform {
     }
 }
 
+//opaque and water mesh of a chunk, either can be empty
+type ChunkMeshes = (Option<mesh_pool::Mesh>, Option<mesh_pool::Mesh>);
+
 pub struct Scene<'a>
 {
     world: world::World,
-    chunk_mesh_rx: mpsc::Receiver<chunk::ChunkMesh>,
-    chunk_tx: mpsc::Sender<world::ChunkCluster>,
+    jobs: jobs::Scheduler,
+    //the latest job for each stage and chunk, results of anything else are stale
+    scheduled: HashMap<(jobs::Stage, (i32,i32,i32)), jobs::JobId>,
+    //failed jobs for each stage and chunk, nothing more is scheduled for it after
+    //MAX_JOB_FAILURES until it goes out of range
+    failures: HashMap<(jobs::Stage, (i32,i32,i32)), u32>,
 
     command_buffer: Box<dyn renderer::CommandBuffer<'a> +'a>,
 
-    meshes: HashMap<(i32,i32,i32), (Box<dyn renderer::Buffer>, i32)>,
+    meshes: HashMap<(i32,i32,i32), mesh_pool::Mesh>,
+    water_meshes: HashMap<(i32,i32,i32), mesh_pool::Mesh>,
+    //lods of the latest mesh built for each chunk and its neighbours
+    lods: HashMap<(i32,i32,i32), lod::Levels>,
+    //meshes whose upload is still in flight, they replace the drawn ones together
+    uploading: HashMap<(i32,i32,i32), ChunkMeshes>,
+    //built meshes waiting for room in the pool, uploaded nearest first
+    deferred: HashMap<(i32,i32,i32), chunk::ChunkMesh>,
+    mesh_pool: mesh_pool::MeshPool,
     uniform_buffer: Box<dyn renderer::Buffer>,
+    //bound once at creation, has to live as long as the pipelines
+    #[allow(dead_code)]
     texture: Box<dyn renderer::Texture>,
 
     chunk_pipeline: Box<dyn renderer::Pipeline>,
+    water_pipeline: Box<dyn renderer::Pipeline>,
     api: Arc<renderer::ApiHandle>,
+    pub stats: DrawStats,
 }
 
-pub struct MeshBuilder 
+
+//opaque chunk meshes in render range for the last frame
+#[derive(Default, Clone, Copy)]
+pub struct DrawStats 
 {
-    mesh_tx: mpsc::Sender<chunk::ChunkMesh>,
-    chunk_rx: mpsc::Receiver<world::ChunkCluster>
+    pub drawn: u32,
+    pub culled: u32,
 }
 
 
@@ -47,17 +71,10 @@ impl<'a> Scene<'a>
 {
     pub fn new(
         api: Arc<renderer::ApiHandle>,
-        chunk_mesh_rx: mpsc::Receiver<chunk::ChunkMesh>,
-        chunk_tx: mpsc::Sender<world::ChunkCluster>) -> Scene<'a>
+        world: world::World) -> Scene<'a>
     {
-        let mut layout = renderer::VertexLayout::new(0);
-        layout.add(
-            renderer::BufferElement{
-                element_type: renderer::BufferElementType::U32, 
-                quantity: 1, 
-                normalized: false
-            }
-        );
+        //no vertex attributes, the vertex shader pulls quads out of the mesh pool
+        let layout = renderer::VertexLayout::new(0);
 
         let shader_info = renderer::ShaderInfo::SpirV(
             &std::fs::read("./shaders/chunk_vert.spv")
@@ -82,13 +99,42 @@ impl<'a> Scene<'a>
             bind_point: 1
         };
 
-        let mut pipeline_info = renderer::PipelineInfo::default();
-        pipeline_info.vbo_layout = layout;
-        pipeline_info.shader_info = shader_info;
-        pipeline_info.descriptor_layouts = vec![uniform_descriptor, texture_descriptor];
+        let quad_descriptor = renderer::DescriptorInfo::Storage {
+            bind_point: 2
+        };
+
+        let origin_descriptor = renderer::DescriptorInfo::Storage {
+            bind_point: 3
+        };
+
+        let pipeline_info = renderer::PipelineInfo{
+            vbo_layout: layout,
+            shader_info,
+            descriptor_layouts: vec![uniform_descriptor, texture_descriptor, quad_descriptor, origin_descriptor],
+            ..Default::default()
+        };
 
         let chunk_pipeline = api.inner.create_pipeline(pipeline_info)
             .expect("failed to create chunk pipeline");
+
+        let water_layout = renderer::VertexLayout::new(0);
+
+        let water_shader_info = renderer::ShaderInfo::SpirV(
+            &std::fs::read("./shaders/chunk_vert.spv")
+                .expect("failed to read shader"), 
+            &std::fs::read("./shaders/chunk_frag.spv")
+                .expect("failed to read shader")
+        );
+
+        let water_pipeline_info = renderer::PipelineInfo{
+            vbo_layout: water_layout,
+            shader_info: water_shader_info,
+            descriptor_layouts: vec![uniform_descriptor, texture_descriptor, quad_descriptor, origin_descriptor],
+            blend_mode: renderer::BlendMode::Alpha
+        };
+
+        let water_pipeline = api.inner.create_pipeline(water_pipeline_info)
+            .expect("failed to create water pipeline");
         
         let mut command_buffer = api.inner.create_command_buffer()
             .expect("failed to create command buffer");
@@ -97,153 +143,575 @@ impl<'a> Scene<'a>
             renderer::BufferCreateInfo::Dynamic(size_of::<Transform>())
         ).expect("failed to create uniform buffer");
 
-        let tex_array_data = image::open("./assets/spritesheet.png")
-            .expect("failed to read spritesheet")
-            .into_rgba8();
+        let (tex_size, tex_layers, tex_pixels) = block::registry().build_texture_array()
+            .expect("failed to build block textures");
 
         let texture = api.inner.create_texture(
             renderer::TextureCreateInfo{
-                width: tex_array_data.width().try_into().unwrap(),
-                height: <u32 as TryInto<i32>>::try_into(tex_array_data.height()).unwrap() / NUM_TEXTURES,
-                layers: NUM_TEXTURES,
-                pixels: tex_array_data.into_raw().as_slice()}
+                width: tex_size as i32,
+                height: tex_size as i32,
+                layers: tex_layers,
+                pixels: tex_pixels.as_slice()}
         ).expect("failed to create texture resource");
 
+        let mesh_pool = mesh_pool::MeshPool::new(api.clone());
+
         let descriptors = vec![
             renderer::DescriptorWriteInfo::Uniform{handle: uniform_buffer.as_ref()},
-            renderer::DescriptorWriteInfo::Texture{handle: texture.as_ref()}
+            renderer::DescriptorWriteInfo::Texture{handle: texture.as_ref()},
+            renderer::DescriptorWriteInfo::Storage{handle: mesh_pool.buffer.as_ref()},
+            renderer::DescriptorWriteInfo::Storage{handle: mesh_pool.origins.as_ref()}
         ];
 
         command_buffer.bind_pipeline(unsafe{&*((&*chunk_pipeline) as *const _)});
         command_buffer.bind_descriptors(descriptors.as_slice());
 
-        let world = world::World::new();
         Scene
         {
             api, 
             world, 
-            chunk_mesh_rx,
-            chunk_tx,
+            jobs: jobs::Scheduler::with_available_workers(),
+            scheduled: HashMap::new(),
+            failures: HashMap::new(),
             meshes: HashMap::new(),
+            water_meshes: HashMap::new(),
+            lods: HashMap::new(),
+            uploading: HashMap::new(),
+            mesh_pool,
+            deferred: HashMap::new(),
             chunk_pipeline,
+            water_pipeline,
             command_buffer,
             uniform_buffer,
-            texture
+            texture,
+            stats: DrawStats::default(),
         }
     }
 
 
     pub fn draw(&mut self, player:&camera::Player) 
     {
+        self.command_buffer.bind_pipeline(unsafe{&*((&*self.chunk_pipeline) as *const _)});
         self.command_buffer.begin();
+        let frustum = player.get_frustum();
+        let in_frustum = |(x, y, z):(i32, i32, i32)| {
+            let min = [(x * CHUNK_SIZE) as f32, (y * CHUNK_SIZE) as f32, (z * CHUNK_SIZE) as f32];
+            frustum.intersects_aabb(min, min.map(|v| v + CHUNK_SIZE as f32))
+        };
+        self.stats = DrawStats::default();
+
+        let transform = Transform{
+            view: player.get_view_mat(),
+            proj: player.get_proj_mat()
+        };
+
+        let mut draws = Vec::new();
         for pos in util::render_range((player.chunk_x, player.chunk_y, player.chunk_z)) 
         {
-            if let Some((mesh, len)) = self.meshes.get(&pos) 
+            if let Some(mesh) = self.meshes.get(&pos) 
             {
-                if *len > 0 //&& player.is_in_frustum(util::chunk_center_from_global_index(pos)) 
+                if !in_frustum(pos) 
+                {
+                    self.stats.culled += 1;
+                }
+                else 
                 {
-                    let transform = Transform{
-                        model: math::get_model(pos),
-                        view: player.get_view_mat(),
-                        proj: player.get_proj_mat()
-                    };
-
-                    self.command_buffer.update_buffer(self.uniform_buffer.as_ref(), transform.as_bytes(), 0);
-                    self.command_buffer.bind_vertex_buffer(mesh.as_ref());
-                    self.command_buffer.draw(0, *len as i32);
+                    self.stats.drawn += 1;
+                    draws.push(mesh.draw());
                 }
             }
         }
+
+        self.command_buffer.update_buffer(self.uniform_buffer.as_ref(), transform.as_bytes(), 0);
+        self.command_buffer.draw_indirect(&draws);
+
+        //water is blended over the opaque pass so it has to go far to near
+        let mut water_positions: Vec<(i32, i32, i32)> = 
+            util::render_range((player.chunk_x, player.chunk_y, player.chunk_z))
+            .filter(|pos| self.water_meshes.contains_key(pos) && in_frustum(*pos))
+            .collect();
+
+        water_positions.sort_by(|a, b| {
+            let dist = |(x, y, z):(i32, i32, i32)| {
+                let (dx, dy, dz) = (
+                    (x * CHUNK_SIZE + H_CHUNK_SIZE) as f32 - player.x,
+                    (y * CHUNK_SIZE + H_CHUNK_SIZE) as f32 - player.y,
+                    (z * CHUNK_SIZE + H_CHUNK_SIZE) as f32 - player.z);
+                dx*dx + dy*dy + dz*dz
+            };
+            dist(*b).total_cmp(&dist(*a))
+        });
+
+        //sub draws of an indirect draw are blended in order
+        let water_draws: Vec<_> = water_positions.iter()
+            .map(|pos| self.water_meshes[pos].draw())
+            .collect();
+
+        self.command_buffer.bind_pipeline(unsafe{&*((&*self.water_pipeline) as *const _)});
+        self.command_buffer.update_buffer(self.uniform_buffer.as_ref(), transform.as_bytes(), 0);
+        self.command_buffer.draw_indirect(&water_draws);
         self.command_buffer.submit();
+        self.mesh_pool.end_frame();
     }
 
 
     pub fn update(&mut self, player:&camera::Player) 
     {
-        for _ in 0..10
+        self.update_world(player);
+        self.api.inner.flush_uploads();
+
+        let ready = self.api.inner.uploads_ready();
+        self.uploading.retain(|&pos, &mut (opaque, water)| {
+            if opaque.iter().chain(&water).any(|mesh| mesh.ready > ready) 
+            {
+                return true;
+            }
+
+            for (meshes, mesh) in [(&mut self.meshes, opaque), (&mut self.water_meshes, water)]
+            {
+                let old = match mesh {
+                    Some(mesh) => meshes.insert(pos, mesh),
+                    None => meshes.remove(&pos)
+                };
+                if let Some(old) = old 
+                {
+                    self.mesh_pool.free(old);
+                }
+            }
+            false
+        });
+    }
+
+
+    //both meshes of the chunk or neither
+    fn upload_mesh(mesh_pool: &mut mesh_pool::MeshPool, mesh: &chunk::ChunkMesh) -> Result<ChunkMeshes, ()>
+    {
+        let upload = |mesh_pool: &mut mesh_pool::MeshPool, quads: &[vertex::Quad]| {
+            if quads.is_empty() {Ok(None)} else {mesh_pool.alloc(mesh.pos, mesh.lod, quads).map(Some)}
+        };
+
+        let opaque = upload(mesh_pool, &mesh.quads)?;
+        match upload(mesh_pool, &mesh.translucent) 
         {
-            if let Ok(mesh) = self.chunk_mesh_rx.try_recv() && mesh.vertices.len() > 0
+            Ok(water) => Ok((opaque, water)),
+            Err(()) => 
             {
-                let len = mesh.vertices.len();
-                let bytes = unsafe{std::slice::from_raw_parts(mesh.vertices.as_ptr() as *const u8, len * 4)};
-                let buf = self.api.inner.create_buffer(
-                    renderer::BufferCreateInfo::ReadOnly(bytes))
-                    .expect("failed to create vertex buffer");
+                opaque.into_iter().for_each(|mesh| mesh_pool.free(mesh));
+                Err(())
+            }
+        }
+    }
+
 
-                self.meshes.insert(mesh.pos, (buf, len as i32));
+    //nearest first, a mesh that doesn't fit makes room by dropping the meshes of chunks further
+    //away than its own, those are meshed again and wait here in turn
+    fn upload_deferred(&mut self, center: (i32, i32, i32)) 
+    {
+        let mut waiting: Vec<_> = self.deferred.keys().copied().collect();
+        waiting.sort_by_key(|&pos| util::chunk_distance(pos, center));
+        for pos in waiting 
+        {
+            let Ok((opaque, water)) = Scene::upload_mesh(&mut self.mesh_pool, &self.deferred[&pos]) else 
+            {
+                let mesh = &self.deferred[&pos];
+                self.make_room(pos, (mesh.quads.len() + mesh.translucent.len()) as u32, center);
+                return;
+            };
+            self.deferred.remove(&pos);
+
+            //a newer mesh of the same chunk makes the one still uploading pointless
+            if let Some((opaque, water)) = self.uploading.insert(pos, (opaque, water)) 
+            {
+                opaque.into_iter().chain(water).for_each(|mesh| self.mesh_pool.free(mesh));
             }
         }
+    }
 
-        self.update_world(player);
+
+    //freed meshes only come back after a few frames, nothing more is dropped while enough is on
+    //its way. The fragments left after that may still be too small, it's tried again next frame
+    fn make_room(&mut self, pos: (i32, i32, i32), quads: u32, center: (i32, i32, i32)) 
+    {
+        let distance = util::chunk_distance(pos, center);
+        let mut farther: Vec<_> = self.meshes.keys().chain(self.water_meshes.keys()).chain(self.uploading.keys())
+            .filter(|&&other| util::chunk_distance(other, center) > distance)
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        farther.sort_by_key(|&other| std::cmp::Reverse(util::chunk_distance(other, center)));
+
+        let mut dropped = Vec::new();
+        for other in farther 
+        {
+            if self.mesh_pool.retiring >= quads 
+            {
+                break;
+            }
+            let (opaque, water) = self.uploading.remove(&other).unwrap_or_default();
+            let meshes = [self.meshes.remove(&other), self.water_meshes.remove(&other), opaque, water];
+            meshes.into_iter().flatten().for_each(|mesh| self.mesh_pool.free(mesh));
+            self.lods.remove(&other);
+            dropped.push(other);
+        }
+        self.world.mark_dirty(dropped);
     }
 
 
-    pub fn update_world(&mut self, player: &camera::Player) 
+    pub fn simulate(&self, player:&mut camera::Player, dt:f32) 
     {
-        let (build_positions,
-            terrain_positions,
-            dirty_positions) = self.world.promote_chunks(player);
+        player.simulate(&self.world, dt);
+    }
+
 
-        let new_chunks: Vec<_> = build_positions.par_iter()
-            .map(|&p| 
+    //edits only mark chunks dirty, update_world sends them off to be re-meshed
+    pub fn interact(&mut self, player:&mut camera::Player) 
+    {
+        let Some(action) = player.take_action() else 
+        {
+            return;
+        };
+
+        let (origin, dir) = player.view_ray();
+        let Some(hit) = self.world.raycast(origin, dir, REACH_DISTANCE) else 
+        {
+            return;
+        };
+
+        match action 
+        {
+            camera::BlockAction::Break => 
             {
-                self.world.chunk_build_task(p)
-            }).collect();
-        
-        for chunk in new_chunks 
+                let (x, y, z) = hit.voxel;
+                self.world.set_voxel(x, y, z, VOXELS::EMPTY).expect("failed to break voxel");
+            }
+            camera::BlockAction::Place => 
+            {
+                let (dx, dy, dz) = hit.face.offset();
+                let (x, y, z) = (hit.voxel.0 + dx, hit.voxel.1 + dy, hit.voxel.2 + dz);
+                let pos = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+                if player.occupies(x, y, z) || !self.world.chunks.contains_key(&pos) 
+                {
+                    return;
+                }
+                self.world.set_voxel(x, y, z, player.selected).expect("failed to place voxel");
+            }
+        }
+    }
+
+
+    //everything around the player is generated, meshed and uploaded, or given up on
+    pub fn is_settled(&self, player:&camera::Player) -> bool 
+    {
+        let (heightmaps, missing, dirty) = self.world.promote_chunks(player);
+        self.scheduled.is_empty() && self.uploading.is_empty() && self.deferred.is_empty() 
+            && heightmaps.iter().all(|&(x, z)| self.given_up(jobs::Stage::Heightmap, (x, 0, z)))
+            && missing.iter().all(|&pos| self.given_up(jobs::Stage::Generate, pos))
+            && dirty.iter().all(|&pos| self.given_up(jobs::Stage::Mesh, pos))
+    }
+
+
+    fn given_up(&self, stage: jobs::Stage, pos: (i32, i32, i32)) -> bool 
+    {
+        self.failures.get(&(stage, pos)).is_some_and(|&failures| failures >= MAX_JOB_FAILURES)
+    }
+
+
+    pub fn voxel_memory(&self) -> usize 
+    {
+        self.world.voxel_memory()
+    }
+
+
+    pub fn save(&mut self) 
+    {
+        if let Err(err) = self.world.save() 
+        {
+            eprintln!("failed to save world: {}", err);
+        }
+    }
+
+
+    //generating, decorating and meshing run as jobs, this hands them out nearest first and
+    //takes in at most MAX_JOB_RESULTS finished ones per frame
+    pub fn update_world(&mut self, player: &camera::Player) 
+    {
+        //chunks that couldn't be saved stay loaded and are tried again next frame
+        let evicted = self.world.evict_chunks(player).unwrap_or_else(|err| {
+            eprintln!("failed to save evicted chunks, keeping them loaded: {}", err);
+            Vec::new()
+        });
+        for pos in evicted 
         {
-            self.world.chunks.insert(chunk.pos, Arc::new(chunk));
+            self.lods.remove(&pos);
+            self.deferred.remove(&pos);
+            let (opaque, water) = self.uploading.remove(&pos).unwrap_or_default();
+            let meshes = [self.meshes.remove(&pos), self.water_meshes.remove(&pos), opaque, water];
+            meshes.into_iter().flatten().for_each(|mesh| self.mesh_pool.free(mesh));
         }
 
-        let entities: Vec<_> = terrain_positions.par_iter()
-            .map(|&p| 
+        self.jobs.focus(player);
+        self.cancel_out_of_range(player);
+
+        for _ in 0..MAX_JOB_RESULTS 
+        {
+            let Some((id, output)) = self.jobs.try_recv() else 
             {
-                self.world.entity_build_task(p)
-            }).collect();
-        
-        self.world.decorate(entities).expect("failed to generate entities");
+                break;
+            };
+            self.finish_job(id, output);
+        }
+        self.upload_deferred((player.chunk_x, player.chunk_y, player.chunk_z));
 
-        if !dirty_positions.is_empty()
+        let (heightmaps, missing, dirty) = self.world.promote_chunks(player);
+        for (x, z) in heightmaps 
         {
-            for p in dirty_positions.iter()
+            //heightmap jobs are keyed by their column at y 0
+            if !self.scheduled.contains_key(&(jobs::Stage::Heightmap, (x, 0, z))) 
             {
-                let new_chunk = self.world.chunks.remove(p).unwrap()
-                    .unwrap_arc()
-                    .with_status(chunk::ChunkStatus::Clean)
-                    .wrap_arc();
-                self.world.chunks.insert(*p, new_chunk);
+                let generator = self.world.generator();
+                self.schedule(jobs::Stage::Heightmap, (x, 0, z), move || {
+                    jobs::Output::Heightmap{pos: (x, z), heightmap: generator.heightmap((x, z))}
+                });
             }
+        }
 
-            dirty_positions.par_iter().for_each(|p|
+        for pos in missing 
+        {
+            if !self.scheduled.contains_key(&(jobs::Stage::Generate, pos)) 
             {
-                let cluster = world::ChunkCluster::new(&self.world, p.0, p.1, p.2);
-                let _ = self.chunk_tx.send(cluster);
-            });
+                let generator = self.world.generator();
+                let heightmap = self.world.heightmaps[&(pos.0, pos.2)].clone();
+                self.schedule(jobs::Stage::Generate, pos, move || {
+                    let (chunk, built) = generator.chunk(pos, &heightmap);
+                    jobs::Output::Generated{chunk, built, pending: generator.pending(pos)}
+                });
+            }
+        }
+
+        //decorate jobs cancelled while the chunk was out of range start over when it's back
+        let center = (player.chunk_x, player.chunk_y, player.chunk_z);
+        let undecorated: Vec<_> = self.world.undecorated.iter()
+            .filter(|&&pos| util::chunk_distance(pos, center) <= RENDER_DISTANCE + 1)
+            .filter(|&&pos| !self.scheduled.contains_key(&(jobs::Stage::Decorate, pos)))
+            .copied()
+            .collect();
+        for pos in undecorated 
+        {
+            self.decorate(pos);
+        }
+
+        //top down, chunks lit after the one above them don't have to be shaded by it later
+        let mut unlit: Vec<_> = self.world.unlit.iter()
+            .filter(|&&pos| util::chunk_distance(pos, center) <= RENDER_DISTANCE + 1)
+            .filter(|&&pos| !self.scheduled.contains_key(&(jobs::Stage::Light, pos)))
+            .copied()
+            .collect();
+        unlit.sort_by_key(|pos| -pos.1);
+        for pos in unlit 
+        {
+            self.light(pos);
+        }
+
+        //a chunk that changed again while its last mesh was being built gets a new job,
+        //the old one is cancelled when the new one replaces it
+        for pos in dirty 
+        {
+            let chunk = self.world.chunks.get_mut(&pos).unwrap();
+            Arc::make_mut(chunk).status = chunk::ChunkStatus::Clean;
+            self.mesh(pos, lod::Levels::new(pos, center));
+        }
+
+        //chunks the player moved towards or away from get a mesh of their new lod, and ones
+        //whose neighbours changed lod new skirts. The old mesh stays drawn until it is uploaded
+        let relod: Vec<_> = self.lods.iter()
+            .filter_map(|(&pos, &old)| {
+                let levels = lod::Levels::new(pos, center);
+                (util::chunk_distance(pos, center) <= RENDER_DISTANCE && old != levels && self.world.can_mesh(pos, center)).then_some((pos, levels))
+            })
+            .collect();
+        for (pos, levels) in relod 
+        {
+            self.mesh(pos, levels);
         }
     }
-}
 
 
-impl MeshBuilder 
-{
-    pub fn new( 
-        chunk_rx: mpsc::Receiver<world::ChunkCluster>, 
-        mesh_tx: mpsc::Sender<chunk::ChunkMesh>) -> MeshBuilder
+    fn mesh(&mut self, pos: (i32, i32, i32), levels: lod::Levels) 
     {
-        MeshBuilder{mesh_tx, chunk_rx}
+        self.lods.insert(pos, levels);
+        let cluster = world::ChunkCluster::new(&self.world, pos.0, pos.1, pos.2);
+        self.schedule(jobs::Stage::Mesh, pos, move || {
+            let center = cluster.center.clone().unwrap();
+            jobs::Output::Meshed(center.get_mesh(cluster, levels))
+        });
     }
 
-    pub fn build_mesh(&self) 
+
+    //decorated from the chunk as it came out of the generator plus pending edits
+    fn decorate(&mut self, pos: (i32, i32, i32)) 
     {
-        //for chunk in self.chunk_rx.iter()
-        if let Ok(chunk) = self.chunk_rx.recv()
+        let chunk = self.world.chunks[&pos].clone();
+        let generator = self.world.generator();
+        self.schedule(jobs::Stage::Decorate, pos, move || {
+            jobs::Output::Decorated{pos, edits: generator.decorations(&chunk)}
+        });
+    }
+
+
+    //lit off the main thread, the result is thrown away if the chunk changes in the meantime
+    fn light(&mut self, pos: (i32, i32, i32)) 
+    {
+        let Some(lone) = light::LoneChunk::new(&self.world.chunks, &self.world.heightmaps, pos) else 
         {
-            let tx_clone = self.mesh_tx.clone();
-            rayon::spawn( move|| 
+            return;
+        };
+        self.schedule(jobs::Stage::Light, pos, move || {
+            let light = lone.light();
+            jobs::Output::Lit{lone, light}
+        });
+    }
+
+
+    fn schedule(
+        &mut self, 
+        stage: jobs::Stage, 
+        pos: (i32, i32, i32), 
+        work: impl FnOnce() -> jobs::Output + Send + 'static)
+    {
+        if self.given_up(stage, pos) 
+        {
+            return;
+        }
+        let id = self.jobs.submit(pos, work);
+        if let Some(old) = self.scheduled.insert((stage, pos), id) 
+        {
+            self.jobs.cancel(&HashSet::from([old]));
+        }
+    }
+
+
+    //chunks only get generated and decorated out to the border and meshed inside the render
+    //range, heightmaps are needed for every column out to the border at any height. Failures
+    //are forgotten out of range so the chunk is tried again when the player comes back
+    fn cancel_out_of_range(&mut self, player: &camera::Player) 
+    {
+        let center = (player.chunk_x, player.chunk_y, player.chunk_z);
+        let in_range = |stage: jobs::Stage, pos: (i32, i32, i32)| {
+            let distance = match stage {
+                jobs::Stage::Heightmap => util::chunk_distance((pos.0, center.1, pos.2), center),
+                _ => util::chunk_distance(pos, center)
+            };
+            let range = match stage {
+                jobs::Stage::Heightmap | jobs::Stage::Generate | jobs::Stage::Decorate | jobs::Stage::Light => RENDER_DISTANCE + 1,
+                jobs::Stage::Mesh => RENDER_DISTANCE
+            };
+            distance <= range
+        };
+        let mut cancelled = HashSet::new();
+        self.scheduled.retain(|&(stage, pos), &mut id| {
+            if !in_range(stage, pos) 
+            {
+                cancelled.insert(id);
+            }
+            in_range(stage, pos)
+        });
+        self.jobs.cancel(&cancelled);
+        self.failures.retain(|&(stage, pos), _| in_range(stage, pos));
+        self.deferred.retain(|&pos, _| util::chunk_distance(pos, center) <= RENDER_DISTANCE);
+    }
+
+
+    //chunks that come out of a finished stage and need light go to unlit
+    fn finish_job(&mut self, id: jobs::JobId, output: jobs::Output) 
+    {
+        let (stage, pos) = match &output {
+            jobs::Output::Heightmap{pos, ..} => (jobs::Stage::Heightmap, (pos.0, 0, pos.1)),
+            jobs::Output::Generated{chunk, ..} => (jobs::Stage::Generate, chunk.pos),
+            jobs::Output::Decorated{pos, ..} => (jobs::Stage::Decorate, *pos),
+            jobs::Output::Lit{lone, ..} => (jobs::Stage::Light, lone.chunk.pos),
+            jobs::Output::Meshed(mesh) => (jobs::Stage::Mesh, mesh.pos),
+            jobs::Output::Failed{..} => match self.scheduled.iter().find(|&(_, &job)| job == id) 
+            {
+                Some((&key, _)) => key,
+                None => return
+            }
+        };
+        if self.scheduled.get(&(stage, pos)) != Some(&id) 
+        {
+            return;
+        }
+        self.scheduled.remove(&(stage, pos));
+        if !matches!(output, jobs::Output::Failed{..}) 
+        {
+            self.failures.remove(&(stage, pos));
+        }
+
+        match output 
+        {
+            jobs::Output::Heightmap{pos, heightmap} => 
+            {
+                self.world.heightmaps.insert(pos, Arc::new(heightmap));
+            }
+            jobs::Output::Generated{chunk, built, pending} => 
+            {
+                //all air and nothing to write into it, nothing to decorate, light or mesh either
+                if built && chunk.status == chunk::ChunkStatus::Empty 
+                    && pending.is_empty() && !self.world.pending.contains_key(&pos) 
                 {
-                    let center = chunk.clone().center.unwrap();
-                    let _ = tx_clone.send(center.get_mesh(chunk));
-                });
+                    self.world.empty.insert(pos);
+                    return;
+                }
+                self.world.chunks.insert(pos, Arc::new(chunk));
+                self.world.apply_pending(pos, pending).expect("failed to apply pending edits");
+                if !built 
+                {
+                    self.world.unlit.insert(pos);
+                    return;
+                }
+
+                self.world.undecorated.insert(pos);
+                self.decorate(pos);
+            }
+            jobs::Output::Decorated{edits, ..} => 
+            {
+                if self.world.undecorated.remove(&pos) 
+                {
+                    self.world.apply_edits(edits).expect("failed to generate entities");
+                    self.world.unlit.insert(pos);
+                }
+            }
+            jobs::Output::Lit{lone, light} => 
+            {
+                //a changed chunk stays unlit and is sent off again by update_world
+                if self.world.apply_light(&lone, light) 
+                {
+                    self.world.unlit.remove(&pos);
+                }
+            }
+            jobs::Output::Meshed(mesh) => 
+            {
+                //uploaded at the end of update_world, a newer one replaces one still waiting
+                self.deferred.insert(mesh.pos, mesh);
+            }
+            jobs::Output::Failed{..} => 
+            {
+                let failures = self.failures.entry((stage, pos)).or_default();
+                *failures += 1;
+                if *failures >= MAX_JOB_FAILURES 
+                {
+                    eprintln!("{:?} job for {:?} failed {} times, giving up", stage, pos, failures);
+                    return;
+                }
+
+                //the other stages are scheduled again by update_world now that they aren't
+                //scheduled, a chunk is only meshed again when it's dirty
+                eprintln!("{:?} job for {:?} failed, trying again", stage, pos);
+                if stage == jobs::Stage::Mesh 
+                {
+                    self.world.mark_dirty([pos]);
+                }
+            }
         }
     }
 }
@@ -0,0 +1,235 @@
+use crate::{*, camera::Player, scene::Scene};
+
+// Renders one frame of a generated world without a window and writes it to a png.
+// Nothing is loaded from or saved to the save directory so the output only depends
+// on the seed and the camera pose.
+pub struct ScreenshotInfo {
+    pub path: String,
+    pub api: renderer::ApiCreateInfo,
+    pub seed: u32,
+    pub pos: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub width: u32,
+    pub height: u32
+}
+
+impl ScreenshotInfo 
+{
+    //--screenshot <path> [--gl] [--seed n] [--pos x y z] [--yaw r] [--pitch r] [--size w h]
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Option<ScreenshotInfo>, String> 
+    {
+        let mut info = ScreenshotInfo{
+            path: String::new(),
+            api: renderer::ApiCreateInfo::VK,
+            seed: SEED,
+            pos: [START_X, START_Y, START_Z],
+            yaw: 0.0,
+            pitch: 0.0,
+            width: WIDTH,
+            height: HEIGHT
+        };
+        let mut screenshot = false;
+
+        while let Some(arg) = args.next() 
+        {
+            match arg.as_str() 
+            {
+                "--screenshot" => 
+                {
+                    screenshot = true;
+                    info.path = args.next().ok_or("--screenshot needs an output path")?;
+                }
+                "--gl" => info.api = renderer::ApiCreateInfo::GL,
+                "--seed" => info.seed = parse_next(&mut args, &arg)?,
+                "--pos" => 
+                {
+                    for axis in 0..3 
+                    {
+                        info.pos[axis] = parse_next(&mut args, &arg)?;
+                    }
+                }
+                "--yaw" => info.yaw = parse_next(&mut args, &arg)?,
+                "--pitch" => info.pitch = parse_next(&mut args, &arg)?,
+                "--size" => 
+                {
+                    info.width = parse_next(&mut args, &arg)?;
+                    info.height = parse_next(&mut args, &arg)?;
+                }
+                _ => return Err(format!("unknown argument {}", arg))
+            }
+        }
+
+        if !screenshot 
+        {
+            return Ok(None);
+        }
+
+        if info.width == 0 || info.height == 0 
+        {
+            return Err("--size must not be zero".to_string());
+        }
+
+        Ok(Some(info))
+    }
+}
+
+
+fn parse_next<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, String> 
+{
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .ok_or(format!("{} is missing a value or it isn't a number", flag))
+}
+
+
+pub fn run(info: ScreenshotInfo) -> Result<(), String> 
+{
+    let pixels = render(&info)?;
+    image::save_buffer(&info.path, &pixels, info.width, info.height, image::ColorType::Rgba8)
+        .map_err(|err| format!("failed to write screenshot: {}", err))
+}
+
+
+//rgba8 rows top to bottom, fails when the world around the camera doesn't finish loading
+//within SCREENSHOT_TIMEOUT_SECS
+pub fn render(info: &ScreenshotInfo) -> Result<Vec<u8>, String> 
+{
+    let api = info.api.request_headless(info.width, info.height)
+        .map_err(|_| "failed to create headless renderer".to_string())?;
+    //the api is shared with the scene but stays on this thread
+    #[allow(clippy::arc_with_non_send_sync)]
+    let api = Arc::new(api);
+
+    let mut player = Player::new();
+    [player.x, player.y, player.z] = info.pos;
+    player.yaw = info.yaw;
+    player.pitch = info.pitch;
+    player.aspect = info.width as f32 / info.height as f32;
+    player.update(&[], 0.0);
+
+    let world = world::World::new()
+        .with_seed(info.seed)
+        .without_store();
+    let mut scene = Scene::new(api.clone(), world);
+
+    let start = std::time::Instant::now();
+    let timeout = std::time::Duration::from_secs(SCREENSHOT_TIMEOUT_SECS);
+    while !scene.is_settled(&player) 
+    {
+        if start.elapsed() > timeout 
+        {
+            return Err(format!("the world didn't finish loading within {}s", SCREENSHOT_TIMEOUT_SECS));
+        }
+        scene.update(&player);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+
+    scene.draw(&player);
+    api.inner.read_frame()
+        .map_err(|_| "failed to read back frame".to_string())
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn args(line: &str) -> Result<Option<ScreenshotInfo>, String>
+    {
+        ScreenshotInfo::from_args(line.split_whitespace().map(String::from))
+    }
+
+
+    #[test]
+    fn parses_arguments()
+    {
+        assert!(args("").unwrap().is_none());
+
+        let info = args("--screenshot out.png --gl --seed 7 --pos 1 2.5 -3 --yaw 0.5 --pitch -1 --size 64 32").unwrap().unwrap();
+        assert_eq!(info.path, "out.png");
+        assert!(matches!(info.api, renderer::ApiCreateInfo::GL));
+        assert_eq!(info.seed, 7);
+        assert_eq!(info.pos, [1.0, 2.5, -3.0]);
+        assert_eq!((info.yaw, info.pitch), (0.5, -1.0));
+        assert_eq!((info.width, info.height), (64, 32));
+
+        assert!(args("--screenshot").is_err());
+        assert!(args("--screenshot out.png --size 0 32").is_err());
+        assert!(args("--screenshot out.png --seed x").is_err());
+        assert!(args("--screenshot out.png --fov 90").is_err());
+    }
+
+
+    //renders a fixed pose and compares it to tests/golden/<name>.png, GOLDEN_BLESS writes the
+    //render there instead. A mismatch is written next to it as <name>.actual.png. Building with
+    //gpu-tests says there is a device, not finding one or the golden image is a failure
+    #[cfg(feature = "gpu-tests")]
+    fn golden(name: &str, api: renderer::ApiCreateInfo)
+    {
+        //glfw and the drivers don't like being set up from two test threads at once
+        static GPU: Mutex<()> = Mutex::new(());
+        let _guard = GPU.lock().unwrap_or_else(|err| err.into_inner());
+
+        if api.request_headless(1, 1).is_err()
+        {
+            panic!("no {} device, gpu-tests needs one", name);
+        }
+
+        let info = ScreenshotInfo{
+            path: String::new(),
+            api,
+            seed: 1,
+            pos: [0.0, 80.0, 0.0],
+            yaw: 0.6,
+            pitch: -0.4,
+            width: 320,
+            height: 180
+        };
+        let pixels = render(&info).unwrap();
+
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+        let path = dir.join(format!("{}.png", name));
+        if std::env::var_os("GOLDEN_BLESS").is_some()
+        {
+            std::fs::create_dir_all(&dir).unwrap();
+            image::save_buffer(&path, &pixels, info.width, info.height, image::ColorType::Rgba8).unwrap();
+            eprintln!("wrote {}", path.display());
+            return;
+        }
+
+        let expected = image::open(&path)
+            .unwrap_or_else(|err| panic!("no golden image at {}, run with GOLDEN_BLESS=1 to write it: {}", path.display(), err))
+            .to_rgba8();
+        assert_eq!(expected.dimensions(), (info.width, info.height));
+        //drivers round differently, a few pixels off by a little are fine
+        let wrong = pixels.chunks_exact(4)
+            .zip(expected.as_raw().chunks_exact(4))
+            .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 8))
+            .count();
+        let allowed = (info.width * info.height) as usize / 100;
+        if wrong > allowed
+        {
+            let actual = dir.join(format!("{}.actual.png", name));
+            image::save_buffer(&actual, &pixels, info.width, info.height, image::ColorType::Rgba8).unwrap();
+            panic!("{} pixels differ from {}, see {}", wrong, path.display(), actual.display());
+        }
+    }
+
+
+    #[cfg(feature = "gpu-tests")]
+    #[test]
+    fn golden_vulkan()
+    {
+        golden("vulkan", renderer::ApiCreateInfo::VK);
+    }
+
+
+    #[cfg(feature = "gpu-tests")]
+    #[test]
+    fn golden_opengl()
+    {
+        golden("opengl", renderer::ApiCreateInfo::GL);
+    }
+}
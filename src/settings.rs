@@ -3,6 +3,10 @@ pub use camera_settings::*;
 pub use chunk_settings::*;
 pub use game_settings::*;
 pub use entities::*;
+pub use save_settings::*;
+pub use physics_settings::*;
+pub use terrain_settings::*;
+pub use render_settings::*;
 
 pub mod screen_settings 
 {
@@ -16,20 +20,15 @@ pub mod screen_settings
 pub mod camera_settings 
 {
     use super::screen_settings;
-    use super::chunk_settings;
 
     pub const NEAR:f32 = 0.1;
     pub const FAR:f32 = 1000.0;
     pub const VFOV_TAN:f32 = 0.5;
     pub const HFOV_TAN:f32 = VFOV_TAN * screen_settings::ASPECT_RATIO;
-    pub const VFOV_SEC:f32 = 1.0 + 0.5 * VFOV_TAN*VFOV_TAN;
-    pub const HFOV_SEC:f32 = 1.0 + 0.5 * HFOV_TAN*HFOV_TAN;
     pub const INV_VFOV:f32 = 1.0 / VFOV_TAN;
     pub const INV_HFOV:f32 = 1.0 / HFOV_TAN;
     pub const DEPTH:f32 = FAR - NEAR;
     pub const INV_DEPTH:f32 = 1.0 / DEPTH;
-    pub const FRUSTUM_Y: f32 = VFOV_SEC * chunk_settings::CHUNK_RADIUS;
-    pub const FRUSTUM_X: f32 = HFOV_SEC * chunk_settings::CHUNK_RADIUS;
     pub const PROJECTION: [f32; 16] = [
         INV_HFOV,      0.0,                  0.0,                      0.0,
             0.0, INV_VFOV,                  0.0,                      0.0,
@@ -46,7 +45,7 @@ pub mod chunk_settings
     pub const H_CHUNK_SIZE:i32 = CHUNK_SIZE / 2;
     pub const CHUNK_AREA:i32 = CHUNK_SIZE * CHUNK_SIZE;
     pub const CHUNK_VOL:i32 = CHUNK_AREA * CHUNK_SIZE;
-    pub const CHUNK_RADIUS: f32 = 27.7128129211;//CHUNK_SIZE as f32 * math::ROOT_3 / 2.0;
+    pub const CHUNK_RADIUS: f32 = 27.712_812;//CHUNK_SIZE as f32 * math::ROOT_3 / 2.0;
 }
 
 
@@ -54,10 +53,26 @@ pub mod game_settings
 {
     use super::chunk_settings;
 
-    pub const RENDER_DISTANCE:i32 = 5;
+    pub const RENDER_DISTANCE:i32 = 8;
     pub const RENDER_VOL:i32 = (2*RENDER_DISTANCE+1)*(2*RENDER_DISTANCE+1)*(2*RENDER_DISTANCE+1);
+    //chunks stay loaded until they are this far away so turning back doesn't reload them
+    pub const UNLOAD_DISTANCE:i32 = RENDER_DISTANCE + 3;
+    //chunks kept in memory, past it the least recently used ones outside the border are dropped
+    //early. Has to hold the render range and its border, (2*RENDER_DISTANCE+3)^3
+    pub const CHUNK_BUDGET:usize = 8192;
+    //finished jobs taken in per frame
+    pub const MAX_JOB_RESULTS:usize = 32;
+    //a job that panics this many times for the same stage and chunk isn't tried again
+    pub const MAX_JOB_FAILURES:u32 = 3;
+    //chunks at least this many chunks from the player are meshed at lod 1, 2, ..., each one
+    //halving the resolution. Past the first three rings lod 1 and 2 take two and four rings
+    pub const LOD_DISTANCES:[i32; 2] = [3, 5];
+    //chunks the heightmap proves solid are only loaded within this many chunks of the player
+    pub const OCCLUDED_RADIUS:i32 = 2;
 
     pub const SEED:u32 = 1;
+    pub const REACH_DISTANCE:f32 = 8.0;
+    pub const BLOCKS_PATH:&str = "./assets/blocks.json";
 
     pub const START_X:f32 = 0.0;
     pub const START_Y:f32 = 0.0;
@@ -69,42 +84,140 @@ pub mod game_settings
 }
 
 
+pub mod terrain_settings 
+{
+    pub const SEA_LEVEL:i32 = 10;
+    pub const SNOW_LINE:i32 = 64;
+    pub const SUBSURFACE_DEPTH:i32 = 4;
+    //horizontal size of the temperature and humidity features in voxels
+    pub const BIOME_SCALE:f64 = 600.1;
+    //how far climate can drift from a biome's center before the next one takes over
+    pub const BIOME_BLEND:f64 = 0.05;
+    pub const INV_BIOME_BLEND:f64 = 1.0 / BIOME_BLEND;
+    pub const CLIMATE_CONTRAST:f64 = 1.5;
+
+    //3D noise can move the surface by up to this many voxels, making overhangs and arches
+    pub const OVERHANG_RANGE:i32 = 12;
+    pub const INV_OVERHANG_RANGE:f64 = 1.0 / OVERHANG_RANGE as f64;
+
+    //every CAVE_CELL x CAVE_CELL column of the world may start one worm
+    pub const CAVE_CELL:i32 = 64;
+    pub const CAVE_CHANCE:u64 = 70;
+    pub const CAVE_STEPS:i32 = 120;
+    pub const CAVE_STEP_LENGTH:f64 = 1.5;
+    pub const CAVE_MIN_RADIUS:f64 = 1.5;
+    pub const CAVE_MAX_RADIUS:f64 = 3.5;
+    pub const CAVE_MIN_Y:i32 = 6;
+    pub const CAVE_MAX_Y:i32 = 48;
+    pub const CAVE_TURN_SCALE:f64 = 40.1;
+    pub const CAVE_REACH:f64 = CAVE_STEPS as f64 * CAVE_STEP_LENGTH + CAVE_MAX_RADIUS;
+
+    //keeps the feature rolls from lining up with the cave cells sharing a position
+    pub const FEATURE_SEED_OFFSET:u32 = 5;
+    //surface feature chances are out of this many candidate voxels
+    pub const FEATURE_ROLL:i32 = 65536;
+}
+
+
+pub mod physics_settings 
+{
+    pub const PHYSICS_DT:f32 = 1.0 / 60.0;
+    pub const GRAVITY:f32 = 28.0;
+    pub const TERMINAL_VELOCITY:f32 = 60.0;
+    pub const JUMP_VELOCITY:f32 = 9.0;
+    pub const WALK_SPEED:f32 = 5.0;
+    pub const PLAYER_HALF_WIDTH:f32 = 0.3;
+    pub const PLAYER_HEIGHT:f32 = 1.8;
+    pub const EYE_HEIGHT:f32 = 1.6;
+    pub const STEP_HEIGHT:f32 = 1.05;
+}
+
+
+pub mod render_settings 
+{
+    //quads in the shared chunk mesh buffer, 8 bytes each
+    pub const QUAD_POOL_SIZE:u32 = 1 << 22;
+    //chunk meshes that can be resident at once, opaque and translucent count separately
+    pub const MESH_SLOTS:u32 = 1 << 16;
+    //frames a freed mesh range waits before it is reused, at least the frames in flight
+    pub const MESH_RETIRE_FRAMES:u64 = 3;
+    //a screenshot gives up when the world around the camera isn't loaded by then
+    pub const SCREENSHOT_TIMEOUT_SECS:u64 = 120;
+}
+
+
+pub mod save_settings 
+{
+    pub const SAVE_DIR:&str = "./saves/world";
+    pub const REGION_SIZE:i32 = 8;
+    pub const REGION_AREA:i32 = REGION_SIZE * REGION_SIZE;
+    pub const REGION_VOL:i32 = REGION_AREA * REGION_SIZE;
+}
+
+
 pub mod entities 
 {
-    pub const NUM_ENTITIES:usize = 8;
-    pub const NUM_TEXTURES:i32 = NUM_ENTITIES as i32 + 1;
-    #[repr(u8)]
+    //index into block::registry(), only the blocks the generator needs have names here
     #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
-    pub enum VOXELS {
-        EMPTY,
-        SAND,
-        GRASS,
-        DIRT,
-        COBBLESTONE,
-        SNOW,
-        LEAF,
-        WOOD,
-        WATER
-    }
+    pub struct VOXELS(pub u16);
 
     impl VOXELS {
+        pub const EMPTY: VOXELS = VOXELS(0);
+        pub const SAND: VOXELS = VOXELS(1);
+        pub const GRASS: VOXELS = VOXELS(2);
+        pub const DIRT: VOXELS = VOXELS(3);
+        pub const COBBLESTONE: VOXELS = VOXELS(4);
+        pub const SNOW: VOXELS = VOXELS(5);
+        pub const LEAF: VOXELS = VOXELS(6);
+        pub const WOOD: VOXELS = VOXELS(7);
+        pub const WATER: VOXELS = VOXELS(8);
+        pub const COAL: VOXELS = VOXELS(9);
+        pub const IRON: VOXELS = VOXELS(10);
+        pub const GOLD: VOXELS = VOXELS(11);
+        pub const DIAMOND: VOXELS = VOXELS(12);
+
+        //every placeable block, air excluded
         pub fn iter() -> impl Iterator<Item = VOXELS> {
-            [
-                VOXELS::SAND,
-                VOXELS::GRASS,
-                VOXELS::DIRT,
-                VOXELS::COBBLESTONE,
-                VOXELS::SNOW,
-                VOXELS::LEAF,
-                VOXELS::WOOD,
-                VOXELS::WATER
-            ].into_iter()
+            (1..crate::block::registry().len() as u16).map(VOXELS)
+        }
+    }
+
+    //names the block file has to use at the fixed ids above
+    pub const BUILTIN_BLOCKS: [(VOXELS, &str); 13] = [
+        (VOXELS::EMPTY, "air"),
+        (VOXELS::SAND, "sand"),
+        (VOXELS::GRASS, "grass"),
+        (VOXELS::DIRT, "dirt"),
+        (VOXELS::COBBLESTONE, "cobblestone"),
+        (VOXELS::SNOW, "snow"),
+        (VOXELS::LEAF, "leaf"),
+        (VOXELS::WOOD, "wood"),
+        (VOXELS::WATER, "water"),
+        (VOXELS::COAL, "coal_ore"),
+        (VOXELS::IRON, "iron_ore"),
+        (VOXELS::GOLD, "gold_ore"),
+        (VOXELS::DIAMOND, "diamond_ore"),
+    ];
+
+    //width of the id in vertices, the same as VOXELS
+    pub const VOXEL_ID_BITS:u32 = 16;
+
+    impl TryFrom<u16> for VOXELS {
+        type Error = ();
+
+        fn try_from(id: u16) -> Result<Self, Self::Error> {
+            if (id as usize) < crate::block::registry().len() {
+                Ok(VOXELS(id))
+            } else {
+                Err(())
+            }
         }
     }
 
     #[repr(u8)]
     #[derive(Clone, Copy, PartialEq, Debug)]
     pub enum ENTITIES {
-        SEED
+        //index into feature::FEATURES
+        FEATURE(u8)
     }
 }
@@ -1,24 +1,98 @@
 use noise::{NoiseFn, Perlin};
 use crate::settings::*;
-use std::sync::{Arc, atomic::{AtomicU8, AtomicBool, Ordering}};
 
 pub struct Noise {
+    pub seed: u32,
     perlin: Perlin,
+    temperature: Perlin,
+    humidity: Perlin,
+    density: Perlin,
+    cave: Perlin,
 }
 
 impl Noise {
     pub fn new(seed:u32) -> Self {
-        Self{perlin: Perlin::new(seed)}
+        Self{
+            seed,
+            perlin: Perlin::new(seed),
+            temperature: Perlin::new(seed.wrapping_add(1)),
+            humidity: Perlin::new(seed.wrapping_add(2)),
+            density: Perlin::new(seed.wrapping_add(3)),
+            cave: Perlin::new(seed.wrapping_add(4)),
+        }
     }
 
-    pub fn get_height(&self, x:f64, z:f64) -> i32 {
-        let h1 = (self.perlin.get([x / 100.1, z / 100.1]) * CHUNK_SIZE as f64) as i32;
-        let h2 = (self.perlin.get([x / 200.1, z / 200.1]) * CHUNK_SIZE as f64) as i32;
-        let h3 = (self.perlin.get([x / 400.1, z / 400.1]) * CHUNK_SIZE as f64) as i32;
-        let h4 = (self.perlin.get([x / 800.1, z / 800.1]) * CHUNK_SIZE as f64) as i32;
+    //four octaves blended into roughly -1..1, biomes scale it into their own heights
+    pub fn get_detail(&self, x:f64, z:f64) -> f64 {
+        let h1 = self.perlin.get([x / 100.1, z / 100.1]);
+        let h2 = self.perlin.get([x / 200.1, z / 200.1]);
+        let h3 = self.perlin.get([x / 400.1, z / 400.1]);
+        let h4 = self.perlin.get([x / 800.1, z / 800.1]);
 
-        H_CHUNK_SIZE + h1 - h2 + h3 - h4 
+        h1 - h2 + h3 - h4
     }
+
+    //3D noise pushed against the heightmap, positive density is solid
+    pub fn get_density(&self, x:f64, y:f64, z:f64, height:i32) -> f64 {
+        let n = self.density.get([x / 60.1, y / 30.1, z / 60.1]) 
+            + 0.5 * self.density.get([x / 20.1, y / 20.1, z / 20.1]);
+
+        (height as f64 - y) * INV_OVERHANG_RANGE + n
+    }
+
+    //smooth 3D noise for steering cave worms, callers offset the input for independent channels
+    pub fn get_cave(&self, x:f64, y:f64, z:f64) -> f64 {
+        self.cave.get([x / CAVE_TURN_SCALE, y / CAVE_TURN_SCALE, z / CAVE_TURN_SCALE])
+    }
+
+    //(temperature, humidity) clamped to -1..1
+    pub fn get_climate(&self, x:f64, z:f64) -> (f64, f64) {
+        let t = self.temperature.get([x / BIOME_SCALE, z / BIOME_SCALE]) * CLIMATE_CONTRAST;
+        let h = self.humidity.get([x / BIOME_SCALE, z / BIOME_SCALE]) * CLIMATE_CONTRAST;
+
+        (t.clamp(-1.0, 1.0), h.clamp(-1.0, 1.0))
+    }
+}
+
+
+//splitmix64 finalizer over a seed and a position, the same inputs give the same bits on every thread
+pub fn hash(seed:u32, (x,y,z):(i32, i32, i32)) -> u64 {
+    let mut h = ((x as u32 as u64) << 32 | z as u32 as u64)
+        .wrapping_add((y as u32 as u64).wrapping_mul(0xD6E8FEB86659FD93))
+        .wrapping_add((seed as u64).wrapping_mul(0x9E3779B97F4A7C15));
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+    h ^ (h >> 31)
+}
+
+
+//small deterministic generator for placement passes, seeded from hash
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed:u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut h = self.0;
+        h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+        h ^ (h >> 31)
+    }
+
+    //uniform in lo..hi, hi must be above lo
+    pub fn range(&mut self, lo:i32, hi:i32) -> i32 {
+        lo + (self.next_u64() % (hi - lo) as u64) as i32
+    }
+}
+
+
+//chunks from one to the other along the axis they're furthest apart on
+#[inline(always)]
+pub fn chunk_distance((x1,y1,z1):(i32,i32,i32), (x2,y2,z2):(i32,i32,i32)) -> i32 {
+    (x1-x2).abs().max((y1-y2).abs()).max((z1-z2).abs())
 }
 
 
@@ -94,24 +168,114 @@ pub const fn chunk_center_from_global_index((x, y, z): (i32, i32, i32)) -> (f32,
     )
 }
 
-#[derive(Clone)]
-pub struct Semaphore {
-    status: std::sync::Arc<std::sync::atomic::AtomicBool>
+//sorted (start, len) ranges that are free, handed out first fit
+pub struct FreeList {
+    //sorted by start and never adjacent
+    ranges: Vec<(u64, u64)>,
 }
 
-impl Semaphore {
-    pub fn new() -> Semaphore {
-        Semaphore {status: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false))}
+impl FreeList {
+    pub fn new(size: u64) -> FreeList {
+        FreeList{ranges: vec![(0, size)]}
+    }
+
+    //start of a range of len whose start is a multiple of align, None when nothing fits
+    pub fn alloc(&mut self, len: u64, align: u64) -> Option<u64> {
+        let (idx, start) = self.ranges.iter().enumerate().find_map(|(idx, &(start, free_len))| {
+            let aligned = start.next_multiple_of(align);
+            (aligned + len <= start + free_len).then_some((idx, aligned))
+        })?;
+
+        let (free_start, free_len) = self.ranges.remove(idx);
+        let end = start + len;
+        if end < free_start + free_len
+        {
+            self.ranges.insert(idx, (end, free_start + free_len - end));
+        }
+        //padding left in front by the alignment stays free
+        if start > free_start
+        {
+            self.ranges.insert(idx, (free_start, start - free_start));
+        }
+        Some(start)
     }
 
-    pub fn signal(&self) {
-        //self.status.fetch_not(std::sync::atomic::Ordering::Relaxed);
-        self.status.store(true, std::sync::atomic::Ordering::Relaxed);
+    pub fn free(&mut self, start: u64, len: u64) {
+        let idx = self.ranges.partition_point(|&(free_start, _)| free_start < start);
+        self.ranges.insert(idx, (start, len));
+
+        //merge with the neighbours on either side
+        if idx + 1 < self.ranges.len() && start + len == self.ranges[idx + 1].0
+        {
+            self.ranges[idx].1 += self.ranges[idx + 1].1;
+            self.ranges.remove(idx + 1);
+        }
+        if idx > 0 && self.ranges[idx - 1].0 + self.ranges[idx - 1].1 == start
+        {
+            self.ranges[idx - 1].1 += self.ranges[idx].1;
+            self.ranges.remove(idx);
+        }
     }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn aligned_allocs_leave_their_padding_free()
+    {
+        let mut list = FreeList::new(256);
+        assert_eq!(list.alloc(10, 1), Some(0));
+        assert_eq!(list.alloc(16, 64), Some(64));
+        assert_eq!(list.ranges, vec![(10, 54), (80, 176)]);
 
-    pub fn wait(&self) {
-        while !self.status.load(std::sync::atomic::Ordering::Relaxed) {}
-        //self.signal();
-        self.status.store(false, std::sync::atomic::Ordering::Relaxed);
+        //the padding in front is handed out to the next alloc that fits
+        assert_eq!(list.alloc(50, 2), Some(10));
+        assert_eq!(list.ranges, vec![(60, 4), (80, 176)]);
     }
-}
\ No newline at end of file
+
+
+    #[test]
+    fn freed_ranges_merge_on_both_sides()
+    {
+        let mut list = FreeList::new(300);
+        let (a, b, c) = (list.alloc(100, 1).unwrap(), list.alloc(100, 1).unwrap(), list.alloc(100, 1).unwrap());
+        assert!(list.ranges.is_empty());
+
+        list.free(a, 100);
+        list.free(c, 100);
+        assert_eq!(list.ranges, vec![(0, 100), (200, 100)]);
+        list.free(b, 100);
+        assert_eq!(list.ranges, vec![(0, 300)]);
+
+        //merging only with the range after it, then only with the one before it
+        let mut list = FreeList::new(300);
+        let (a, b, _) = (list.alloc(100, 1).unwrap(), list.alloc(100, 1).unwrap(), list.alloc(100, 1).unwrap());
+        list.free(b, 100);
+        list.free(a, 100);
+        assert_eq!(list.ranges, vec![(0, 200)]);
+        list.free(250, 50);
+        list.free(200, 50);
+        assert_eq!(list.ranges, vec![(0, 300)]);
+    }
+
+
+    #[test]
+    fn full_lists_return_none()
+    {
+        let mut list = FreeList::new(128);
+        assert_eq!(list.alloc(129, 1), None);
+        assert_eq!(list.alloc(100, 1), Some(0));
+        assert_eq!(list.alloc(32, 1), None);
+        //enough space in total, but not once it's aligned
+        assert_eq!(list.alloc(20, 128), None);
+        assert_eq!(list.ranges, vec![(100, 28)]);
+
+        list.free(0, 100);
+        assert_eq!(list.alloc(128, 1), Some(0));
+        assert_eq!(list.alloc(1, 1), None);
+    }
+}
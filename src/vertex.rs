@@ -0,0 +1,202 @@
+use crate::{*, world::Face};
+
+// Chunk meshes are one record of two u32s per greedy quad, the vertex shader pulls them
+// out of a storage buffer and expands the six corners itself. Bits from the low end:
+//   0: u 5 | v 5 | depth 5 | face 3 | width-1 5 | height-1 5 | flip 1 | texture rotation 2
+//   1: voxel id 16 | block light 4 | sky light 4 | ao 2 per corner in quad_corners order
+// u and v are the quad's min corner on its plane, depth the voxel layer the face sits on
+// and flip picks the diagonal the quad is split along. Positions and sizes of lod meshes count
+// cells of 1 << lod voxels. unpack_quad in chunk.vert reads the
+// same layout, bump VERTEX_FORMAT_VERSION with it.
+pub const VERTEX_FORMAT_VERSION: u32 = 3;
+
+//corners emitted per quad, two triangles
+pub const QUAD_VERTICES: u32 = 6;
+
+const COORD_STRIDE: u32 = 5;
+const FACE_STRIDE: u32 = 3;
+const SIZE_STRIDE: u32 = 5;
+const FLIP_STRIDE: u32 = 1;
+const ROTATION_STRIDE: u32 = 2;
+const LIGHT_STRIDE: u32 = 8;
+const AO_STRIDE: u32 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Quad(pub [u32; 2]);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct QuadData {
+    pub u: u32,
+    pub v: u32,
+    pub depth: u32,
+    pub face: u32,
+    //1..=32 voxels along u and v
+    pub width: u32,
+    pub height: u32,
+    pub flip: bool,
+    //quarter turns of the face texture, nothing sets it yet
+    pub rotation: u32,
+    pub voxel_id: VOXELS,
+    //packed like the light map, sky in the high nibble and block light in the low one
+    pub light: u32,
+    pub ao: [u32; 4],
+}
+
+fn mask(stride: u32) -> u32
+{
+    (1 << stride) - 1
+}
+
+impl Quad {
+    pub fn pack(data: QuadData) -> Quad
+    {
+        let mut res = data.rotation & mask(ROTATION_STRIDE);
+        res <<= FLIP_STRIDE; res |= data.flip as u32;
+        res <<= SIZE_STRIDE; res |= (data.height - 1) & mask(SIZE_STRIDE);
+        res <<= SIZE_STRIDE; res |= (data.width - 1) & mask(SIZE_STRIDE);
+        res <<= FACE_STRIDE; res |= data.face & mask(FACE_STRIDE);
+        res <<= COORD_STRIDE; res |= data.depth & mask(COORD_STRIDE);
+        res <<= COORD_STRIDE; res |= data.v & mask(COORD_STRIDE);
+        res <<= COORD_STRIDE; res |= data.u & mask(COORD_STRIDE);
+
+        let mut block = 0;
+        for ao in data.ao.iter().rev()
+        {
+            block <<= AO_STRIDE; block |= ao & mask(AO_STRIDE);
+        }
+        block <<= LIGHT_STRIDE; block |= data.light & mask(LIGHT_STRIDE);
+        block <<= VOXEL_ID_BITS; block |= data.voxel_id.0 as u32;
+
+        Quad([res, block])
+    }
+
+
+    pub fn unpack(self) -> QuadData
+    {
+        let [mut res, mut block] = self.0;
+        let take = |word: &mut u32, stride: u32| {
+            let value = *word & mask(stride);
+            *word >>= stride;
+            value
+        };
+
+        QuadData{
+            u: take(&mut res, COORD_STRIDE),
+            v: take(&mut res, COORD_STRIDE),
+            depth: take(&mut res, COORD_STRIDE),
+            face: take(&mut res, FACE_STRIDE),
+            width: take(&mut res, SIZE_STRIDE) + 1,
+            height: take(&mut res, SIZE_STRIDE) + 1,
+            flip: take(&mut res, FLIP_STRIDE) == 1,
+            rotation: take(&mut res, ROTATION_STRIDE),
+            voxel_id: VOXELS(take(&mut block, VOXEL_ID_BITS) as u16),
+            light: take(&mut block, LIGHT_STRIDE),
+            ao: [(); 4].map(|_| take(&mut block, AO_STRIDE)),
+        }
+    }
+
+
+    //a single voxel face, x y z are the voxel the face belongs to
+    pub fn voxel_face(x:u32, y:u32, z:u32, face:Face, light:u32, voxel_id:VOXELS) -> Quad
+    {
+        let (u, v, depth) = match face {
+            Face::Top | Face::Bottom => (x, z, y),
+            Face::Right | Face::Left => (y, z, x),
+            Face::Front | Face::Back => (x, y, z)
+        };
+        Quad::pack(QuadData{
+            u, v, depth, face: face as u32, width: 1, height: 1, flip: false, rotation: 0,
+            voxel_id, light, ao: [0; 4]
+        })
+    }
+
+
+    pub fn as_bytes(quads: &[Quad]) -> &[u8]
+    {
+        unsafe{std::slice::from_raw_parts(quads.as_ptr() as *const u8, size_of_val(quads))}
+    }
+}
+
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn quad(min: bool) -> QuadData
+    {
+        let pick = |lo: u32, hi: u32| if min {lo} else {hi};
+        QuadData{
+            u: pick(0, mask(COORD_STRIDE)),
+            v: pick(0, mask(COORD_STRIDE)),
+            depth: pick(0, mask(COORD_STRIDE)),
+            face: pick(0, mask(FACE_STRIDE)),
+            width: pick(1, 1 << SIZE_STRIDE),
+            height: pick(1, 1 << SIZE_STRIDE),
+            flip: !min,
+            rotation: pick(0, mask(ROTATION_STRIDE)),
+            voxel_id: VOXELS(pick(0, mask(VOXEL_ID_BITS)) as u16),
+            light: pick(0, mask(LIGHT_STRIDE)),
+            ao: [pick(0, mask(AO_STRIDE)); 4],
+        }
+    }
+
+
+    #[test]
+    fn round_trip_min_and_max()
+    {
+        for data in [quad(true), quad(false)]
+        {
+            assert_eq!(Quad::pack(data).unpack(), data);
+        }
+        assert_eq!(Quad::pack(quad(true)).0, [0, 0]);
+        //the first word has one bit to spare
+        assert_eq!(Quad::pack(quad(false)).0, [u32::MAX >> 1, u32::MAX]);
+    }
+
+
+    //each field at its max with everything else at its min, so no field spills into another
+    #[test]
+    fn fields_are_independent()
+    {
+        let (min, max) = (quad(true), quad(false));
+        let fields: [fn(&mut QuadData, &QuadData); 14] = [
+            |d, m| d.u = m.u,
+            |d, m| d.v = m.v,
+            |d, m| d.depth = m.depth,
+            |d, m| d.face = m.face,
+            |d, m| d.width = m.width,
+            |d, m| d.height = m.height,
+            |d, m| d.flip = m.flip,
+            |d, m| d.rotation = m.rotation,
+            |d, m| d.voxel_id = m.voxel_id,
+            |d, m| d.light = m.light,
+            |d, m| d.ao[0] = m.ao[0],
+            |d, m| d.ao[1] = m.ao[1],
+            |d, m| d.ao[2] = m.ao[2],
+            |d, m| d.ao[3] = m.ao[3],
+        ];
+
+        for set in fields
+        {
+            let mut data = min;
+            set(&mut data, &max);
+            assert_eq!(Quad::pack(data).unpack(), data);
+
+            let mut data = max;
+            set(&mut data, &min);
+            assert_eq!(Quad::pack(data).unpack(), data);
+        }
+    }
+
+
+    #[test]
+    fn voxel_face_lands_on_the_face_plane()
+    {
+        let data = Quad::voxel_face(3, 17, 31, Face::Right, 0xa5, VOXELS::WATER).unpack();
+        assert_eq!((data.u, data.v, data.depth), (17, 31, 3));
+        assert_eq!((data.face, data.width, data.height), (Face::Right as u32, 1, 1));
+        assert_eq!((data.light, data.voxel_id), (0xa5, VOXELS::WATER));
+    }
+}
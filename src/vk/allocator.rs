@@ -0,0 +1,199 @@
+use ash::vk;
+use crate::{renderer, util};
+use std::sync::Mutex;
+
+// Device memory comes out of a few large blocks per memory type instead of an allocate_memory
+// for every buffer and image, drivers only have to allow maxMemoryAllocationCount of those.
+// Blocks hand out aligned ranges first fit, requests bigger than half a block get a block of
+// their own. Buffers and optimal tiling images never share a block so bufferImageGranularity
+// can be ignored. Host visible blocks stay mapped for as long as they live.
+const BLOCK_SIZE: vk::DeviceSize = 64 << 20;
+
+pub(super) struct Allocation {
+    pub(super) memory: vk::DeviceMemory,
+    pub(super) offset: vk::DeviceSize,
+    pub(super) size: vk::DeviceSize,
+    //null unless the memory is host visible
+    pub(super) mapped: *mut u8,
+    pool: usize,
+}
+
+struct Block {
+    memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    free: util::FreeList,
+    dedicated: bool,
+    allocations: u32,
+    size: vk::DeviceSize,
+    used: vk::DeviceSize,
+}
+
+//the mapping is only ever written through allocations, which hand out disjoint ranges
+unsafe impl Send for Block {}
+
+struct Pool {
+    memory_type: u32,
+    linear: bool,
+    blocks: Vec<Block>,
+}
+
+pub(super) struct Allocator {
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pools: Mutex<Vec<Pool>>,
+}
+
+impl Allocator {
+    pub(super) fn new(memory_properties: vk::PhysicalDeviceMemoryProperties) -> Allocator
+    {
+        Allocator{memory_properties, pools: Mutex::new(Vec::new())}
+    }
+
+
+    //linear is true for buffers and linear tiling images
+    pub(super) fn allocate(
+        &self,
+        device: &ash::Device,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        linear: bool
+    ) -> Result<Allocation, ()>
+    {
+        let memory_type = self.find_memory_type(requirements.memory_type_bits, properties)?;
+        let mut pools = self.pools.lock().unwrap();
+
+        let pool = match pools.iter().position(|pool| pool.memory_type == memory_type && pool.linear == linear) {
+            Some(pool) => pool,
+            None => {
+                pools.push(Pool{memory_type, linear, blocks: Vec::new()});
+                pools.len() - 1
+            }
+        };
+        let blocks = &mut pools[pool].blocks;
+
+        let dedicated = requirements.size > BLOCK_SIZE / 2;
+        let found = blocks.iter_mut().enumerate()
+            .filter(|(_, block)| !block.dedicated && !dedicated)
+            .find_map(|(idx, block)| block.free.alloc(requirements.size, requirements.alignment).map(|offset| (idx, offset)));
+
+        let (idx, offset) = match found {
+            Some(found) => found,
+            None => {
+                let size = if dedicated {requirements.size} else {BLOCK_SIZE};
+                let mut block = self.create_block(device, memory_type, size, dedicated)?;
+                let offset = block.free.alloc(requirements.size, requirements.alignment)
+                    .expect("fresh memory block too small");
+                blocks.push(block);
+                (blocks.len() - 1, offset)
+            }
+        };
+
+        let block = &mut blocks[idx];
+        block.allocations += 1;
+        block.used += requirements.size;
+
+        let mapped = if block.mapped.is_null() {
+            std::ptr::null_mut()
+        } else {
+            unsafe{block.mapped.add(offset as usize)}
+        };
+
+        Ok(Allocation{memory: block.memory, offset, size: requirements.size, mapped, pool})
+    }
+
+
+    pub(super) fn free(&self, device: &ash::Device, allocation: &Allocation)
+    {
+        let mut pools = self.pools.lock().unwrap();
+        let blocks = &mut pools[allocation.pool].blocks;
+        let idx = blocks.iter().position(|block| block.memory == allocation.memory)
+            .expect("freed memory from an unknown block");
+
+        let block = &mut blocks[idx];
+        block.free.free(allocation.offset, allocation.size);
+        block.allocations -= 1;
+        block.used -= allocation.size;
+
+        //one empty shared block is kept around so a buffer that keeps coming and going
+        //doesn't allocate and free device memory every time
+        let spare = blocks.iter().filter(|block| !block.dedicated && block.allocations == 0).count();
+        let block = &blocks[idx];
+        if block.allocations == 0 && (block.dedicated || spare > 1)
+        {
+            unsafe{device.free_memory(block.memory, None)};
+            blocks.remove(idx);
+        }
+    }
+
+
+    pub(super) fn stats(&self) -> renderer::MemoryStats
+    {
+        let pools = self.pools.lock().unwrap();
+        let mut stats = renderer::MemoryStats::default();
+        for block in pools.iter().flat_map(|pool| pool.blocks.iter())
+        {
+            stats.blocks += 1;
+            stats.allocations += block.allocations;
+            stats.reserved += block.size;
+            stats.used += block.used;
+        }
+        stats
+    }
+
+
+    //every allocation has to be freed by now, runs right before the device is destroyed
+    pub(super) fn destroy(&self, device: &ash::Device)
+    {
+        for pool in self.pools.lock().unwrap().drain(..)
+        {
+            for block in pool.blocks
+            {
+                unsafe{device.free_memory(block.memory, None)};
+            }
+        }
+    }
+
+
+    fn create_block(
+        &self,
+        device: &ash::Device,
+        memory_type: u32,
+        size: vk::DeviceSize,
+        dedicated: bool
+    ) -> Result<Block, ()>
+    {
+        let alloc_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type);
+
+        let memory = unsafe{device.allocate_memory(&alloc_info, None)}.map_err(|_| ())?;
+
+        let host_visible = self.memory_properties.memory_types[memory_type as usize].property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE);
+
+        let mapped = if host_visible {
+            unsafe{device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty())}
+                .expect("failed to map memory block") as *mut u8
+        } else {
+            std::ptr::null_mut()
+        };
+
+        Ok(Block{memory, mapped, free: util::FreeList::new(size), dedicated, allocations: 0, size, used: 0})
+    }
+
+
+    fn find_memory_type(&self, memory_type: u32, properties: vk::MemoryPropertyFlags) -> Result<u32, ()>
+    {
+        for i in 0..self.memory_properties.memory_type_count
+        {
+            if  memory_type & (1 << i) != 0
+                && self.memory_properties.memory_types[i as usize].property_flags
+                    & properties
+                    == properties
+            {
+                return Ok(i)
+            }
+        }
+
+        Err(())
+    }
+}
@@ -3,7 +3,8 @@ use ash::vk;
 
 pub(super) struct Buffer{
     pub(super) handle: vk::Buffer,
-    pub(super) memory: vk::DeviceMemory,
+    allocation: allocator::Allocation,
+    //null unless the buffer lives in host visible memory
     pub(super) memory_mapped: *const std::ffi::c_void,
     pub(super) size: vk::DeviceSize,
     pub(super) range: vk::DeviceSize,
@@ -16,9 +17,9 @@ impl Drop for Buffer
     {
         unsafe 
         {
-            self.device.device.free_memory(self.memory, None);
             self.device.device.destroy_buffer(self.handle, None);
         }
+        self.device.allocator.free(&self.device.device, &self.allocation);
     }
 }
 
@@ -30,71 +31,51 @@ impl Buffer {
         usage: vk::BufferUsageFlags, 
         properties: vk::MemoryPropertyFlags
     ) -> Buffer 
+    {
+        Buffer::create(api.device.clone(), size, range, usage, properties)
+    }
+
+
+    //for owners that keep the device around but not the whole api
+    pub(super) fn create(
+        device: Arc<device::Device>,
+        size: vk::DeviceSize, 
+        range: vk::DeviceSize,
+        usage: vk::BufferUsageFlags, 
+        properties: vk::MemoryPropertyFlags
+    ) -> Buffer 
     {
         let buffer_create_info = vk::BufferCreateInfo::default()
             .size(size)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-        let buffer = unsafe{api.device.device.create_buffer(&buffer_create_info, None)}
+        let buffer = unsafe{device.device.create_buffer(&buffer_create_info, None)}
             .expect("failed to create buffer");
 
-        let memory_requirements = unsafe{api.device.device.get_buffer_memory_requirements(buffer)};
+        let memory_requirements = unsafe{device.device.get_buffer_memory_requirements(buffer)};
 
-        let alloc_info = vk::MemoryAllocateInfo::default() .allocation_size(size) .memory_type_index(
-                image::find_memory_type(
-                    &api.instance, 
-                    api.physical_device, 
-                    memory_requirements.memory_type_bits, 
-                    properties
-                ).expect("failed to find suitable memory type")
-            );
-        
-        let buffer_memory = unsafe{api.device.device.allocate_memory(&alloc_info, None)}
+        let allocation = device.allocator.allocate(&device.device, memory_requirements, properties, true)
             .expect("failed to allocate buffer memory");
 
-        unsafe{api.device.device.bind_buffer_memory(buffer, buffer_memory, 0)}
+        unsafe{device.device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)}
             .expect("failed to bind buffer memory");
 
-
         Buffer{
             handle: buffer, 
-            memory: buffer_memory, 
-            memory_mapped: std::ptr::null(), 
+            memory_mapped: allocation.mapped as *const _, 
+            allocation,
             size,
             range,
-            device: api.device.clone()
+            device
         }
     }
 
-    pub(super) fn map_memory(&mut self) 
-    {
-        self.memory_mapped = unsafe{
-            self.device.device.map_memory(
-                self.memory, 
-                0, 
-                self.size, 
-                vk::MemoryMapFlags::empty()
-            )
-        }.expect("failed to map buffer memory");
-    }
-
     pub(super) fn device_local(
         api: &vulkan::VKInner, 
         data: &[u8],
-        cmd: command_pool::TempBuffer
     ) -> Buffer 
     {
-        let mut staging_buffer = Buffer::new(
-            api, 
-            data.len() as vk::DeviceSize,
-            0,
-            vk::BufferUsageFlags::TRANSFER_SRC, 
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
-
-        staging_buffer.map_memory();
-        <Buffer as crate::renderer::Buffer>::sub_data(&mut staging_buffer, data, 0);
-
         let final_buffer = Buffer::new(
             api, 
             data.len() as vk::DeviceSize, 
@@ -102,8 +83,11 @@ impl Buffer {
             vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER, 
             vk::MemoryPropertyFlags::DEVICE_LOCAL);
 
-        cmd.copy_buffer_to_buffer(&staging_buffer, &final_buffer, data.len() as vk::DeviceSize);
-        cmd.submit();
+        api.stage(data, |staging, offset| {
+            let cmd = api.transfer_pool.create_temp_command_buffer(api.queues.transfer);
+            cmd.copy_buffer_to_buffer(staging, offset, &final_buffer, data.len() as vk::DeviceSize);
+            cmd.submit();
+        });
 
         final_buffer
     }
@@ -1,6 +1,7 @@
 use ash::vk;
 use crate::renderer;
 use super::*;
+use std::{cell::RefCell, rc::Rc};
 
 //THIS IS JUST FOR DRAWING FOR NOW
 //TODO: should combine this with CommandPool::TempBuffer
@@ -23,10 +24,19 @@ pub(super) struct CommandBuffer<'a> {
 
     dynamic_ranges: Vec<u32>,
 
+    //per frame draw_indirect commands and how much of them this frame used, a buffer that
+    //had to grow mid frame waits in retired_indirect until the frame is done with it
+    indirect: Vec<Option<buffer::Buffer>>,
+    indirect_used: Vec<usize>,
+    retired_indirect: Vec<Vec<buffer::Buffer>>,
+
     image_idx: usize,
     vbo: vk::Buffer,
+    recording: bool,
 
-    swapchain: vk::SwapchainKHR,
+    targets: Rc<RefCell<swapchain::RenderTargets>>,
+    //generation of the render targets render_finished was sized for
+    generation: u32,
 }
 
 impl Drop for CommandBuffer<'_> {
@@ -34,10 +44,15 @@ impl Drop for CommandBuffer<'_> {
     {
         unsafe 
         {
-            self.device.device.queue_wait_idle(self.graphics_queue)
-                .expect("failed command buffer cleanup");
-            self.device.device.queue_wait_idle(self.present_queue)
-                .expect("failed command buffer cleanup");
+            //a lost device has nothing in flight anymore, the handles still have to be destroyed
+            //and panicking here would abort if we're already unwinding
+            for queue in [self.graphics_queue, self.present_queue]
+            {
+                if let Err(err) = self.device.device.queue_wait_idle(queue)
+                {
+                    eprintln!("failed to wait for queue before command buffer cleanup: {}", err);
+                }
+            }
 
 
             for ia in self.image_available.iter()
@@ -45,10 +60,7 @@ impl Drop for CommandBuffer<'_> {
                 self.device.device.destroy_semaphore(*ia, None);
             }
 
-            for rf in self.render_finished.iter()
-            {
-                self.device.device.destroy_semaphore(*rf, None);
-            }
+            self.destroy_render_finished();
 
             for fif in self.frame_in_flight.iter()
             {
@@ -81,7 +93,6 @@ impl<'a> CommandBuffer<'_> {
             .flags(vk::FenceCreateFlags::SIGNALED);
 
         let mut image_available: Vec<vk::Semaphore> = Vec::new();
-        let mut render_finished: Vec<vk::Semaphore> = Vec::new();
         let mut frame_in_flight: Vec<vk::Fence> = Vec::new();
         for _ in 0..frames_in_flight
         {
@@ -92,13 +103,9 @@ impl<'a> CommandBuffer<'_> {
             .expect("failed to create fence"));
         }
 
-        for _ in 0..api.swapchain.images.len()
-        {
-            render_finished.push(unsafe{api.device.device.create_semaphore(&semaphore_create_info, None)}
-                .expect("failed to create semaphore"));
-        }
-
-
+        let targets = api.targets.clone();
+        let generation = targets.borrow().generation;
+        let render_finished = CommandBuffer::create_render_finished(&api.device, targets.borrow().color_views().len());
 
         CommandBuffer {
             handles, 
@@ -111,25 +118,130 @@ impl<'a> CommandBuffer<'_> {
             cur_draw: vec![0; frames_in_flight as usize],
             cur_frame: 0,
             vbo: vk::Buffer::null(),
+            recording: false,
 
             dynamic_ranges: Vec::new(),
 
+            indirect: (0..frames_in_flight).map(|_| None).collect(),
+            indirect_used: vec![0; frames_in_flight as usize],
+            retired_indirect: (0..frames_in_flight).map(|_| Vec::new()).collect(),
+
             image_available,
             render_finished,
             frame_in_flight,
 
-            swapchain: api.swapchain.swapchain
+            targets,
+            generation
+        }
+    }
+
+
+    //one per swapchain image since presentation of an image is what waits on it
+    fn create_render_finished(device: &device::Device, count: usize) -> Vec<vk::Semaphore> 
+    {
+        let semaphore_create_info = vk::SemaphoreCreateInfo::default();
+        (0..count).map(|_| unsafe{device.device.create_semaphore(&semaphore_create_info, None)}
+            .expect("failed to create semaphore"))
+            .collect()
+    }
+
+
+    fn destroy_render_finished(&mut self) 
+    {
+        for rf in self.render_finished.drain(..)
+        {
+            unsafe 
+            {
+                self.device.device.destroy_semaphore(rf, None);
+            }
+        }
+    }
+}
+
+impl CommandBuffer<'_> {
+    //descriptors at this draw's dynamic offsets and the bound vertex buffer, if there is one
+    fn bind_draw_state(&self) 
+    {
+        let dynamic_offsets: Vec<u32> = self.dynamic_ranges.iter().map(
+            |&r| self.cur_draw[self.cur_frame] as vk::DeviceSize as u32
+                * r + self.cur_frame as vk::DeviceSize as u32 * 1_000_000
+        ).collect();
+
+        unsafe
+        {
+            self.device.device.cmd_bind_descriptor_sets(
+                self.handles[self.cur_frame],
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.unwrap().layout,
+                0,
+                std::slice::from_ref(&self.descriptor_set),
+                dynamic_offsets.as_slice(),
+            );
+
+            if self.vbo != vk::Buffer::null()
+            {
+                self.device.device.cmd_bind_vertex_buffers(
+                    self.handles[self.cur_frame], 
+                    0, 
+                    &[self.vbo], 
+                    &[0]);
+            }
+        }
+    }
+
+
+    //appends draw commands to this frame's indirect buffer, returns the buffer and where they went
+    fn write_indirect(&mut self, bytes: &[u8]) -> (vk::Buffer, usize) 
+    {
+        let len = bytes.len();
+        let frame = self.cur_frame;
+        let mut offset = self.indirect_used[frame];
+        let fits = self.indirect[frame].as_ref()
+            .is_some_and(|buffer| offset + len <= buffer.size as usize);
+
+        if !fits 
+        {
+            let size = (offset + len).next_power_of_two().max(1 << 16);
+            let buffer = buffer::Buffer::create(
+                self.device.clone(),
+                size as vk::DeviceSize,
+                0,
+                vk::BufferUsageFlags::INDIRECT_BUFFER,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
+
+            if let Some(old) = self.indirect[frame].replace(buffer) 
+            {
+                self.retired_indirect[frame].push(old);
+            }
+            offset = 0;
         }
+
+        let buffer = self.indirect[frame].as_ref().unwrap();
+        <buffer::Buffer as renderer::Buffer>::sub_data(buffer, bytes, offset as i32);
+        self.indirect_used[frame] = offset + len;
+        (buffer.handle, offset)
     }
 }
 
 impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
-    //this only needs to run once we can think of a better architecture
+    //binding before begin picks the pipeline whose render pass the frame uses,
+    //binding while recording switches pipelines inside that render pass
     fn bind_pipeline(&mut self, pipeline: &'a dyn renderer::Pipeline) 
     {
         let pipeline = pipeline.as_any().downcast_ref::<pipeline::Pipeline>()
             .expect("must bind a pipeline created by vulkan api to vulkan command buffer");
 
+        if self.recording 
+        {
+            unsafe 
+            {
+                self.device.device.cmd_bind_pipeline(
+                    self.handles[self.cur_frame], 
+                    vk::PipelineBindPoint::GRAPHICS, 
+                    pipeline.handle);
+            }
+        }
+
         self.pipeline = Some(pipeline);
     }
 
@@ -169,6 +281,16 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
 
                 }
 
+                renderer::DescriptorWriteInfo::Storage {handle} => {
+                    let buffer = handle.as_any().downcast_ref::<buffer::Buffer>()
+                        .expect("must use buffer created with vulkan api in vulkan descriptor");
+
+                    buffer_infos.push(
+                        vk::DescriptorBufferInfo::default()
+                            .buffer(buffer.handle)
+                            .range(vk::WHOLE_SIZE));
+                }
+
                 renderer::DescriptorWriteInfo::Texture {handle} => {
                     let texture = handle.as_any().downcast_ref::<texture::Texture>()
                         .expect("must use texture created with vulkan api in vulkan descriptor");
@@ -200,6 +322,19 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
                     buffer_idx += 1;
                 }
 
+                renderer::DescriptorWriteInfo::Storage {handle: _} => {
+                    descriptor_write.push(
+                        vk::WriteDescriptorSet::default()
+                            .dst_binding(i as u32)
+                            .dst_array_element(0)
+                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                            .buffer_info(&buffer_infos[buffer_idx..buffer_idx+1])
+                            .dst_set(self.descriptor_set)
+                    );
+
+                    buffer_idx += 1;
+                }
+
                 renderer::DescriptorWriteInfo::Texture {handle: _} => {
                     descriptor_write.push(
                         vk::WriteDescriptorSet::default()
@@ -232,31 +367,55 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
 
     fn draw(&mut self, start:i32, end:i32) 
     {
-        let dynamic_offsets: Vec<u32> = self.dynamic_ranges.iter().map(
-            |&r| (
-                self.cur_draw[self.cur_frame] as vk::DeviceSize as u32
-                * r + self.cur_frame as vk::DeviceSize as u32 * 1_000_000
-            ) as u32
-        ).collect();
+        //frame skipped while minimized or waiting on a new swapchain
+        if !self.recording 
+        {
+            return;
+        }
 
+        self.bind_draw_state();
         unsafe
         {
-            self.device.device.cmd_bind_descriptor_sets(
-                self.handles[self.cur_frame],
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pipeline.unwrap().layout,
-                0,
-                std::slice::from_ref(&self.descriptor_set),
-                dynamic_offsets.as_slice(),
-            );
+            self.device.device.cmd_draw(self.handles[self.cur_frame], (end-start) as u32, 1, start as u32, 0);
+        }
 
-            self.device.device.cmd_bind_vertex_buffers(
-                self.handles[self.cur_frame], 
-                0, 
-                &[self.vbo], 
-                &[0]);
+        self.cur_draw[self.cur_frame] += 1;
+    }
+
+    fn draw_indirect(&mut self, draws: &[renderer::DrawIndirect]) 
+    {
+        if !self.recording || draws.is_empty()
+        {
+            return;
+        }
+
+        self.bind_draw_state();
+        let handle = self.handles[self.cur_frame];
+
+        if !self.device.multi_draw_indirect 
+        {
+            for draw in draws 
+            {
+                unsafe
+                {
+                    self.device.device.cmd_draw(
+                        handle, draw.vertex_count, draw.instance_count, draw.first_vertex, draw.first_instance);
+                }
+            }
+            self.cur_draw[self.cur_frame] += 1;
+            return;
+        }
+
+        let (buffer, offset) = self.write_indirect(renderer::DrawIndirect::as_bytes(draws));
 
-            self.device.device.cmd_draw(self.handles[self.cur_frame], (end-start) as u32, 1, 0, 0);
+        unsafe
+        {
+            self.device.device.cmd_draw_indirect(
+                handle, 
+                buffer, 
+                offset as vk::DeviceSize, 
+                draws.len() as u32, 
+                size_of::<renderer::DrawIndirect>() as u32);
         }
 
         self.cur_draw[self.cur_frame] += 1;
@@ -264,6 +423,11 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
 
     fn draw_indexed(&mut self, start:i32, end:i32) 
     {
+        if !self.recording 
+        {
+            return;
+        }
+
         unsafe 
         {
             self.device.device.cmd_draw_indexed(
@@ -276,22 +440,69 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
     {
         self.cur_draw[self.cur_frame] = 0;
         let begin_info = vk::CommandBufferBeginInfo::default();
+        let targets = self.targets.clone();
+        let mut targets = targets.borrow_mut();
         unsafe 
         {
             //This makes sure previous frame has been rendered but not necessarily presented
             self.device.device.wait_for_fences(&[self.frame_in_flight[self.cur_frame]], true, u64::MAX)
                 .expect("failed to wait for fences");
-            self.device.device.reset_fences(&[self.frame_in_flight[self.cur_frame]])
-                .expect("failed to reset fences");
+        }
+        self.retired_indirect[self.cur_frame].clear();
+        self.indirect_used[self.cur_frame] = 0;
+
+        //a minimized window waits for the resize that restores it
+        if targets.minimized 
+        {
+            return;
+        }
 
-            let (img_idx, _) = self.device.swapchain.acquire_next_image(
-                self.swapchain, 
-                u64::MAX, 
-                self.image_available[self.cur_frame], 
-                vk::Fence::null())
-                .expect("failed to get swapchain image");
+        if targets.out_of_date 
+        {
+            targets.recreate();
+            if targets.minimized 
+            {
+                return;
+            }
+        }
+
+        if targets.generation != self.generation 
+        {
+            //recreate waited for the device so nothing is still waiting on these
+            self.destroy_render_finished();
+            self.render_finished = CommandBuffer::create_render_finished(&self.device, targets.color_views().len());
+            self.generation = targets.generation;
+        }
+
+        let acquired = match &targets.swapchain 
+        {
+            Some(swapchain) => unsafe 
+            {
+                self.device.swapchain.acquire_next_image(
+                    swapchain.swapchain, 
+                    u64::MAX, 
+                    self.image_available[self.cur_frame], 
+                    vk::Fence::null())
+            },
+            None => Ok((0, false))
+        };
+
+        self.image_idx = match acquired 
+        {
+            Ok((img_idx, _)) => img_idx as usize,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => 
+            {
+                targets.out_of_date = true;
+                return;
+            }
+            Err(err) => panic!("failed to get swapchain image: {}", err)
+        };
 
-            self.image_idx = img_idx as usize;
+        unsafe 
+        {
+            //only reset once we know this frame gets submitted, a skipped frame would leave it unsignaled
+            self.device.device.reset_fences(&[self.frame_in_flight[self.cur_frame]])
+                .expect("failed to reset fences");
 
             self.device.device.reset_command_buffer(self.handles[self.cur_frame], vk::CommandBufferResetFlags::empty())
                 .expect("failed to reset command buffer");
@@ -301,19 +512,38 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
 
         let pipeline = self.pipeline.expect("pipeline not bound before drawing");
 
+        let mut framebuffer = pipeline.framebuffer.borrow_mut();
+        if framebuffer.generation != targets.generation 
+        {
+            *framebuffer = pipeline::Framebuffer::new(self.device.clone(), &targets, &pipeline.render_pass);
+        }
+
         let clear_values = vec![
                 vk::ClearValue{color: vk::ClearColorValue{float32: [0.6, 0.8, 0.99, 1.0]}},
                 vk::ClearValue{depth_stencil: vk::ClearDepthStencilValue::default().depth(1.0)}
         ];
 
+        let extent = targets.extent;
         let render_pass_info = vk::RenderPassBeginInfo::default()
-            .render_pass(self.pipeline.unwrap().render_pass.handle)
-            .framebuffer(pipeline.framebuffer.handles[self.image_idx])
+            .render_pass(pipeline.render_pass.handle)
+            .framebuffer(framebuffer.handles[self.image_idx])
             .clear_values(clear_values.as_slice())
             .render_area(
                 vk::Rect2D::default()
                     .offset(vk::Offset2D::default().x(0).y(0))
-                    .extent(vk::Extent2D::default().width(1280).height(720)));
+                    .extent(extent));
+
+        let viewport = vk::Viewport::default()
+            .x(0.0)
+            .y(0.0)
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        let scissor = vk::Rect2D::default()
+            .offset(vk::Offset2D::default().x(0).y(0))
+            .extent(extent);
 
         unsafe 
         {
@@ -327,38 +557,66 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
                 vk::PipelineBindPoint::GRAPHICS, 
                 pipeline.handle);
 
+            self.device.device.cmd_set_viewport(self.handles[self.cur_frame], 0, &[viewport]);
+            self.device.device.cmd_set_scissor(self.handles[self.cur_frame], 0, &[scissor]);
         }
+        self.recording = true;
     }
 
     fn submit(&mut self)
     {
-        let wait_semaphores = [self.render_finished[self.image_idx]];
-        let image_indices = [self.image_idx as u32];
-        let swapchains = [self.swapchain];
+        if !self.recording 
+        {
+            return;
+        }
 
-        let present_info = vk::PresentInfoKHR::default()
-            .swapchains(&swapchains)
-            .wait_semaphores(&wait_semaphores)
-            .image_indices(&image_indices);
+        let mut targets = self.targets.borrow_mut();
 
         unsafe 
         {
 
             self.device.device.cmd_end_render_pass(self.handles[self.cur_frame]);
+            self.recording = false;
             self.device.device.end_command_buffer(self.handles[self.cur_frame])
                 .expect("failed to end command buffer");
 
+            //offscreen frames have no image to wait for and nothing to present
+            let (wait_semaphores, signal_semaphores) = if targets.is_offscreen() {
+                (&[][..], &[][..])
+            } else {
+                (
+                    std::slice::from_ref(&self.image_available[self.cur_frame]), 
+                    std::slice::from_ref(&self.render_finished[self.image_idx])
+                )
+            };
+
             let submit_info = vk::SubmitInfo::default()
                 .command_buffers(std::slice::from_ref(&self.handles[self.cur_frame]))
-                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
-                .signal_semaphores(std::slice::from_ref(&self.render_finished[self.image_idx]))
-                .wait_semaphores(std::slice::from_ref(&self.image_available[self.cur_frame]));
+                .wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT][..wait_semaphores.len()])
+                .signal_semaphores(signal_semaphores)
+                .wait_semaphores(wait_semaphores);
 
             self.device.device.queue_submit(self.graphics_queue, &[submit_info], self.frame_in_flight[self.cur_frame])
                 .expect("failed to submit to queue");
 
-            self.device.swapchain.queue_present(self.present_queue, &present_info)
-                .expect("failed to present swapchain image");
+            if let Some(swapchain) = &targets.swapchain 
+            {
+                let wait_semaphores = [self.render_finished[self.image_idx]];
+                let image_indices = [self.image_idx as u32];
+                let swapchains = [swapchain.swapchain];
+
+                let present_info = vk::PresentInfoKHR::default()
+                    .swapchains(&swapchains)
+                    .wait_semaphores(&wait_semaphores)
+                    .image_indices(&image_indices);
+
+                match self.device.swapchain.queue_present(self.present_queue, &present_info) 
+                {
+                    Ok(false) => {}
+                    Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => targets.out_of_date = true,
+                    Err(err) => panic!("failed to present swapchain image: {}", err)
+                }
+            }
         }
 
         self.cur_frame += 1;
@@ -378,3 +636,79 @@ impl<'a> renderer::CommandBuffer<'a> for CommandBuffer<'a> {
             offset + (self.cur_draw[self.cur_frame] * buffer.range as usize + self.cur_frame*1_000_000) as i32);
     }
 }
+
+
+//needs a vulkan driver, run with --features gpu-tests
+#[cfg(all(test, feature = "gpu-tests"))]
+mod tests
+{
+    use crate::{camera::{self, Camera}, renderer, scene, world};
+    use std::sync::{Arc, Mutex};
+
+    //the drivers don't like being set up from two test threads at once
+    static GPU: Mutex<()> = Mutex::new(());
+
+    fn api() -> Arc<renderer::ApiHandle>
+    {
+        let Ok(api) = renderer::ApiCreateInfo::VK.request_headless(64, 64) else 
+        {
+            panic!("no vulkan device, gpu-tests needs one");
+        };
+        #[allow(clippy::arc_with_non_send_sync)]
+        Arc::new(api)
+    }
+
+    //the scene and api of VoxelEngine::run, dropped in the same order with frames still in flight
+    fn draw_frames(api: Arc<renderer::ApiHandle>) -> scene::Scene<'static>
+    {
+        let mut player = camera::Player::new();
+        player.update(&[], 0.0);
+        let mut scene = scene::Scene::new(api, world::World::new().without_store());
+        for _ in 0..5
+        {
+            scene.update(&player);
+            scene.draw(&player);
+        }
+        scene
+    }
+
+    #[test]
+    fn drops_cleanly()
+    {
+        let _guard = GPU.lock().unwrap_or_else(|err| err.into_inner());
+        let api = api();
+
+        let command_buffer = api.inner.create_command_buffer().unwrap();
+        drop(command_buffer);
+        //a second one after the first is gone still gets working handles
+        let command_buffer = api.inner.create_command_buffer().unwrap();
+        drop(command_buffer);
+        drop(api);
+    }
+
+    #[test]
+    fn drops_on_engine_teardown()
+    {
+        let _guard = GPU.lock().unwrap_or_else(|err| err.into_inner());
+        let api = api();
+
+        let scene = draw_frames(api.clone());
+        drop(scene);
+        drop(api);
+    }
+
+    #[test]
+    fn drops_while_unwinding()
+    {
+        let _guard = GPU.lock().unwrap_or_else(|err| err.into_inner());
+
+        //a panicking drop would abort the test binary here instead of returning the error
+        let result = std::panic::catch_unwind(||
+        {
+            let api = api();
+            let _scene = draw_frames(api.clone());
+            panic!("engine panicked mid frame");
+        });
+        assert!(result.is_err());
+    }
+}
@@ -40,7 +40,7 @@ pub(super) fn create_command_pools(
     instance: &vulkan::Instance, 
     device: Arc<device::Device>,
     physical_device: vk::PhysicalDevice, 
-    surface: vk::SurfaceKHR,
+    surface: Option<vk::SurfaceKHR>,
 ) -> (CommandPool, CommandPool)
 {
     let indices = physical_device::get_queue_families(instance, physical_device, surface)
@@ -65,7 +65,7 @@ pub(super) fn create_command_pools(
 
     (
         CommandPool{device: device.clone(), handle: graphics_pool}, 
-        CommandPool{device: device, handle: transfer_pool}
+        CommandPool{device, handle: transfer_pool}
     )
 }
 
@@ -93,7 +93,7 @@ impl TempBuffer {
     pub(super) fn transition_image_layout(
         &self,
         image: &image::Image,
-        format: vk::Format,
+        _format: vk::Format,
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
         layers: u32,
@@ -177,6 +177,7 @@ impl TempBuffer {
     pub(super) fn copy_buffer_to_image(
         &self, 
         buffer: &buffer::Buffer, 
+        offset: vk::DeviceSize,
         image: &image::Image, 
         width: u32, 
         height: u32, 
@@ -184,7 +185,7 @@ impl TempBuffer {
     )
     {
         let region = vk::BufferImageCopy::default()
-            .buffer_offset(0)
+            .buffer_offset(offset)
             .buffer_row_length(0)
             .buffer_image_height(0)
             .image_subresource(
@@ -212,14 +213,81 @@ impl TempBuffer {
             }
     }
 
+    //expects the image in TRANSFER_SRC_OPTIMAL as left by an offscreen render pass
+    pub(super) fn copy_image_to_buffer(
+        &self, 
+        image: &image::Image, 
+        buffer: &buffer::Buffer, 
+        width: u32, 
+        height: u32
+    )
+    {
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        let barrier = vk::ImageMemoryBarrier::default()
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image.handle)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .subresource_range(subresource_range);
+
+        let region = vk::BufferImageCopy::default()
+            .buffer_offset(0)
+            .buffer_row_length(0)
+            .buffer_image_height(0)
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .mip_level(0)
+                    .base_array_layer(0)
+                    .layer_count(1))
+            .image_offset(vk::Offset3D::default())
+            .image_extent(
+                vk::Extent3D::default()
+                    .depth(1)
+                    .width(width)
+                    .height(height));
+
+        unsafe 
+        {
+            self.device.device.cmd_pipeline_barrier(
+                self.handle,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+
+            self.device.device.cmd_copy_image_to_buffer(
+                self.handle,
+                image.handle,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.handle,
+                std::slice::from_ref(&region)
+            );
+        }
+    }
+
     pub(super) fn copy_buffer_to_buffer(
         &self, 
         src: &buffer::Buffer, 
+        src_offset: vk::DeviceSize,
         dst: &buffer::Buffer, 
         size: vk::DeviceSize
     ) 
     {
         let copy_region = vk::BufferCopy::default()
+            .src_offset(src_offset)
             .size(size);
 
         unsafe 
@@ -26,7 +26,7 @@ impl DebugUtilsMessenger
 {
     pub(super) fn new(entry: &ash::Entry, instance: &ash::Instance) -> DebugUtilsMessenger
     {
-        let debug_utils_instance = ash::ext::debug_utils::Instance::new(&entry, &instance);
+        let debug_utils_instance = ash::ext::debug_utils::Instance::new(entry, instance);
         let debug_create_info = debug_utils_messenger_create_info();
         unsafe {
             DebugUtilsMessenger {
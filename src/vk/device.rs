@@ -6,6 +6,9 @@ pub(super) const DEVICE_EXTENSIONS: [*const i8; 1] = [vk::KHR_SWAPCHAIN_NAME.as_
 pub(super) struct Device {
     pub device: ash::Device,
     pub swapchain: ash::khr::swapchain::Device,
+    //draw_indirect can go out as one call, otherwise it falls back to a draw per command
+    pub multi_draw_indirect: bool,
+    pub allocator: allocator::Allocator,
 }
 
 impl Drop for Device 
@@ -14,6 +17,7 @@ impl Drop for Device
     {
         unsafe 
         {
+            self.allocator.destroy(&self.device);
             self.device.destroy_device(None);
         }
     }
@@ -24,7 +28,7 @@ impl Device
     pub(super) fn new(
         instance: &vulkan::Instance, 
         device: vk::PhysicalDevice, 
-        indices: physical_device::QueueFamilyIndices
+        indices: &physical_device::QueueFamilyIndices
     ) -> (Device, Queues) {
 
         let mut queue_create_infos: Vec<vk::DeviceQueueCreateInfo> = Vec::new();
@@ -39,15 +43,29 @@ impl Device
             queue_create_infos.push(queue_create_info);
         }
 
+        let supported = unsafe{instance.instance.get_physical_device_features(device)};
+        let multi_draw_indirect = supported.multi_draw_indirect == vk::TRUE 
+            && supported.draw_indirect_first_instance == vk::TRUE;
+
         let physical_device_features = vk::PhysicalDeviceFeatures::default()
             .sampler_anisotropy(true)
-            .sample_rate_shading(true);
+            .sample_rate_shading(true)
+            .multi_draw_indirect(multi_draw_indirect)
+            .draw_indirect_first_instance(multi_draw_indirect);
         
+        //uploads signal a timeline semaphore, core since 1.2
+        let mut vulkan_12_features = vk::PhysicalDeviceVulkan12Features::default()
+            .timeline_semaphore(true);
+
         let device_create_info = vk::DeviceCreateInfo::default()
             .queue_create_infos(queue_create_infos.as_slice())
             .enabled_features(&physical_device_features)
-            .enabled_extension_names(&super::device::DEVICE_EXTENSIONS);
+            .enabled_extension_names(&super::device::DEVICE_EXTENSIONS)
+            .push_next(&mut vulkan_12_features);
+
 
+        let memory_properties = unsafe{instance.instance.get_physical_device_memory_properties(device)};
+        let allocator = allocator::Allocator::new(memory_properties);
 
         unsafe 
         {
@@ -61,7 +79,7 @@ impl Device
             let families = Queues{graphics, present, transfer};
 
             let device_swapchain = ash::khr::swapchain::Device::new(&instance.instance, &device);
-            let device = Device{device, swapchain: device_swapchain};
+            let device = Device{device, swapchain: device_swapchain, multi_draw_indirect, allocator};
 
             (device, families)
         }
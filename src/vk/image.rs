@@ -58,7 +58,7 @@ impl ImageView {
 pub struct Image {
     device: Arc<device::Device>,
     pub(super) handle: vk::Image,
-    pub(super) memory: vk::DeviceMemory
+    allocation: allocator::Allocation,
 }
 
 impl Drop for Image {
@@ -66,18 +66,17 @@ impl Drop for Image {
     {
         unsafe  
         {
-            self.device.device.free_memory(self.memory, None);
             self.device.device.destroy_image(self.handle, None);
         }
+        self.device.allocator.free(&self.device.device, &self.allocation);
     }
 }
 
 
 impl Image {
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
-        instance: &vulkan::Instance,
         device: Arc<device::Device>,
-        physical_device: vk::PhysicalDevice,
         extent: vk::Extent3D, 
         mip_levels: u32, 
         array_layers: u32,
@@ -107,47 +106,21 @@ impl Image {
 
         let mem_requirements = unsafe{device.device.get_image_memory_requirements(image)};
 
-        let alloc_info = vk::MemoryAllocateInfo::default()
-            .allocation_size(mem_requirements.size)
-            .memory_type_index(find_memory_type(&instance, physical_device, mem_requirements.memory_type_bits, properties)
-                .expect("failed to find suitable memory type"));
+        let allocation = device.allocator.allocate(
+            &device.device, 
+            mem_requirements, 
+            properties, 
+            tiling == vk::ImageTiling::LINEAR
+        ).expect("failed to allocate image memory");
 
-        let image_memory = unsafe{device.device.allocate_memory(&alloc_info, None)}
-            .expect("failed to allocate image memory");
-
-        unsafe{device.device.bind_image_memory(image, image_memory, 0)}
+        unsafe{device.device.bind_image_memory(image, allocation.memory, allocation.offset)}
             .expect("failed to bind image memory");
 
-        Image{device, handle: image, memory: image_memory}
+        Image{device, handle: image, allocation}
     }
 }
 
 
-pub(super) fn find_memory_type(
-    instance: &vulkan::Instance, 
-    physical_device: vk::PhysicalDevice, 
-    memory_type: u32, 
-    properties: vk::MemoryPropertyFlags
-) -> Result<u32, ()>
-{
-    let mem_properties = unsafe{
-        instance.instance.get_physical_device_memory_properties(physical_device)
-    };
-
-    for i in 0..mem_properties.memory_type_count 
-    {
-        if  memory_type & (1 << i) != 0  
-            && mem_properties.memory_types[i as usize].property_flags 
-                & properties 
-                == properties
-        {
-            return Ok(i)
-        }
-    }
-
-    Err(())
-}
-
 pub(super) fn find_supported_image_format(
     instance: &vulkan::Instance, 
     physical_device: vk::PhysicalDevice, 
@@ -162,13 +135,12 @@ pub(super) fn find_supported_image_format(
             instance.instance.get_physical_device_format_properties(physical_device, format)
         };
 
-        if  tiling == vk::ImageTiling::LINEAR 
-            && properties.linear_tiling_features.contains(features) 
-        {
-            return Some(format)
-        } 
-        else if tiling == vk::ImageTiling::OPTIMAL 
-                && properties.optimal_tiling_features.contains(features) 
+        let supported = match tiling {
+            vk::ImageTiling::LINEAR => properties.linear_tiling_features,
+            vk::ImageTiling::OPTIMAL => properties.optimal_tiling_features,
+            _ => continue
+        };
+        if supported.contains(features) 
         {
             return Some(format)
         }
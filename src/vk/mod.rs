@@ -10,5 +10,8 @@ pub(crate) mod buffer;
 pub(crate) mod pipeline;
 pub(crate) mod command_buffer;
 pub(crate) mod texture;
+pub(crate) mod allocator;
+pub(crate) mod staging;
+pub(crate) mod transfer;
 
 use std::sync::Arc;
\ No newline at end of file
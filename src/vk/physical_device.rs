@@ -41,7 +41,7 @@ pub(super) fn create(instance: &vulkan::Instance) -> vk::PhysicalDevice
 pub(super) fn get_queue_families(
     instance: &vulkan::Instance, 
     device: vk::PhysicalDevice, 
-    surface: vk::SurfaceKHR
+    surface: Option<vk::SurfaceKHR>
 ) -> Result<QueueFamilyIndices, ()>
 {
     let mut present_queue: Option<u32> = None;
@@ -54,9 +54,9 @@ pub(super) fn get_queue_families(
     {
         let graphics_support = family.queue_flags & vk::QueueFlags::GRAPHICS != vk::QueueFlags::empty();
         let transfer_support = family.queue_flags & vk::QueueFlags::TRANSFER != vk::QueueFlags::empty();
-        let present_support = unsafe{
+        let present_support = surface.is_some_and(|surface| unsafe{
             instance.surface.get_physical_device_surface_support(device, i as _, surface)
-        }.unwrap();
+        }.unwrap());
 
         if transfer_support {
             transfer_queue = Some(i as u32);
@@ -1,9 +1,11 @@
 use super::*;
 use crate::renderer;
 use ash::vk;
+use std::cell::RefCell;
 
 pub(super) struct Pipeline {
-    pub(super) framebuffer: Framebuffer,
+    //rebuilt by the command buffer when the render targets change
+    pub(super) framebuffer: RefCell<Framebuffer>,
     pub(super) render_pass: RenderPass,
     pub(super) handle: vk::Pipeline,
     pub(super) descriptor_set_layout: vk::DescriptorSetLayout,
@@ -27,12 +29,11 @@ impl Drop for Pipeline {
 
 impl Pipeline { pub(super) fn new(
         api: &vulkan::VKInner,
-        color_image_view: vk::ImageView,
-        depth_image_view: vk::ImageView,
-        info: renderer::PipelineInfo, 
-        swapchain: &swapchain::Swapchain
+        info: renderer::PipelineInfo
     ) -> Pipeline 
     {
+        let targets = api.targets.borrow();
+
         let renderer::ShaderInfo::SpirV(vert, frag) = info.shader_info else {
             panic!("spir-v shaders required for vulkan")
         };
@@ -82,29 +83,29 @@ impl Pipeline { pub(super) fn new(
                     .offset(info.vbo_layout.size(Some(i)) as u32));
         }
 
+        //pipelines that pull their vertices from a storage buffer have no vertex input
+        let vertex_binding_descriptions = if info.vbo_layout.elements.is_empty() {
+            &[][..]
+        } else {
+            std::slice::from_ref(&vertex_binding_description)
+        };
+
         let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::default()
-            .vertex_binding_descriptions(std::slice::from_ref(&vertex_binding_description))
+            .vertex_binding_descriptions(vertex_binding_descriptions)
             .vertex_attribute_descriptions(vertex_attribute_descriptions.as_slice());
 
         let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
             .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
             .primitive_restart_enable(false);
 
-        let viewport = vk::Viewport::default()
-            .x(0.0)
-            .y(0.0)
-            .width(swapchain.extent.width as f32)
-            .height(swapchain.extent.height as f32)
-            .min_depth(0.0)
-            .max_depth(1.0);
-
-        let scissor = vk::Rect2D::default()
-            .offset(vk::Offset2D::default().x(0).y(0))
-            .extent(swapchain.extent);
-
+        //set per frame so the pipeline survives swapchain recreation
         let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-            .viewports(std::slice::from_ref(&viewport))
-            .scissors(std::slice::from_ref(&scissor));
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&dynamic_states);
 
         let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
             .rasterizer_discard_enable(false)
@@ -116,14 +117,19 @@ impl Pipeline { pub(super) fn new(
         let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
             .rasterization_samples(vk::SampleCountFlags::TYPE_1);
 
+        let blend = info.blend_mode == renderer::BlendMode::Alpha;
+
         let color_blend_attachment = vk::PipelineColorBlendAttachmentState::default()
             .color_write_mask(
                 vk::ColorComponentFlags::A | vk::ColorComponentFlags::R 
                 | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B)
-            .blend_enable(false)
+            .blend_enable(blend)
             .color_blend_op(vk::BlendOp::ADD)
-            .src_color_blend_factor(vk::BlendFactor::ONE)
-            .dst_color_blend_factor(vk::BlendFactor::ZERO);
+            .src_color_blend_factor(if blend {vk::BlendFactor::SRC_ALPHA} else {vk::BlendFactor::ONE})
+            .dst_color_blend_factor(if blend {vk::BlendFactor::ONE_MINUS_SRC_ALPHA} else {vk::BlendFactor::ZERO})
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(if blend {vk::BlendFactor::ONE_MINUS_SRC_ALPHA} else {vk::BlendFactor::ZERO});
 
         let color_blend_attachment = vk::PipelineColorBlendStateCreateInfo::default()
             .logic_op_enable(false)
@@ -132,7 +138,7 @@ impl Pipeline { pub(super) fn new(
 
         let depth_stencil = vk::PipelineDepthStencilStateCreateInfo::default()
                 .depth_test_enable(true)
-                .depth_write_enable(true)
+                .depth_write_enable(!blend)
                 .depth_compare_op(vk::CompareOp::LESS)
                 .min_depth_bounds(0.0)
                 .max_depth_bounds(1.0);
@@ -158,6 +164,13 @@ impl Pipeline { pub(super) fn new(
                             .stage_flags(vk::ShaderStageFlags::FRAGMENT)
                             .binding(*bind_point as u32)
                     }
+                    renderer::DescriptorInfo::Storage {bind_point} => {
+                        vk::DescriptorSetLayoutBinding::default()
+                            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                            .descriptor_count(1)
+                            .stage_flags(vk::ShaderStageFlags::VERTEX)
+                            .binding(*bind_point as u32)
+                    }
                     _ => {panic!("descriptor can not be of type {:?}", descriptor)}
                 });
         }
@@ -184,6 +197,12 @@ impl Pipeline { pub(super) fn new(
                             .descriptor_count(vulkan::VKInner::FRAMES_IN_FLIGHT)
                     }
 
+                    renderer::DescriptorInfo::Storage{bind_point:_} => {
+                        vk::DescriptorPoolSize::default()
+                            .ty(vk::DescriptorType::STORAGE_BUFFER)
+                            .descriptor_count(vulkan::VKInner::FRAMES_IN_FLIGHT)
+                    }
+
                     _ => {panic!("descriptor con not be of type {:?}", descriptor)}
                 }
             );
@@ -203,16 +222,17 @@ impl Pipeline { pub(super) fn new(
             .expect("failed to create pipeline layout");
 
         let render_pass_info = RenderPassCreateInfo {
-            color_attachment: Some(swapchain.format),
-            depth_attachment: Some(api.depth_format),
+            color_attachment: Some(targets.format),
+            depth_attachment: Some(targets.depth_format),
             resolve_attachment: None,
             load: false,
-            store: true
+            store: true,
+            present: !targets.is_offscreen()
         };
 
         let render_pass = RenderPass::new(api.device.clone(), &render_pass_info);
 
-        let framebuffer = Framebuffer::new(api.device.clone(), swapchain, color_image_view, depth_image_view, &render_pass);
+        let framebuffer = Framebuffer::new(api.device.clone(), &targets, &render_pass);
 
         let pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
             .stages(shader_stages.as_slice())
@@ -223,6 +243,7 @@ impl Pipeline { pub(super) fn new(
             .multisample_state(&multisample_state)
             .depth_stencil_state(&depth_stencil)
             .color_blend_state(&color_blend_attachment)
+            .dynamic_state(&dynamic_state)
             .layout(pipeline_layout)
             .render_pass(render_pass.handle)
             .subpass(0)
@@ -247,7 +268,7 @@ impl Pipeline { pub(super) fn new(
             handle: pipeline, 
             device: api.device.clone(), 
             render_pass, 
-            framebuffer, 
+            framebuffer: RefCell::new(framebuffer), 
             descriptor_pool, 
             layout: pipeline_layout,
             descriptor_set_layout
@@ -278,6 +299,8 @@ pub(super) struct RenderPassCreateInfo {
     pub(super) resolve_attachment: Option<vk::Format>,
     pub(super) load: bool,
     pub(super) store: bool,
+    //offscreen color attachments end up ready to be copied out instead
+    pub(super) present: bool,
 }
 
 pub struct RenderPass {
@@ -303,6 +326,11 @@ impl RenderPass {
     {
 
         let mut attachments: Vec<vk::AttachmentDescription> = Vec::new();
+        let store_op = if info.store {
+            vk::AttachmentStoreOp::STORE
+        } else {
+            vk::AttachmentStoreOp::DONT_CARE
+        };
         let mut counter: u32 = 0;
         let mut color_idx = None;
         let mut depth_idx = None;
@@ -310,8 +338,14 @@ impl RenderPass {
 
         if let Some(format) = info.color_attachment 
         {
+            let final_layout = if info.present {
+                vk::ImageLayout::PRESENT_SRC_KHR
+            } else {
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL
+            };
+
             let (initial_layout, load_op) = if info.load {
-                (vk::ImageLayout::PRESENT_SRC_KHR, vk::AttachmentLoadOp::LOAD)
+                (final_layout, vk::AttachmentLoadOp::LOAD)
             } else {
                 (vk::ImageLayout::UNDEFINED, vk::AttachmentLoadOp::CLEAR)
             };
@@ -320,9 +354,9 @@ impl RenderPass {
                 vk::AttachmentDescription::default()
                     .format(format)
                     .initial_layout(initial_layout)
-                    .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                    .final_layout(final_layout)
                     .load_op(load_op)
-                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .store_op(store_op)
                     .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
                     .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
                     .samples(vk::SampleCountFlags::TYPE_1));
@@ -345,7 +379,7 @@ impl RenderPass {
                     .initial_layout(initial_layout)
                     .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
                     .load_op(load_op)
-                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .store_op(store_op)
                     .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
                     .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
                     .samples(vk::SampleCountFlags::TYPE_1));
@@ -389,17 +423,17 @@ impl RenderPass {
 
         if color_idx.is_some() 
         {
-            subpass = subpass.color_attachments(std::slice::from_ref(&color_attachment_ref.as_ref().unwrap()));
+            subpass = subpass.color_attachments(std::slice::from_ref(color_attachment_ref.as_ref().unwrap()));
         }
 
         if depth_idx.is_some() 
         {
-            subpass = subpass.depth_stencil_attachment(&depth_attachment_ref.as_ref().unwrap())
+            subpass = subpass.depth_stencil_attachment(depth_attachment_ref.as_ref().unwrap())
         }
 
         if resolve_idx.is_some() 
         {
-            subpass = subpass.resolve_attachments(std::slice::from_ref(&color_attachment_resolve_ref.as_ref().unwrap()));
+            subpass = subpass.resolve_attachments(std::slice::from_ref(color_attachment_resolve_ref.as_ref().unwrap()));
         }
 
         let dependency = vk::SubpassDependency::default()
@@ -424,6 +458,7 @@ impl RenderPass {
 
 pub(super) struct Framebuffer {
     pub(super) handles: Vec<vk::Framebuffer>,
+    pub(super) generation: u32,
     device: Arc<device::Device>,
 }
 
@@ -441,24 +476,22 @@ impl Drop for Framebuffer {
 
 impl Framebuffer
 {
-    fn new(
+    pub(super) fn new(
         device: Arc<device::Device>, 
-        swapchain: &swapchain::Swapchain, 
-        color_image_view: vk::ImageView,
-        depth_image_view: vk::ImageView,
+        targets: &swapchain::RenderTargets,
         render_pass: &RenderPass
     ) -> Framebuffer 
     {
         let mut framebuffers: Vec<vk::Framebuffer> = Vec::new();
 
-        for i in 0..swapchain.image_views.len() 
+        for color_view in targets.color_views() 
         {
-            let attachments = [swapchain.image_views[i].handle, depth_image_view];
+            let attachments = [color_view, targets.depth_image_view.handle];
             let framebuffer_info = vk::FramebufferCreateInfo::default()
                 .attachments(attachments.as_slice())
                 .render_pass(render_pass.handle)
-                .width(swapchain.extent.width)
-                .height(swapchain.extent.height)
+                .width(targets.extent.width)
+                .height(targets.extent.height)
                 .layers(1);
 
 
@@ -467,6 +500,6 @@ impl Framebuffer
                     .expect("failed to create framebuffer"));
         }
 
-        Framebuffer {device, handles: framebuffers}
+        Framebuffer {device, handles: framebuffers, generation: targets.generation}
     }
 }
@@ -0,0 +1,83 @@
+use ash::vk;
+use super::*;
+use std::collections::VecDeque;
+
+// Uploads copy their data into one mapped buffer that is written front to back and wraps
+// around, rather than creating a staging buffer per upload. Every write is tagged with a
+// ticket and its space stays reserved until release hears that ticket's transfer finished.
+const STAGING_SIZE: vk::DeviceSize = 16 << 20;
+//copies out of a buffer into an image need offsets that are a multiple of the texel size
+const STAGING_ALIGN: vk::DeviceSize = 16;
+
+pub(super) struct StagingRing {
+    pub(super) buffer: buffer::Buffer,
+    head: vk::DeviceSize,
+    //(start, ticket) of writes the gpu may still be reading, oldest first
+    pending: VecDeque<(vk::DeviceSize, u64)>,
+}
+
+impl StagingRing {
+    pub(super) fn new(device: Arc<device::Device>) -> StagingRing
+    {
+        let buffer = buffer::Buffer::create(
+            device,
+            STAGING_SIZE,
+            0,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
+
+        StagingRing{buffer, head: 0, pending: VecDeque::new()}
+    }
+
+
+    //copies data in and returns where it went, None until enough older writes are released
+    pub(super) fn push(&mut self, data: &[u8], ticket: u64) -> Option<vk::DeviceSize>
+    {
+        let len = (data.len() as vk::DeviceSize).next_multiple_of(STAGING_ALIGN);
+        if self.pending.is_empty()
+        {
+            self.head = 0;
+        }
+        let tail = self.pending.front().map_or(0, |&(start, _)| start);
+
+        let start = if self.pending.is_empty() || self.head > tail {
+            //free space runs from head to the end and then from the start up to tail
+            if self.head + len <= STAGING_SIZE {
+                self.head
+            } else if len < tail {
+                0
+            } else {
+                return None
+            }
+        } else if self.head + len < tail {
+            self.head
+        } else {
+            return None
+        };
+
+        <buffer::Buffer as crate::renderer::Buffer>::sub_data(&self.buffer, data, start as i32);
+        self.head = start + len;
+        self.pending.push_back((start, ticket));
+        Some(start)
+    }
+
+
+    //every transfer up to and including completed has finished reading its data
+    pub(super) fn release(&mut self, completed: u64)
+    {
+        while self.pending.front().is_some_and(|&(_, ticket)| ticket <= completed)
+        {
+            self.pending.pop_front();
+        }
+    }
+
+
+    pub(super) fn in_use(&self) -> vk::DeviceSize
+    {
+        match self.pending.front() {
+            None => 0,
+            Some(&(tail, _)) if self.head > tail => self.head - tail,
+            Some(&(tail, _)) => STAGING_SIZE - tail + self.head
+        }
+    }
+}
@@ -24,7 +24,7 @@ impl Surface
         {
             let mut surface: glfw::ffi::VkSurfaceKHR = std::ptr::null_mut();
             if window.create_window_surface(
-                std::mem::transmute(instance.instance.handle()), 
+                std::mem::transmute::<vk::Instance, glfw::ffi::VkInstance>(instance.instance.handle()), 
                 std::ptr::null(), 
                 &mut surface
             ) == glfw::ffi::VkResult_VK_SUCCESS {
@@ -4,7 +4,6 @@ use std::sync::Arc;
 
 pub(super) struct Swapchain {
     pub(super) image_views: Vec<image::ImageView>,
-    pub(super) images: Vec<vk::Image>,
     pub(super) swapchain: vk::SwapchainKHR,
     pub(super) format: vk::Format,
     pub(super) device: Arc<super::device::Device>,
@@ -27,7 +26,8 @@ pub(super) fn create(
     device: Arc<device::Device>, 
     physical_device: vk::PhysicalDevice,
     surface: vk::SurfaceKHR,
-    window: &glfw::PWindow
+    size: (u32, u32),
+    old_swapchain: vk::SwapchainKHR
 ) -> Swapchain
 {
     let support_details = SwapchainSupportDetails::query_device(
@@ -38,7 +38,7 @@ pub(super) fn create(
 
     let surface_format = support_details.choose_format();
     let present_mode = support_details.choose_present_mode();
-    let extent = support_details.choose_extent(window);
+    let extent = support_details.choose_extent(size);
 
     let image_count = if support_details.capabilities.max_image_count > 0 {
         std::cmp::min(
@@ -61,7 +61,7 @@ pub(super) fn create(
         physical_device::get_queue_families(
             instance, 
             physical_device, 
-            surface
+            Some(surface)
         ).unwrap().iter()
     ).into_iter().collect();
 
@@ -76,7 +76,8 @@ pub(super) fn create(
         .pre_transform(support_details.capabilities.current_transform)
         .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
         .present_mode(present_mode)
-        .clipped(true);
+        .clipped(true)
+        .old_swapchain(old_swapchain);
 
     let swapchain = unsafe{device.swapchain.create_swapchain(&create_info, None)}
         .expect("failed to create swapchain");
@@ -88,7 +89,6 @@ pub(super) fn create(
 
     Swapchain {
         image_views, 
-        images, 
         swapchain, 
         format: surface_format.format, 
         device,
@@ -104,10 +104,10 @@ pub(super) fn create_swapchain_views(
 {
     let mut image_views: Vec<image::ImageView> = Vec::new();
 
-    for i in 0..images.len() 
+    for &image in images 
     {
         image_views.push(
-            image::ImageView::new(device.clone(), images[i], format, vk::ImageAspectFlags::COLOR, 1, 1)
+            image::ImageView::new(device.clone(), image, format, vk::ImageAspectFlags::COLOR, 1, 1)
         );
     }
 
@@ -164,15 +164,217 @@ impl SwapchainSupportDetails
         self.present_modes[0]
     }
 
-    pub(super) fn choose_extent(&self, window: &glfw::PWindow) -> vk::Extent2D 
+    //size is the framebuffer size reported by the window, only used when the surface leaves it to us
+    pub(super) fn choose_extent(&self, (width, height): (u32, u32)) -> vk::Extent2D 
     {
         if self.capabilities.current_extent.width != u32::MAX {
             self.capabilities.current_extent
         } else {
-            let (width, height) = window.get_framebuffer_size();
+            let (min, max) = (self.capabilities.min_image_extent, self.capabilities.max_image_extent);
             vk::Extent2D::default()
-                .width(width as u32)
-                .height(height as u32)
+                .width(width.clamp(min.width, max.width))
+                .height(height.clamp(min.height, max.height))
         }
     }
 }
+
+
+//the swapchain and the attachments that have to match its size, everything
+//here is rebuilt together when the surface changes. without a surface the
+//color image is the render target and there is no swapchain at all
+pub(super) struct RenderTargets {
+    pub(super) color_image_view: image::ImageView,
+    pub(super) color_image: image::Image,
+    pub(super) depth_image_view: image::ImageView,
+    pub(super) depth_image: image::Image,
+    pub(super) swapchain: Option<Swapchain>,
+    pub(super) extent: vk::Extent2D,
+    pub(super) format: vk::Format,
+    //bumped on every rebuild so framebuffers and per image semaphores know they are stale
+    pub(super) generation: u32,
+    pub(super) minimized: bool,
+    pub(super) out_of_date: bool,
+    pub(super) depth_format: vk::Format,
+    size: (u32, u32),
+    surface: Option<vk::SurfaceKHR>,
+    physical_device: vk::PhysicalDevice,
+    device: Arc<device::Device>,
+    instance: Arc<vulkan::Instance>,
+}
+
+impl RenderTargets 
+{
+    pub(super) fn new(
+        instance: Arc<vulkan::Instance>, 
+        device: Arc<device::Device>, 
+        physical_device: vk::PhysicalDevice,
+        surface: Option<vk::SurfaceKHR>,
+        size: (u32, u32)
+    ) -> RenderTargets
+    {
+        let depth_format = image::find_depth_format(&instance, physical_device);
+        let swapchain = surface.map(|surface| 
+            create(&instance, device.clone(), physical_device, surface, size, vk::SwapchainKHR::null()));
+        let (extent, format) = RenderTargets::target_info(swapchain.as_ref(), size);
+        let (color_image, color_image_view, depth_image, depth_image_view) = 
+            create_attachments(device.clone(), extent, format, depth_format, swapchain.is_none());
+
+        RenderTargets {
+            color_image_view,
+            color_image,
+            depth_image_view,
+            depth_image,
+            swapchain,
+            extent,
+            format,
+            generation: 0,
+            minimized: false,
+            out_of_date: false,
+            depth_format,
+            size,
+            surface,
+            physical_device,
+            device,
+            instance,
+        }
+    }
+
+
+    //offscreen frames are read back as rgba so the color image doesn't follow the surface format
+    fn target_info(swapchain: Option<&Swapchain>, (width, height): (u32, u32)) -> (vk::Extent2D, vk::Format) 
+    {
+        match swapchain 
+        {
+            Some(swapchain) => (swapchain.extent, swapchain.format),
+            None => (vk::Extent2D{width, height}, vk::Format::R8G8B8A8_SRGB)
+        }
+    }
+
+
+    pub(super) fn is_offscreen(&self) -> bool 
+    {
+        self.swapchain.is_none()
+    }
+
+
+    //what the framebuffers draw into, one per swapchain image
+    pub(super) fn color_views(&self) -> Vec<vk::ImageView> 
+    {
+        match &self.swapchain 
+        {
+            Some(swapchain) => swapchain.image_views.iter().map(|view| view.handle).collect(),
+            None => vec![self.color_image_view.handle]
+        }
+    }
+
+
+    pub(super) fn resize(&mut self, size: (u32, u32)) 
+    {
+        self.size = size;
+        self.recreate();
+    }
+
+
+    //a zero sized surface (minimized window) can't have a swapchain, frames are skipped until it grows again
+    pub(super) fn recreate(&mut self) 
+    {
+        unsafe{self.device.device.device_wait_idle()}
+            .expect("failed to wait for device idle");
+
+        let extent = match self.surface 
+        {
+            Some(surface) => SwapchainSupportDetails::query_device(&self.instance, self.physical_device, surface)
+                .choose_extent(self.size),
+            None => vk::Extent2D{width: self.size.0, height: self.size.1}
+        };
+        self.minimized = extent.width == 0 || extent.height == 0;
+        if self.minimized 
+        {
+            return;
+        }
+
+        if let Some(surface) = self.surface 
+        {
+            let old_swapchain = self.swapchain.as_ref().map_or(vk::SwapchainKHR::null(), |swapchain| swapchain.swapchain);
+            self.swapchain = Some(create(
+                &self.instance, 
+                self.device.clone(), 
+                self.physical_device, 
+                surface, 
+                self.size, 
+                old_swapchain));
+        }
+
+        (self.extent, self.format) = RenderTargets::target_info(self.swapchain.as_ref(), self.size);
+        (self.color_image, self.color_image_view, self.depth_image, self.depth_image_view) = create_attachments(
+            self.device.clone(), 
+            self.extent,
+            self.format, 
+            self.depth_format,
+            self.is_offscreen());
+
+        self.out_of_date = false;
+        self.generation += 1;
+    }
+}
+
+
+fn create_attachments(
+    device: Arc<device::Device>, 
+    extent: vk::Extent2D,
+    format: vk::Format,
+    depth_format: vk::Format,
+    offscreen: bool
+) -> (image::Image, image::ImageView, image::Image, image::ImageView)
+{
+    let screen_extent = vk::Extent3D::default()
+        .depth(0)
+        .width(extent.width)
+        .height(extent.height);
+
+    let color_usage = if offscreen {
+        vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::COLOR_ATTACHMENT
+    } else {
+        vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT
+    };
+
+    let color_image = image::Image::new(
+        device.clone(),
+        screen_extent, 
+        1, 
+        1,
+        vk::SampleCountFlags::TYPE_1, 
+        format, 
+        vk::ImageTiling::OPTIMAL, 
+        color_usage, 
+        vk::MemoryPropertyFlags::DEVICE_LOCAL);
+
+    let color_image_view = image::ImageView::new(
+        device.clone(), 
+        color_image.handle, 
+        format, 
+        vk::ImageAspectFlags::COLOR, 
+        1,
+        1);
+    
+    let depth_image = image::Image::new(
+        device.clone(), 
+        screen_extent, 
+        1, 
+        1,
+        vk::SampleCountFlags::TYPE_1, 
+        depth_format, 
+        vk::ImageTiling::OPTIMAL, 
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL);
+
+    let depth_image_view = image::ImageView::new(
+        device, 
+        depth_image.handle, 
+        depth_format, 
+        vk::ImageAspectFlags::DEPTH, 
+        1,
+        1);
+
+    (color_image, color_image_view, depth_image, depth_image_view)
+}
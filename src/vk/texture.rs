@@ -3,6 +3,8 @@ use super::*;
 use ash::vk;
 
 pub(super) struct Texture {
+    //owns the memory behind image_view
+    #[allow(dead_code)]
     pub(super) image: image::Image,
     pub(super) image_view: image::ImageView,
     pub(super) sampler: Sampler,
@@ -18,30 +20,8 @@ impl renderer::Texture for Texture {
 impl Texture {
     pub(super) fn new(api: &vulkan::VKInner, info: renderer::TextureCreateInfo<'_>) -> Texture 
     {
-        let image_size = (info.height * info.width * info.layers * 4) as vk::DeviceSize;
-
-        let mut staging_buffer = buffer::Buffer::new(
-            api, image_size,
-            0,
-            vk::BufferUsageFlags::TRANSFER_SRC, 
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
-
-        staging_buffer.map_memory();
-
-        unsafe 
-        {
-            std::ptr::copy_nonoverlapping(
-                info.pixels.as_ptr() as *const std::ffi::c_void, 
-                staging_buffer.memory_mapped as *mut std::ffi::c_void, 
-                info.pixels.len());
-
-            api.device.device.unmap_memory(staging_buffer.memory);
-        }
-
         let image = image::Image::new(
-            &api.instance,
             api.device.clone(),
-            api.physical_device,
             vk::Extent3D::default()
                 .depth(1)
                 .width(info.width as u32)
@@ -63,21 +43,24 @@ impl Texture {
             info.layers as _,
             1);
 
-        let temp = api.graphics_pool.create_temp_command_buffer(api.queues.graphics);
-        temp.copy_buffer_to_image(
-            &staging_buffer, 
-            &image, 
-            info.width as u32, 
-            info.height as u32, 
-            info.layers as u32);
-
-        temp.transition_image_layout(
-            &image,
-            vk::Format::R8G8B8A8_SRGB,
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
-            info.layers as _,
-            1);
+        api.stage(info.pixels, |staging, offset| {
+            let temp = api.graphics_pool.create_temp_command_buffer(api.queues.graphics);
+            temp.copy_buffer_to_image(
+                staging, 
+                offset,
+                &image, 
+                info.width as u32, 
+                info.height as u32, 
+                info.layers as u32);
+
+            temp.transition_image_layout(
+                &image,
+                vk::Format::R8G8B8A8_SRGB,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                info.layers as _,
+                1);
+        });
 
         let image_view = image::ImageView::new(
             api.device.clone(), 
@@ -0,0 +1,334 @@
+use ash::vk;
+use super::*;
+use std::collections::VecDeque;
+
+// Uploads that shouldn't stall the frame are recorded into one transfer command buffer per
+// batch. flush submits the batch on the transfer queue, releasing every range it wrote to the
+// graphics queue family, and the batch signals the timeline semaphore with its ticket. poll
+// looks at the semaphore and acquires finished ranges on the graphics queue in a small
+// submission of its own, after which everything up to that ticket is ready to draw.
+pub(super) struct Uploader {
+    device: Arc<device::Device>,
+    transfer_pool: vk::CommandPool,
+    transfer_queue: vk::Queue,
+    graphics_pool: vk::CommandPool,
+    graphics_queue: vk::Queue,
+    //(transfer, graphics), None when they are the same family and nothing changes owner
+    families: Option<(u32, u32)>,
+    timeline: vk::Semaphore,
+
+    //batch being recorded and the (buffer, offset, size) ranges it writes
+    batch: Option<vk::CommandBuffer>,
+    written: Vec<(vk::Buffer, vk::DeviceSize, vk::DeviceSize)>,
+    //ticket of the last submitted batch, the one being recorded signals the next
+    submitted: u64,
+    ready: u64,
+
+    transfers: VecDeque<(u64, vk::CommandBuffer)>,
+    free_transfers: Vec<vk::CommandBuffer>,
+    //ranges released by the transfer queue that the graphics queue hasn't acquired yet
+    released: Vec<(u64, vk::Buffer, vk::DeviceSize, vk::DeviceSize)>,
+    acquires: VecDeque<(vk::Fence, vk::CommandBuffer)>,
+    free_acquires: Vec<(vk::Fence, vk::CommandBuffer)>,
+    //staging for uploads the ring had no room for, kept until their batch is done
+    oversized: Vec<(u64, buffer::Buffer)>,
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self)
+    {
+        unsafe
+        {
+            self.device.device.device_wait_idle()
+                .expect("failed to wait for device idle");
+
+            for (fence, _) in self.acquires.drain(..).chain(self.free_acquires.drain(..))
+            {
+                self.device.device.destroy_fence(fence, None);
+            }
+            self.device.device.destroy_semaphore(self.timeline, None);
+        }
+    }
+}
+
+impl Uploader {
+    pub(super) fn new(
+        device: Arc<device::Device>,
+        indices: &physical_device::QueueFamilyIndices,
+        queues: &device::Queues,
+        graphics_pool: &command_pool::CommandPool,
+        transfer_pool: &command_pool::CommandPool,
+    ) -> Uploader
+    {
+        let mut timeline_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let semaphore_info = vk::SemaphoreCreateInfo::default()
+            .push_next(&mut timeline_info);
+
+        let timeline = unsafe{device.device.create_semaphore(&semaphore_info, None)}
+            .expect("failed to create timeline semaphore");
+
+        let families = (indices.transfer != indices.graphics).then_some((indices.transfer, indices.graphics));
+
+        Uploader{
+            device,
+            transfer_pool: transfer_pool.handle,
+            transfer_queue: queues.transfer,
+            graphics_pool: graphics_pool.handle,
+            graphics_queue: queues.graphics,
+            families,
+            timeline,
+            batch: None,
+            written: Vec::new(),
+            submitted: 0,
+            ready: 0,
+            transfers: VecDeque::new(),
+            free_transfers: Vec::new(),
+            released: Vec::new(),
+            acquires: VecDeque::new(),
+            free_acquires: Vec::new(),
+            oversized: Vec::new(),
+        }
+    }
+
+
+    //records a copy of data into dst at offset and returns the ticket it completes with
+    pub(super) fn copy(
+        &mut self,
+        staging: &mut staging::StagingRing,
+        dst: &buffer::Buffer,
+        data: &[u8],
+        offset: vk::DeviceSize
+    ) -> u64
+    {
+        let ticket = self.submitted + 1;
+        let size = data.len() as vk::DeviceSize;
+
+        let (src, src_offset) = match staging.push(data, ticket) {
+            Some(src_offset) => (staging.buffer.handle, src_offset),
+            None => {
+                let buffer = buffer::Buffer::create(
+                    self.device.clone(),
+                    size,
+                    0,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
+                <buffer::Buffer as crate::renderer::Buffer>::sub_data(&buffer, data, 0);
+                let handle = buffer.handle;
+                self.oversized.push((ticket, buffer));
+                (handle, 0)
+            }
+        };
+
+        let cmd = self.batch();
+        let region = vk::BufferCopy::default()
+            .src_offset(src_offset)
+            .dst_offset(offset)
+            .size(size);
+
+        unsafe{self.device.device.cmd_copy_buffer(cmd, src, dst.handle, std::slice::from_ref(&region))};
+        self.written.push((dst.handle, offset, size));
+        ticket
+    }
+
+
+    pub(super) fn flush(&mut self)
+    {
+        let Some(cmd) = self.batch.take() else
+        {
+            return;
+        };
+        let ticket = self.submitted + 1;
+
+        //with a single family the release barrier makes the copies visible to the shaders directly
+        let (src_family, dst_family, dst_stage, dst_access) = match self.families {
+            Some((transfer, graphics)) =>
+                (transfer, graphics, vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::NONE),
+            None =>
+                (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED, vk::PipelineStageFlags::VERTEX_SHADER, vk::AccessFlags::SHADER_READ),
+        };
+
+        let barriers: Vec<vk::BufferMemoryBarrier> = self.written.iter()
+            .map(|&(buffer, offset, size)| vk::BufferMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(dst_access)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family)
+                .buffer(buffer)
+                .offset(offset)
+                .size(size))
+            .collect();
+
+        if self.families.is_some()
+        {
+            self.released.extend(self.written.iter().map(|&(buffer, offset, size)| (ticket, buffer, offset, size)));
+        }
+        self.written.clear();
+
+        let signal_values = [ticket];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::default()
+            .signal_semaphore_values(&signal_values);
+        let submit_info = vk::SubmitInfo::default()
+            .command_buffers(std::slice::from_ref(&cmd))
+            .signal_semaphores(std::slice::from_ref(&self.timeline))
+            .push_next(&mut timeline_info);
+
+        unsafe
+        {
+            self.device.device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &barriers,
+                &[]);
+
+            self.device.device.end_command_buffer(cmd)
+                .expect("failed to end transfer command buffer");
+            self.device.device.queue_submit(self.transfer_queue, std::slice::from_ref(&submit_info), vk::Fence::null())
+                .expect("failed to submit transfer batch");
+        }
+
+        self.submitted = ticket;
+        self.transfers.push_back((ticket, cmd));
+    }
+
+
+    //ticket of the last batch the gpu finished
+    pub(super) fn completed(&self) -> u64
+    {
+        unsafe{self.device.device.get_semaphore_counter_value(self.timeline)}
+            .expect("failed to read timeline semaphore")
+    }
+
+
+    //ticket of the last submitted batch, staging written for a synchronous upload can be
+    //released together with it
+    pub(super) fn submitted(&self) -> u64
+    {
+        self.submitted
+    }
+
+
+    //recycles whatever finished and returns the ticket everything up to is ready to draw
+    pub(super) fn poll(&mut self, staging: &mut staging::StagingRing) -> u64
+    {
+        let completed = self.completed();
+        staging.release(completed);
+        if completed == self.ready
+        {
+            return self.ready;
+        }
+
+        self.oversized.retain(|&(ticket, _)| ticket > completed);
+        while self.transfers.front().is_some_and(|&(ticket, _)| ticket <= completed)
+        {
+            let (_, cmd) = self.transfers.pop_front().unwrap();
+            self.free_transfers.push(cmd);
+        }
+
+        let acquired: Vec<vk::BufferMemoryBarrier> = self.released.iter()
+            .filter(|&&(ticket, ..)| ticket <= completed)
+            .map(|&(_, buffer, offset, size)| vk::BufferMemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::NONE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                .src_queue_family_index(self.families.unwrap().0)
+                .dst_queue_family_index(self.families.unwrap().1)
+                .buffer(buffer)
+                .offset(offset)
+                .size(size))
+            .collect();
+        self.released.retain(|&(ticket, ..)| ticket > completed);
+
+        if !acquired.is_empty()
+        {
+            self.acquire(&acquired);
+        }
+
+        self.ready = completed;
+        self.ready
+    }
+
+
+    //graphics submissions after this one see the ranges as their own
+    fn acquire(&mut self, barriers: &[vk::BufferMemoryBarrier])
+    {
+        while self.acquires.front().is_some_and(|&(fence, _)| unsafe{self.device.device.get_fence_status(fence)} == Ok(true))
+        {
+            let (fence, cmd) = self.acquires.pop_front().unwrap();
+            unsafe{self.device.device.reset_fences(&[fence])}
+                .expect("failed to reset fence");
+            self.free_acquires.push((fence, cmd));
+        }
+
+        let (fence, cmd) = match self.free_acquires.pop() {
+            Some(free) => free,
+            None => unsafe {
+                let fence = self.device.device.create_fence(&vk::FenceCreateInfo::default(), None)
+                    .expect("failed to create fence");
+                (fence, self.allocate(self.graphics_pool))
+            }
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        let submit_info = vk::SubmitInfo::default()
+            .command_buffers(std::slice::from_ref(&cmd));
+
+        unsafe
+        {
+            self.device.device.begin_command_buffer(cmd, &begin_info)
+                .expect("failed to start acquire command buffer");
+            self.device.device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                barriers,
+                &[]);
+            self.device.device.end_command_buffer(cmd)
+                .expect("failed to end acquire command buffer");
+            self.device.device.queue_submit(self.graphics_queue, std::slice::from_ref(&submit_info), fence)
+                .expect("failed to submit acquire barriers");
+        }
+
+        self.acquires.push_back((fence, cmd));
+    }
+
+
+    fn batch(&mut self) -> vk::CommandBuffer
+    {
+        if let Some(cmd) = self.batch
+        {
+            return cmd;
+        }
+
+        let cmd = match self.free_transfers.pop() {
+            Some(cmd) => cmd,
+            None => self.allocate(self.transfer_pool)
+        };
+
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe{self.device.device.begin_command_buffer(cmd, &begin_info)}
+            .expect("failed to start transfer command buffer");
+
+        self.batch = Some(cmd);
+        cmd
+    }
+
+
+    fn allocate(&self, pool: vk::CommandPool) -> vk::CommandBuffer
+    {
+        let alloc_info = vk::CommandBufferAllocateInfo::default()
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_pool(pool)
+            .command_buffer_count(1);
+
+        unsafe{self.device.device.allocate_command_buffers(&alloc_info)}
+            .expect("failed to allocate command buffers")[0]
+    }
+}
@@ -1,6 +1,7 @@
 use ash::{self, Entry, vk};
-use crate::{renderer::*, vk::image::Image};
+use crate::renderer::*;
 use super::*;
+use std::{cell::RefCell, rc::Rc};
 
 
 pub(super) struct Instance {
@@ -10,7 +11,7 @@ pub(super) struct Instance {
 
 impl Instance 
 {
-    fn new(entry: &ash::Entry, glfw: &glfw::Glfw) -> Instance
+    fn new(entry: &ash::Entry, glfw: Option<&glfw::Glfw>) -> Instance
     {
         let application_info = vk::ApplicationInfo::default()
             .application_name(c"Minecraft Clone")
@@ -23,7 +24,7 @@ impl Instance
         let mut instance_create_info = if debug::ENABLE_VALIDATION_LAYERS {
             vk::InstanceCreateInfo::default()
                 .application_info(&application_info)
-                .enabled_layer_names(unsafe{std::mem::transmute(debug::VALIDATION_LAYERS.as_slice())})
+                .enabled_layer_names(unsafe{std::mem::transmute::<&[&std::ffi::CStr], &[*const std::ffi::c_char]>(debug::VALIDATION_LAYERS.as_slice())})
                 .push_next(&mut debug_create_info)
         } else {
             vk::InstanceCreateInfo::default()
@@ -40,7 +41,6 @@ impl Instance
                 .expect("failed to create instance")
         };
         let surface = ash::khr::surface::Instance::new(entry, &instance);
-        let swapchain = ash::khr::swapchain::Instance::new(entry, &instance);
 
         Instance {instance, surface}
     }
@@ -59,104 +59,99 @@ impl Drop for Instance
 
 pub struct VKInner {
     //device level
-    pub(super)color_image: image::Image,
-    pub(super)color_image_view: image::ImageView,
-    pub(super)depth_image: image::Image,
-    pub(super)depth_image_view: image::ImageView,
+    pub(super)targets: Rc<RefCell<swapchain::RenderTargets>>,
     pub(super)queues: device::Queues,
-    pub(super)swapchain: swapchain::Swapchain,
+    //waits for the device when dropped, so it goes before anything a transfer may still use
+    pub(super)uploader: RefCell<transfer::Uploader>,
     pub(super)graphics_pool: command_pool::CommandPool,
     pub(super)transfer_pool: command_pool::CommandPool,
+    pub(super)staging: RefCell<staging::StagingRing>,
     pub(super)physical_device: vk::PhysicalDevice,
     pub(super)device: Arc<device::Device>,
-    //instance level
+    //instance level, only held to be destroyed with the device
+    #[allow(dead_code)]
     pub(super)debug_utils_messenger: Option<debug::DebugUtilsMessenger>,
-    pub(super)surface: surface::Surface,
+    //None when rendering offscreen
+    #[allow(dead_code)]
+    pub(super)surface: Option<surface::Surface>,
     pub(super)instance: Arc<Instance>,
-
-
-    //data
-    pub(super) depth_format: vk::Format,
 }
 
 impl VKInner {
     pub(super) const FRAMES_IN_FLIGHT:u32 = 2;
 
     pub fn new(window: &glfw::PWindow, glfw: &glfw::Glfw) -> VKInner 
+    {
+        let (width, height) = window.get_framebuffer_size();
+        VKInner::create(Some((window, glfw)), (width as u32, height as u32))
+    }
+
+
+    //renders into an image that can be read back with read_frame, needs no window system at all.
+    //Err when there is no vulkan loader or no device to render with
+    pub fn headless(width: u32, height: u32) -> Result<VKInner, ()> 
+    {
+        if !VKInner::has_device() 
+        {
+            return Err(());
+        }
+        Ok(VKInner::create(None, (width, height)))
+    }
+
+
+    //a bare instance is enough to see whether any driver is installed
+    fn has_device() -> bool 
+    {
+        unsafe 
+        {
+            let Ok(entry) = Entry::load() else 
+            {
+                return false;
+            };
+            let application_info = vk::ApplicationInfo::default().api_version(vk::API_VERSION_1_3);
+            let create_info = vk::InstanceCreateInfo::default().application_info(&application_info);
+            let Ok(instance) = entry.create_instance(&create_info, None) else 
+            {
+                return false;
+            };
+            let found = instance.enumerate_physical_devices().is_ok_and(|devices| !devices.is_empty());
+            instance.destroy_instance(None);
+            found
+        }
+    }
+
+
+    fn create(window: Option<(&glfw::PWindow, &glfw::Glfw)>, size: (u32, u32)) -> VKInner 
     {
         let entry = unsafe {
             Entry::load().expect("failed to initialize vulkan loader")
         };
 
-        let instance = Arc::new(Instance::new(&entry, glfw));
+        let instance = Arc::new(Instance::new(&entry, window.map(|(_, glfw)| glfw)));
         #[cfg(debug_assertions)]
         let debug_utils_messenger = Some(debug::DebugUtilsMessenger::new(&entry, &instance.instance));
         #[cfg(not(debug_assertions))]
         let debug_utils_messenger = None;
 
-        let surface = surface::Surface::new(window, instance.clone());
+        let surface = window.map(|(window, _)| surface::Surface::new(window, instance.clone()));
+        let surface_handle = surface.as_ref().map(|surface| surface.handle);
         let physical_device = physical_device::create(&instance);
-        let queue_family_indices = physical_device::get_queue_families(&instance, physical_device, surface.handle)
+        let queue_family_indices = physical_device::get_queue_families(&instance, physical_device, surface_handle)
             .expect("failed to find adequate queue families");
-        let (device, queues) = device::Device::new(&instance, physical_device, queue_family_indices);
+        let (device, queues) = device::Device::new(&instance, physical_device, &queue_family_indices);
         let device = Arc::new(device);
-        let swapchain = swapchain::create(&instance, device.clone(), physical_device, surface.handle, window);
-        let (graphics_pool, transfer_pool) = command_pool::create_command_pools(&instance, device.clone(), physical_device, surface.handle);
-
-        let screen_extent = vk::Extent3D::default()
-            .depth(0)
-            .width(swapchain.extent.width)
-            .height(swapchain.extent.height);
-
-        let color_image = Image::new(
-            &instance,
-            device.clone(),
-            physical_device,
-            screen_extent, 
-            1, 
-            1,
-            vk::SampleCountFlags::TYPE_1, 
-            swapchain.format, 
-            vk::ImageTiling::OPTIMAL, 
-            vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT, 
-            vk::MemoryPropertyFlags::DEVICE_LOCAL);
-
-        let color_image_view = image::ImageView::new(
+        let targets = swapchain::RenderTargets::new(
+            instance.clone(), 
             device.clone(), 
-            color_image.handle, 
-            swapchain.format, 
-            vk::ImageAspectFlags::COLOR, 
-            1,
-            1);
-        
-        let depth_format = image::find_depth_format(&instance, physical_device);
-        let depth_image = Image::new(
-            &instance, 
-            device.clone(), 
-            physical_device,
-            screen_extent, 
-            1, 
-            1,
-            vk::SampleCountFlags::TYPE_1, 
-            depth_format, 
-            vk::ImageTiling::OPTIMAL, 
-            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL);
-
-        let depth_image_view = image::ImageView::new(
-            device.clone(), 
-            depth_image.handle, 
-            depth_format, 
-            vk::ImageAspectFlags::DEPTH, 
-            1,
-            1);
+            physical_device, 
+            surface_handle, 
+            size);
+        let (graphics_pool, transfer_pool) = command_pool::create_command_pools(&instance, device.clone(), physical_device, surface_handle);
+        let staging = staging::StagingRing::new(device.clone());
+        let uploader = transfer::Uploader::new(device.clone(), &queue_family_indices, &queues, &graphics_pool, &transfer_pool);
 
         VKInner { 
-            color_image,
-            color_image_view,
-            depth_image,
-            depth_image_view,
-            swapchain,
+            targets: Rc::new(RefCell::new(targets)),
             device,
             instance,
             debug_utils_messenger,
@@ -165,15 +160,43 @@ impl VKInner {
             queues,
             graphics_pool,
             transfer_pool,
-            depth_format
+            staging: RefCell::new(staging),
+            uploader: RefCell::new(uploader),
         }
     }
 
 
-    fn get_required_extensions(glfw: &glfw::Glfw) -> Vec<std::ffi::CString>
+    //puts data somewhere a transfer can read it from and hands that to submit, which records
+    //and submits the copy out of it. Oversized uploads get a staging buffer of their own
+    pub(super) fn stage(&self, data: &[u8], submit: impl FnOnce(&buffer::Buffer, vk::DeviceSize))
     {
-        let mut extensions = glfw.get_required_instance_extensions()
-            .expect("failed to get glfw extensions");
+        let mut staging = self.staging.borrow_mut();
+        let mut uploader = self.uploader.borrow_mut();
+        //the copy is done when stage returns, the ticket only keeps the ring in order
+        let ticket = uploader.submitted();
+
+        match staging.push(data, ticket) {
+            Some(offset) => submit(&staging.buffer, offset),
+            None => {
+                let buffer = buffer::Buffer::new(
+                    self, 
+                    data.len() as vk::DeviceSize,
+                    0,
+                    vk::BufferUsageFlags::TRANSFER_SRC, 
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
+                <buffer::Buffer as Buffer>::sub_data(&buffer, data, 0);
+                submit(&buffer, 0);
+            }
+        }
+
+        uploader.poll(&mut staging);
+    }
+
+
+    fn get_required_extensions(glfw: Option<&glfw::Glfw>) -> Vec<std::ffi::CString>
+    {
+        let mut extensions = glfw.map_or(Vec::new(), |glfw| glfw.get_required_instance_extensions()
+            .expect("failed to get glfw extensions"));
 
         if debug::ENABLE_VALIDATION_LAYERS
         {
@@ -188,40 +211,46 @@ impl VKInner {
 impl Api for VKInner {
     fn create_pipeline(&self, pipeline_info: PipelineInfo) -> Result<Box<dyn Pipeline>, ()> 
     {
-        let pipeline = pipeline::Pipeline::new(
-            self, 
-            self.color_image_view.handle, 
-            self.depth_image_view.handle, 
-            pipeline_info, 
-            &self.swapchain);
+        let pipeline = pipeline::Pipeline::new(self, pipeline_info);
 
         Ok(Box::new(pipeline))
     }
 
     fn create_command_buffer<'a>(&self) -> Result<Box<dyn CommandBuffer<'a> + 'a>, ()>
     {
-        Ok(Box::new(command_buffer::CommandBuffer::new(&self, self.graphics_pool.handle)))
+        Ok(Box::new(command_buffer::CommandBuffer::new(self, self.graphics_pool.handle)))
     }
 
     fn create_buffer(&self, info: BufferCreateInfo) -> Result<Box<dyn Buffer>, ()> 
     {
         let buffer = match info {
-            BufferCreateInfo::ReadOnly(data) => {
-                let cmd = self.transfer_pool.create_temp_command_buffer(self.queues.transfer);
-                buffer::Buffer::device_local(&self, data, cmd)
-
-            }
+            BufferCreateInfo::ReadOnly(data) => buffer::Buffer::device_local(self, data),
             BufferCreateInfo::Dynamic(size) => {
-                let mut buffer = buffer::Buffer::new(
-                    &self,
+                buffer::Buffer::new(
+                    self,
                     (3_000_000) as vk::DeviceSize,
                     size as vk::DeviceSize,
                     vk::BufferUsageFlags::UNIFORM_BUFFER,
                     vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
-                );
-
-                buffer.map_memory();
-                buffer
+                )
+            }
+            BufferCreateInfo::DeviceStorage(size) => {
+                buffer::Buffer::new(
+                    self,
+                    size as vk::DeviceSize,
+                    size as vk::DeviceSize,
+                    vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL
+                )
+            }
+            BufferCreateInfo::Storage(size) => {
+                buffer::Buffer::new(
+                    self,
+                    size as vk::DeviceSize,
+                    size as vk::DeviceSize,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT
+                )
             }
         };
         Ok(Box::new(buffer))
@@ -229,6 +258,69 @@ impl Api for VKInner {
 
     fn create_texture(&self, texture_info: TextureCreateInfo) -> Result<Box<dyn Texture>, ()> 
     {
-        Ok(Box::new(texture::Texture::new(&self, texture_info)))
+        Ok(Box::new(texture::Texture::new(self, texture_info)))
+    }
+
+    fn resize(&self, width: u32, height: u32) 
+    {
+        self.targets.borrow_mut().resize((width, height));
+    }
+
+    fn read_frame(&self) -> Result<Vec<u8>, ()> 
+    {
+        let targets = self.targets.borrow();
+        if !targets.is_offscreen() 
+        {
+            return Err(());
+        }
+
+        unsafe{self.device.device.device_wait_idle()}
+            .expect("failed to wait for device idle");
+
+        let extent = targets.extent;
+        let size = (extent.width * extent.height * 4) as vk::DeviceSize;
+        let staging_buffer = buffer::Buffer::new(
+            self, 
+            size,
+            0,
+            vk::BufferUsageFlags::TRANSFER_DST, 
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT);
+
+        let cmd = self.graphics_pool.create_temp_command_buffer(self.queues.graphics);
+        cmd.copy_image_to_buffer(&targets.color_image, &staging_buffer, extent.width, extent.height);
+        cmd.submit();
+
+        let mut pixels = vec![0u8; size as usize];
+        unsafe 
+        {
+            std::ptr::copy_nonoverlapping(staging_buffer.memory_mapped as *const u8, pixels.as_mut_ptr(), pixels.len());
+        }
+        Ok(pixels)
+    }
+
+    fn upload(&self, buffer: &dyn Buffer, data: &[u8], offset: i32) -> u64 
+    {
+        let buffer = buffer.as_any().downcast_ref::<buffer::Buffer>()
+            .expect("upload target is not a vulkan buffer");
+
+        let mut staging = self.staging.borrow_mut();
+        self.uploader.borrow_mut().copy(&mut staging, buffer, data, offset as vk::DeviceSize)
+    }
+
+    fn flush_uploads(&self) 
+    {
+        self.uploader.borrow_mut().flush();
+    }
+
+    fn uploads_ready(&self) -> u64 
+    {
+        self.uploader.borrow_mut().poll(&mut self.staging.borrow_mut())
+    }
+
+    fn memory_stats(&self) -> Result<MemoryStats, ()> 
+    {
+        let mut stats = self.device.allocator.stats();
+        stats.staging = self.staging.borrow().in_use();
+        Ok(stats)
     }
 }
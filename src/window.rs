@@ -1,5 +1,5 @@
 use glfw::{
-    self, Action, Context, Key, fail_on_errors,
+    self, Action, Key, fail_on_errors,
 };
 use crate::{*, camera::{self, Camera, Player}, scene::Scene};
 pub struct VoxelEngine 
@@ -11,6 +11,14 @@ pub struct VoxelEngine
     api: Arc<renderer::ApiHandle>
 }
 
+impl Default for VoxelEngine 
+{
+    fn default() -> Self 
+    {
+        VoxelEngine::new()
+    }
+}
+
 impl VoxelEngine 
 {
     pub fn new() -> VoxelEngine
@@ -29,21 +37,30 @@ impl VoxelEngine
 
         window.show();
         window.set_key_polling(true);
+        window.set_mouse_button_polling(true);
+        window.set_framebuffer_size_polling(true);
 
         let player = Player::new();
 
+        //the api is shared with the scene but stays on this thread
+        #[allow(clippy::arc_with_non_send_sync)]
+        let api = Arc::new(api);
+
         VoxelEngine{
             glfw, 
             window, 
             events, 
             player,
-            api: Arc::new(api),
+            api,
         }
     }
 
 
     pub fn handle_events(&mut self) -> Vec<crate::camera::PlayerEvent>
     {
+        use crate::camera::PlayerEvent;
+        let mut player_events = Vec::<PlayerEvent>::new();
+
         self.glfw.poll_events();
         for (_, event) in glfw::flush_messages(&self.events) 
         {
@@ -53,12 +70,35 @@ impl VoxelEngine
                     self.window.set_should_close(true);
                     break;
                 }
+                glfw::WindowEvent::FramebufferSize(width, height) => {
+                    self.api.inner.resize(width.max(0) as u32, height.max(0) as u32);
+                    if width > 0 && height > 0 
+                    {
+                        self.player.aspect = width as f32 / height as f32;
+                    }
+                }
+                glfw::WindowEvent::Key(Key::F, _, Action::Press, _) => {
+                    player_events.push(PlayerEvent::ToggleFly);
+                }
+                glfw::WindowEvent::MouseButton(glfw::MouseButtonLeft, Action::Press, _) => {
+                    player_events.push(PlayerEvent::Break);
+                }
+                glfw::WindowEvent::MouseButton(glfw::MouseButtonRight, Action::Press, _) => {
+                    player_events.push(PlayerEvent::Place);
+                }
+                glfw::WindowEvent::Key(key, _, Action::Press, _) 
+                    if (Key::Num1 as i32..=Key::Num8 as i32).contains(&(key as i32)) => 
+                {
+                    let slot = (key as i32 - Key::Num1 as i32) as usize;
+                    if let Some(voxel) = VOXELS::iter().nth(slot) 
+                    {
+                        player_events.push(PlayerEvent::Select(voxel));
+                    }
+                }
                 _ => {},
             }
         }
 
-        use crate::camera::PlayerEvent;
-        let mut player_events = Vec::<PlayerEvent>::new();
         if self.window.get_key(Key::W) == Action::Press 
         {
             player_events.push(PlayerEvent::MoveForward);
@@ -83,6 +123,10 @@ impl VoxelEngine
         {
             player_events.push(PlayerEvent::MoveDown);
         }
+        if self.window.get_key(Key::Space) == Action::Press 
+        {
+            player_events.push(PlayerEvent::Jump);
+        }
 
         player_events
     }
@@ -100,19 +144,7 @@ impl VoxelEngine
         let mut second = 1.0;
 
         let (mut x1, mut y1) = self.window.get_cursor_pos();
-        let (chunk_tx, chunk_rx) = std::sync::mpsc::channel::<world::ChunkCluster>();
-        let (mesh_tx, mesh_rx) = std::sync::mpsc::channel::<chunk::ChunkMesh>();
-
-        let mut scene = Scene::new(self.api.clone(), mesh_rx, chunk_tx);
-        let mesh_builder = scene::MeshBuilder::new(chunk_rx, mesh_tx);
-
-        std::thread::spawn( move|| 
-            {
-                loop 
-                {
-                    mesh_builder.build_mesh();
-                }
-            });
+        let mut scene = Scene::new(self.api.clone(), world::World::new());
 
         let mut n_frames = 0;
 
@@ -125,7 +157,17 @@ impl VoxelEngine
             second -= delta_time;
             if second <= 0.0 
             {
-                self.window.set_title(&format!("{}", n_frames));
+                let mut title = format!("{} | chunks {:.1} MiB | drawn {} culled {}", 
+                    n_frames, scene.voxel_memory() as f64 / (1024.0 * 1024.0),
+                    scene.stats.drawn, scene.stats.culled);
+                if let Ok(memory) = self.api.inner.memory_stats()
+                {
+                    title += &format!(" | gpu {:.1}/{:.1} MiB in {} blocks", 
+                        memory.used as f64 / (1024.0 * 1024.0), 
+                        memory.reserved as f64 / (1024.0 * 1024.0), 
+                        memory.blocks);
+                }
+                self.window.set_title(&title);
                 n_frames = 0;
                 second = 1.0;
             }
@@ -137,8 +179,12 @@ impl VoxelEngine
             player_events.extend(self.handle_mouse_move(x1 - x0, y1 - y0));
 
             self.player.update(player_events.as_slice(), delta_time as f32);
+            scene.simulate(&mut self.player, delta_time as f32);
+            scene.interact(&mut self.player);
             scene.update(&self.player);
             last_update_time = now;
         }
+
+        scene.save();
     }
 }
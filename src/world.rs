@@ -1,51 +1,184 @@
 use crate::*;
-use std::{collections::HashMap};
+use std::{collections::{HashMap, HashSet}};
 
 
+//(columns without a heightmap, missing chunks, chunks to mesh), see World::promote_chunks
+pub type Promoted = (Vec<(i32, i32)>, Vec<(i32, i32, i32)>, Vec<(i32, i32, i32)>);
+
+//voxels to write by global position, per chunk
+type Writes = HashMap<(i32,i32,i32), HashMap<(i32,i32,i32), VOXELS>>;
+
 pub struct World 
 {
     pub chunks:HashMap<(i32,i32,i32), Arc<chunk::Chunk>>,
-    pub noise: util::Noise,
+    pub noise: Arc<util::Noise>,
+    //None keeps everything in memory, nothing is loaded or saved
+    pub store: Option<Arc<region::RegionStore>>,
+    //feature edits that landed in chunks which weren't loaded yet, keyed by chunk. They go to
+    //the store with the chunks and come back when the chunk is generated
+    pub pending: HashMap<(i32,i32,i32), Vec<feature::Edit>>,
+    //built chunks whose features haven't been placed yet, they aren't lit until they are
+    pub undecorated: HashSet<(i32,i32,i32)>,
+    //decorated or loaded chunks waiting for their light
+    pub unlit: HashSet<(i32,i32,i32)>,
+    //keyed by chunk column (x, z)
+    pub heightmaps: HashMap<(i32,i32), Arc<biome::Heightmap>>,
+    //chunks below the sky that were built without a single voxel, they aren't kept
+    pub empty: HashSet<(i32,i32,i32)>,
+    //evict_chunks call a chunk was last in the render range or its border
+    last_used: HashMap<(i32,i32,i32), u64>,
+    tick: u64,
+}
+
+impl Default for World {
+    fn default() -> Self 
+    {
+        World::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self 
     {
-        let noise = util::Noise::new(SEED);
+        let noise = Arc::new(util::Noise::new(SEED));
         World{  chunks:HashMap::new(),
-                noise}
+                noise,
+                store: Some(Arc::new(region::RegionStore::new(SAVE_DIR))),
+                pending: HashMap::new(),
+                undecorated: HashSet::new(),
+                unlit: HashSet::new(),
+                heightmaps: HashMap::new(),
+                empty: HashSet::new(),
+                last_used: HashMap::new(),
+                tick: 0}
     }
 
 
-    pub fn chunk_build_task(&self, (x,y,z):(i32,i32,i32)) -> chunk::Chunk 
+    pub fn with_seed(mut self, seed:u32) -> Self 
     {
-        chunk::Chunk::new(x, y, z)
-            .with_status(chunk::ChunkStatus::Dirty)
-            .with_build_voxels(&self.noise)
+        self.noise = Arc::new(util::Noise::new(seed));
+        self
     }
 
 
-    pub fn entity_build_task(&self, (x,y,z):(i32,i32,i32)) -> Vec<(i32,i32,i32,ENTITIES)> 
+    pub fn without_store(mut self) -> Self 
     {
-        self.chunks.get(&(x,y,z)).unwrap().generate_entities()
+        self.store = None;
+        self
     }
 
 
-    pub fn mesh_build_task(&self, (x, y, z):(i32, i32, i32)) -> chunk::ChunkMesh 
+    //what worker threads need to make chunks without borrowing the world
+    pub fn generator(&self) -> Generator 
     {
-        let chunk_cluster = ChunkCluster::new(&self, x, y, z);
-        self.chunks.get(&(x,y,z)).unwrap().get_mesh(chunk_cluster)
+        Generator{noise: self.noise.clone(), store: self.store.clone()}
+    }
+
+
+    pub fn save(&mut self) -> std::io::Result<()> 
+    {
+        let Some(store) = &self.store else 
+        {
+            return Ok(());
+        };
+
+        store.save_chunks(self.chunks.values()
+            .filter(|chunk| chunk.modified)
+            .map(|chunk| chunk.as_ref()))?;
+
+        //saved edits are appended to, they can't stay here as well
+        store.save_pending(self.pending.iter().map(|(&pos, edits)| (pos, edits.as_slice())))?;
+        self.pending.clear();
+        Ok(())
+    }
+
+
+    //drops chunks past UNLOAD_DISTANCE and, while more than CHUNK_BUDGET are loaded, the least
+    //recently used ones outside the border. Modified chunks and pending edits are saved on the
+    //way out, without a store modified chunks stay since nothing could bring them back while
+    //pending edits are dropped, the features they came from are placed again with their chunks.
+    //Nothing is dropped when saving fails. Returns what was dropped
+    pub fn evict_chunks(&mut self, player:&camera::Player) -> std::io::Result<Vec<(i32,i32,i32)>> 
+    {
+        let center = (player.chunk_x, player.chunk_y, player.chunk_z);
+        self.tick += 1;
+        for pos in util::render_range(center).chain(util::border_range(center)) 
+        {
+            if self.chunks.contains_key(&pos) 
+            {
+                self.last_used.insert(pos, self.tick);
+            }
+        }
+
+        let distance = |pos| util::chunk_distance(pos, center);
+        let mut evicted: Vec<_> = self.chunks.iter()
+            .filter(|&(&pos, chunk)| {
+                distance(pos) > RENDER_DISTANCE + 1 && (self.store.is_some() || !chunk.modified)
+            })
+            .map(|(&pos, _)| pos)
+            .collect();
+
+        self.heightmaps.retain(|&(x, z), _| util::chunk_distance((x, center.1, z), center) <= UNLOAD_DISTANCE);
+        self.empty.retain(|&pos| util::chunk_distance(pos, center) <= UNLOAD_DISTANCE);
+
+        let far: Vec<_> = self.pending.keys()
+            .filter(|&&pos| distance(pos) > UNLOAD_DISTANCE)
+            .copied()
+            .collect();
+        if let Some(store) = &self.store 
+        {
+            store.save_pending(far.iter().map(|pos| (*pos, self.pending[pos].as_slice())))?;
+        }
+        for pos in far 
+        {
+            self.pending.remove(&pos);
+        }
+
+        let over_budget = self.chunks.len().saturating_sub(CHUNK_BUDGET);
+        let out_of_range = evicted.iter().filter(|&&pos| distance(pos) > UNLOAD_DISTANCE).count();
+        if out_of_range == 0 && over_budget == 0 
+        {
+            return Ok(Vec::new());
+        }
+
+        //everything out of range goes first, then the oldest until the budget holds
+        evicted.sort_by_key(|&pos| (distance(pos) <= UNLOAD_DISTANCE, self.last_used.get(&pos).copied().unwrap_or(0)));
+        evicted.truncate(out_of_range.max(over_budget));
+
+        if let Some(store) = &self.store 
+        {
+            store.save_chunks(evicted.iter()
+                .map(|pos| self.chunks[pos].as_ref())
+                .filter(|chunk| chunk.modified))?;
+        }
+
+        for pos in evicted.iter() 
+        {
+            self.last_used.remove(pos);
+            self.undecorated.remove(pos);
+            self.unlit.remove(pos);
+            self.chunks.remove(pos);
+        }
+        Ok(evicted)
+    }
+
+
+    pub fn voxel_memory(&self) -> usize 
+    {
+        self.chunks.values()
+            .map(|chunk| size_of::<chunk::Chunk>() + chunk.voxels.heap_size())
+            .sum()
     }
 
 
     pub fn get_voxel(&self, global_x:i32, global_y:i32, global_z:i32) -> VOXELS
     {
-        let cx = (global_x as f32 * INV_CHUNK_SIZE).floor() as i32;
-        let cy = (global_y as f32 * INV_CHUNK_SIZE).floor() as i32;
-        let cz = (global_z as f32 * INV_CHUNK_SIZE).floor() as i32;
-        let lx = global_x % CHUNK_SIZE;
-        let ly = global_y % CHUNK_SIZE;
-        let lz = global_z % CHUNK_SIZE;
+        let cx = global_x.div_euclid(CHUNK_SIZE);
+        let cy = global_y.div_euclid(CHUNK_SIZE);
+        let cz = global_z.div_euclid(CHUNK_SIZE);
+        let lx = global_x.rem_euclid(CHUNK_SIZE);
+        let ly = global_y.rem_euclid(CHUNK_SIZE);
+        let lz = global_z.rem_euclid(CHUNK_SIZE);
 
         self.chunks.get(&(cx,cy,cz)).unwrap().get_voxel(lx, ly, lz)
     }
@@ -56,112 +189,412 @@ impl World {
         let cx = (global_x as f32 * INV_CHUNK_SIZE).floor() as i32;
         let cy = (global_y as f32 * INV_CHUNK_SIZE).floor() as i32;
         let cz = (global_z as f32 * INV_CHUNK_SIZE).floor() as i32;
-        let lx = global_x.rem_euclid(CHUNK_SIZE);
-        let ly = global_y.rem_euclid(CHUNK_SIZE);
-        let lz = global_z.rem_euclid(CHUNK_SIZE);
+        if !self.chunks.contains_key(&(cx,cy,cz)) 
+        {
+            panic!("no chunk at {}, {}, {}\n", cx, cy, cz);
+        }
+        self.write_voxels((cx,cy,cz), &[((global_x, global_y, global_z), voxel)], true)
+    }
 
-        let chunk = self.chunks.remove(&(cx,cy,cz))
-            .expect(&format!("no chunk at {}, {}, {}\n", cx, cy, cz))
-            .unwrap_arc()
-            .with_set_voxel(lx, ly, lz, voxel)
-            .unwrap()
-            .wrap_arc();
-        self.chunks.insert(chunk.pos, chunk);
+
+    //voxels of one loaded chunk, the chunk is copied and relit once for all of them
+    fn write_voxels(
+        &mut self, 
+        chunk_pos:(i32,i32,i32), 
+        voxels:&[((i32,i32,i32), VOXELS)], 
+        modified:bool) -> Result<(), ()> 
+    {
+        //the mesher may still hold this chunk through a cluster, make_mut copies it then
+        let chunk = Arc::make_mut(self.chunks.get_mut(&chunk_pos).ok_or(())?);
+        let mut changes = Vec::with_capacity(voxels.len());
+        for &((x, y, z), voxel) in voxels 
+        {
+            let (lx, ly, lz) = (x.rem_euclid(CHUNK_SIZE), y.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE));
+            let old = chunk.get_voxel(lx, ly, lz);
+            chunk.set_voxel(lx, ly, lz, voxel)?;
+            changes.push(((x, y, z), old, voxel));
+        }
+        chunk.modified |= modified;
+
+        let mut lighting = light::Lighting::new(&mut self.chunks, &self.heightmaps);
+        lighting.update_voxels(&changes);
+        let touched = lighting.touched;
+        self.mark_dirty(touched);
+
+        //faces and ao of voxels across a chunk border depend on these too
+        let mut neighbours = HashSet::new();
+        for ((x, y, z), _, _) in changes 
+        {
+            for dy in -1..=1 
+            {
+                for dz in -1..=1 
+                {
+                    for dx in -1..=1 
+                    {
+                        neighbours.insert((
+                            (x + dx).div_euclid(CHUNK_SIZE),
+                            (y + dy).div_euclid(CHUNK_SIZE),
+                            (z + dz).div_euclid(CHUNK_SIZE)));
+                    }
+                }
+            }
+        }
+        neighbours.remove(&chunk_pos);
+        self.mark_dirty(neighbours);
 
         Ok(())
     }
 
 
-    pub fn promote_chunks(&self, player:& camera::Player) -> (
-        Vec<(i32, i32, i32)>, Vec<(i32, i32, i32)>, Vec<(i32, i32, i32)>) 
+    //first voxel that can be targeted along the ray, skipping the one the origin is in.
+    //stops at unloaded chunks since nothing there can be edited
+    pub fn raycast(&self, origin:[f32; 3], dir:[f32; 3], max_distance:f32) -> Option<RayHit> 
     {
-        let (px, py, pz) = (player.chunk_x, player.chunk_y, player.chunk_z);
-        let mut build_positions = Vec::<(i32, i32, i32)>::new();
-        let mut dirty_positions = Vec::<(i32, i32, i32)>::new();
-        let mut terrain_positions = Vec::<(i32, i32, i32)>::new();
+        let mut cell = origin.map(|v| v.floor() as i32);
+        let step = dir.map(|d| if d > 0.0 {1} else if d < 0.0 {-1} else {0});
+        let t_delta = dir.map(|d| if d != 0.0 {(1.0 / d).abs()} else {f32::INFINITY});
+        let mut t_max = [0.0; 3];
+        for axis in 0..3 
+        {
+            t_max[axis] = match step[axis] 
+            {
+                1 => (cell[axis] as f32 + 1.0 - origin[axis]) * t_delta[axis],
+                -1 => (origin[axis] - cell[axis] as f32) * t_delta[axis],
+                _ => f32::INFINITY
+            };
+        }
 
-        for pos in util::render_range((px, py, pz)) 
+        loop 
         {
-            if let Some(chunk) = self.chunks.get(&pos) 
+            let axis = if t_max[0] < t_max[1] && t_max[0] < t_max[2] {0} 
+                else if t_max[1] < t_max[2] {1} 
+                else {2};
+            let distance = t_max[axis];
+            if distance > max_distance 
+            {
+                return None;
+            }
+
+            cell[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+
+            let [x, y, z] = cell;
+            let pos = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+            if !self.chunks.contains_key(&pos) 
             {
-                match chunk.status 
+                if self.is_air(pos) 
                 {
-                    chunk::ChunkStatus::Dirty => 
-                    {
-                        dirty_positions.push(pos);
-                    }
-                    chunk::ChunkStatus::Terrain => 
-                    {
-                        terrain_positions.push(pos);
-                    }
-                    _ => {}
+                    continue;
                 }
-            } 
-            else 
+                return None;
+            }
+
+            if block::get(self.get_voxel(x, y, z)).solid 
             {
-                build_positions.push(pos);
-                dirty_positions.push(pos);
-                terrain_positions.push(pos);
+                //the face we came through points back against the step
+                let face = match (axis, step[axis]) 
+                {
+                    (0, 1) => Face::Left,
+                    (0, _) => Face::Right,
+                    (1, 1) => Face::Bottom,
+                    (1, _) => Face::Top,
+                    (_, 1) => Face::Back,
+                    _ => Face::Front
+                };
+                return Some(RayHit{voxel: (x, y, z), face, distance});
             }
         }
+    }
+
 
-        //handle edge chunks
-        build_positions.extend(util::border_range((px, py, pz))
-            .filter(|&pos| {
-                if let None = self.chunks.get(&pos) {true} else {false}})
-            .collect::<Vec<_>>());
-        (build_positions, terrain_positions, dirty_positions)
+    //lights freshly generated or loaded chunks from the top down so sky light
+    //always has the chunk above to start from. The scene lights them in jobs and hands the
+    //result to apply_light instead
+    pub fn light_chunks(&mut self, positions: &[(i32, i32, i32)]) 
+    {
+        let mut positions = positions.to_vec();
+        positions.sort_by_key(|pos| -pos.1);
+
+        let mut lighting = light::Lighting::new(&mut self.chunks, &self.heightmaps);
+        for pos in positions 
+        {
+            lighting.light_chunk(pos);
+        }
+        let touched = lighting.touched;
+        self.mark_dirty(touched);
     }
 
 
-    pub fn build_tree_at(&mut self, (x,y,z):(i32,i32,i32)) -> Result<(), ()> 
+    //light a job worked out for the chunk on its own, false when the chunk was changed or
+    //dropped since the job got it and has to be lit again
+    pub fn apply_light(&mut self, lone:&light::LoneChunk, light:light::LightMap) -> bool 
     {
-        static HASH_LEN:usize = math::HASH.len();
-        let key1 = (x*13+y*3+z*3) as usize % HASH_LEN;
+        let pos = lone.chunk.pos;
+        if !self.chunks.get(&pos).is_some_and(|chunk| Arc::ptr_eq(chunk, &lone.chunk)) 
+        {
+            return false;
+        }
+
+        let mut lighting = light::Lighting::new(&mut self.chunks, &self.heightmaps);
+        lighting.install(pos, light);
+        let touched = lighting.touched;
+        self.mark_dirty(touched);
+        true
+    }
+
 
-        let height = math::HASH[key1].rem_euclid(4) + 4;
-        for ty in 0..height 
+    //chunks whose light changed need new meshes, empty chunks have none to rebuild and unlit
+    //ones are marked once they are lit, leaving them alone keeps their light jobs valid
+    pub fn mark_dirty(&mut self, positions: impl IntoIterator<Item = (i32, i32, i32)>) 
+    {
+        for pos in positions 
         {
-            self.set_voxel(x,y+ty,z, VOXELS::WOOD)?;
+            if let Some(chunk) = self.chunks.get_mut(&pos) 
+                && chunk.status == chunk::ChunkStatus::Clean && chunk.light.is_lit() 
+            {
+                Arc::make_mut(chunk).status = chunk::ChunkStatus::Dirty;
+            }
         }
+    }
 
-        self.set_voxel(x, y+height, z, VOXELS::LEAF)?;
 
-        for ty in 0..=3 
+    //columns in the render range or its border without a heightmap, chunks of the columns that
+    //have one that need to exist but don't yet, and dirty chunks in the render range that can
+    //be meshed because they and all their neighbours are decorated and lit
+    pub fn promote_chunks(&self, player:& camera::Player) -> Promoted 
+    {
+        let center = (player.chunk_x, player.chunk_y, player.chunk_z);
+        let reach = RENDER_DISTANCE + 1;
+        let columns = (center.0-reach..=center.0+reach)
+            .flat_map(|x| (center.2-reach..=center.2+reach).map(move |z| (x, z)));
+
+        let mut heightmaps = Vec::new();
+        let mut missing = Vec::new();
+        for (x, z) in columns 
         {
-            let key2 = (x*5+ty*11+z*13) as usize %HASH_LEN;
-            let stride = (3-ty)+math::HASH[key2] % 3;
-            for tx in -stride..=stride 
+            if !self.heightmaps.contains_key(&(x, z)) 
             {
-                for tz in -stride..=stride 
+                heightmaps.push((x, z));
+                continue;
+            }
+
+            missing.extend((center.1-reach..=center.1+reach)
+                .map(|y| (x, y, z))
+                .filter(|pos| !self.chunks.contains_key(pos) && !self.is_skipped(*pos, center)));
+        }
+
+        let dirty = util::render_range(center)
+            .filter(|pos| {
+                self.chunks.get(pos).is_some_and(|chunk| chunk.status == chunk::ChunkStatus::Dirty)
+                    && self.can_mesh(*pos, center)
+            })
+            .collect();
+
+        (heightmaps, missing, dirty)
+    }
+
+
+    //the chunk and all its neighbours are decorated and lit, or skipped
+    pub fn can_mesh(&self, pos:(i32, i32, i32), center:(i32, i32, i32)) -> bool 
+    {
+        ChunkCluster::positions(pos).all(|pos| match self.chunks.get(&pos) {
+            Some(chunk) => chunk.light.is_lit(),
+            None => self.is_skipped(pos, center)
+        })
+    }
+
+
+    //chunks that are never kept: air, and buried ones further than OCCLUDED_RADIUS from the
+    //player, closer ones are loaded so whatever gets dug into is there
+    fn is_skipped(&self, pos:(i32, i32, i32), center:(i32, i32, i32)) -> bool 
+    {
+        self.is_air(pos) || (self.is_buried(pos) && util::chunk_distance(pos, center) > OCCLUDED_RADIUS)
+    }
+
+
+    //unloaded chunks that are all air, sky above the terrain or built empty. Bodies and rays
+    //pass through them, meshes and light see them as open sky
+    pub fn is_air(&self, pos:(i32, i32, i32)) -> bool 
+    {
+        let sky = self.heightmaps.get(&(pos.0, pos.2)).is_some_and(|heightmap| pos.1 >= heightmap.top);
+        !self.chunks.contains_key(&pos) && (sky || self.empty.contains(&pos)) && !self.is_edited(pos)
+    }
+
+
+    //unloaded chunks the heightmap proves solid, meshes and light see them as solid and dark
+    pub fn is_buried(&self, pos:(i32, i32, i32)) -> bool 
+    {
+        !self.chunks.contains_key(&pos) && is_buried(&self.heightmaps, pos) && !self.is_edited(pos)
+    }
+
+
+    //edits make a chunk differ from what the heightmap says about it
+    fn is_edited(&self, pos:(i32, i32, i32)) -> bool 
+    {
+        self.pending.contains_key(&pos) || self.is_saved(pos)
+    }
+
+
+    fn is_saved(&self, pos:(i32, i32, i32)) -> bool 
+    {
+        self.store.as_ref().is_some_and(|store| store.contains(pos))
+    }
+
+
+    //chunks that haven't been lit yet are written directly, the edits for chunks that are
+    //already lit are collected and written per chunk to keep light and meshes right, the rest
+    //waits for its chunk. Features are regenerated from the seed so none of this makes a chunk
+    //worth saving
+    pub fn apply_edits(&mut self, edits: Vec<feature::Edit>) -> Result<(), ()> 
+    {
+        let mut lit: Writes = HashMap::new();
+        for edit in edits 
+        {
+            let (x,y,z) = edit.pos;
+            let chunk_pos = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE));
+            let Some(chunk) = self.chunks.get_mut(&chunk_pos) else 
+            {
+                self.pending.entry(chunk_pos).or_default().push(edit);
+                continue;
+            };
+
+            let (lx, ly, lz) = (x.rem_euclid(CHUNK_SIZE), y.rem_euclid(CHUNK_SIZE), z.rem_euclid(CHUNK_SIZE));
+            if !chunk.light.is_lit() 
+            {
+                if edit.replace.allows(chunk.get_voxel(lx, ly, lz)) 
                 {
-                    self.set_voxel(x+tx, y+height+ty, z+tz, VOXELS::LEAF)?;
+                    Arc::make_mut(chunk).set_voxel(lx, ly, lz, edit.voxel)?;
                 }
+                continue;
+            }
+
+            //later edits see what the earlier ones wrote
+            let writes = lit.entry(chunk_pos).or_default();
+            let current = writes.get(&edit.pos).copied().unwrap_or_else(|| chunk.get_voxel(lx, ly, lz));
+            if edit.replace.allows(current) 
+            {
+                writes.insert(edit.pos, edit.voxel);
             }
         }
 
+        for (chunk_pos, writes) in lit 
+        {
+            let writes: Vec<_> = writes.into_iter().collect();
+            self.write_voxels(chunk_pos, &writes, false)?;
+        }
         Ok(())
     }
 
 
-    pub fn decorate(&mut self, entities: Vec<Vec<(i32, i32, i32, ENTITIES)>>) -> Result<(), ()> 
+    //edits buffered for a chunk before it existed, called right after it's generated or loaded
+    //with the ones that were saved for it. Saved edits are only in the store until the chunk is
+    //saved over them, so they make it modified
+    pub fn apply_pending(&mut self, pos: (i32,i32,i32), saved: Vec<feature::Edit>) -> Result<(), ()> 
     {
-        for chunk in entities {
-            for (x,y,z,entity) in chunk {
-                match entity {
-                    ENTITIES::SEED => {
-                        self.build_tree_at((x,y,z))?;
-                    }
-                }
-            }
+        let from_store = !saved.is_empty();
+        if from_store 
+        {
+            self.pending.entry(pos).or_default().splice(0..0, saved);
+        }
+        let Some(edits) = self.pending.remove(&pos) else 
+        {
+            return Ok(());
+        };
+        self.apply_edits(edits)?;
+
+        if from_store 
+            && let Some(chunk) = self.chunks.get_mut(&pos) 
+        {
+            Arc::make_mut(chunk).modified = true;
         }
         Ok(())
     }
 }
 
 
+//whether the heightmap of the chunk's column proves it solid, lighting has no world to ask
+pub fn is_buried(heightmaps:&HashMap<(i32,i32), Arc<biome::Heightmap>>, pos:(i32,i32,i32)) -> bool 
+{
+    heightmaps.get(&(pos.0, pos.2)).is_some_and(|heightmap| heightmap.is_solid(pos.1))
+}
+
+
+//cloned into jobs, the noise and store are shared with the world
+#[derive(Clone)]
+pub struct Generator 
+{
+    noise: Arc<util::Noise>,
+    store: Option<Arc<region::RegionStore>>,
+}
+
+impl Generator {
+    pub fn heightmap(&self, pos:(i32,i32)) -> biome::Heightmap 
+    {
+        biome::Heightmap::sample(&self.noise, pos)
+    }
+
+
+    //saved chunks come back as they were, anything else is built and still needs decorating
+    pub fn chunk(&self, pos:(i32,i32,i32), heightmap:&biome::Heightmap) -> (chunk::Chunk, bool) 
+    {
+        let saved = self.store.as_ref().map(|store| store.load_chunk(pos));
+        match saved 
+        {
+            Some(Ok(Some(chunk))) => (chunk, false),
+            Some(Err(err)) => 
+            {
+                eprintln!("failed to load chunk at {:?}, regenerating: {}", pos, err);
+                (self.build(pos, heightmap), true)
+            }
+            _ => (self.build(pos, heightmap), true)
+        }
+    }
+
+
+    //edits saved for the chunk while it wasn't loaded
+    pub fn pending(&self, pos:(i32,i32,i32)) -> Vec<feature::Edit> 
+    {
+        let Some(store) = &self.store else 
+        {
+            return Vec::new();
+        };
+        store.load_pending(pos).unwrap_or_else(|err| {
+            eprintln!("failed to load pending edits of chunk at {:?}, dropping them: {}", pos, err);
+            Vec::new()
+        })
+    }
+
+
+    fn build(&self, (x,y,z):(i32,i32,i32), heightmap:&biome::Heightmap) -> chunk::Chunk 
+    {
+        chunk::Chunk::new(x, y, z)
+            .with_status(chunk::ChunkStatus::Dirty)
+            .with_build_voxels(&self.noise, heightmap)
+    }
+
+
+    //edits of every feature rooted in the chunk, they can reach into its neighbours
+    pub fn decorations(&self, chunk:&chunk::Chunk) -> Vec<feature::Edit> 
+    {
+        chunk.generate_entities(&self.noise).into_iter()
+            .flat_map(|(x, y, z, entity)| match entity {
+                ENTITIES::FEATURE(feature) => feature::FEATURES[feature as usize].generate(self.noise.seed, (x, y, z))
+            })
+            .collect()
+    }
+}
+
+
+pub struct RayHit 
+{
+    pub voxel:(i32, i32, i32),
+    pub face:Face,
+    pub distance:f32,
+}
+
+
 #[repr(usize)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Face {
     Top, Bottom, Right, Left, Front, Back
 }
@@ -189,19 +622,6 @@ impl Face
             Face::Left, Face::Front, Face::Back
         ].into_iter()
     }
-
-    pub fn coords(&self) -> [[i32; 3]; 6] 
-    {
-        match self 
-        {
-            Face::Top => [[0,1,1], [1,1,1], [1,1,0], [1,1,0], [0,1,0], [0,1,1]],
-            Face::Bottom => [[0,0,0], [1,0,0], [1,0,1], [1,0,1], [0,0,1], [0,0,0]],
-            Face::Right => [[1,0,1], [1,0,0], [1,1,0], [1,1,0], [1,1,1], [1,0,1]],
-            Face::Left =>[[0,0,0], [0,0,1], [0,1,1], [0,1,1], [0,1,0], [0,0,0]],
-            Face::Front => [[0,0,1], [1,0,1], [1,1,1], [1,1,1], [0,1,1], [0,0,1]],
-            Face::Back => [[1,0,0], [0,0,0], [0,1,0], [0,1,0], [1,1,0], [1,0,0]]
-        }
-    }
 }
 
 
@@ -209,67 +629,355 @@ impl Face
 pub struct ChunkCluster 
 {
     pub center:Option<Arc<chunk::Chunk>>,
-    top:Option<Arc<chunk::Chunk>>,
-    bottom:Option<Arc<chunk::Chunk>>,
-    right:Option<Arc<chunk::Chunk>>,
-    left:Option<Arc<chunk::Chunk>>,
-    front:Option<Arc<chunk::Chunk>>,
-    back:Option<Arc<chunk::Chunk>>
+    //the chunk and its 26 neighbours indexed by (dx+1) + (dz+1)*3 + (dy+1)*9
+    neighbours:[Neighbour; 27]
+}
+
+//unloaded chunks read as empty and open to the sky, buried ones as solid and dark
+#[derive(Clone, Default)]
+enum Neighbour 
+{
+    #[default]
+    Unloaded,
+    Loaded(Arc<chunk::Chunk>),
+    Buried,
 }
 
 impl ChunkCluster 
 {
     pub fn new(world:&World, x:i32, y:i32, z:i32) -> Self 
     { 
+        let mut neighbours: [Neighbour; 27] = Default::default();
+        for dy in -1..=1 
+        {
+            for dz in -1..=1 
+            {
+                for dx in -1..=1 
+                {
+                    let pos = (x+dx, y+dy, z+dz);
+                    neighbours[ChunkCluster::neighbour_index(dx, dy, dz)] = match world.chunks.get(&pos) {
+                        Some(chunk) => Neighbour::Loaded(chunk.clone()),
+                        None if world.is_buried(pos) => Neighbour::Buried,
+                        None => Neighbour::Unloaded
+                    };
+                }
+            }
+        }
+
         ChunkCluster 
         {
             center: Some(Arc::clone(world.chunks.get(&(x,y,z)).expect("Center must be valid"))),
-            top:    world.chunks.get(&(x,y+1,z)).map(|chunk| Arc::clone(chunk)),
-            bottom: world.chunks.get(&(x,y-1,z)).map(|chunk| Arc::clone(chunk)),
-            right:  world.chunks.get(&(x+1,y,z)).map(|chunk| Arc::clone(chunk)),
-            left:   world.chunks.get(&(x-1,y,z)).map(|chunk| Arc::clone(chunk)),
-            front:  world.chunks.get(&(x,y,z+1)).map(|chunk| Arc::clone(chunk)),
-            back:   world.chunks.get(&(x,y,z-1)).map(|chunk| Arc::clone(chunk)),
+            neighbours,
         }
     }
 
 
-    pub fn get_voxel(&self, local_x:i32, local_y:i32, local_z:i32) -> VOXELS 
+    #[inline(always)]
+    fn neighbour_index(dx:i32, dy:i32, dz:i32) -> usize 
     {
-        if let Some(chunk) = match (
+        ((dx+1) + (dz+1)*3 + (dy+1)*9) as usize
+    }
+
+
+    //the chunk and every neighbour a cluster around it holds
+    pub fn positions((x, y, z):(i32, i32, i32)) -> impl Iterator<Item = (i32, i32, i32)> 
+    {
+        (-1..=1).flat_map(move |dy| {
+            (-1..=1).flat_map(move |dz| {
+                (-1..=1).map(move |dx| (x+dx, y+dy, z+dz))
+            })
+        })
+    }
+
+
+    //anything past the neighbours counts as unloaded
+    fn neighbour(&self, local_x:i32, local_y:i32, local_z:i32) -> &Neighbour 
+    {
+        const UNLOADED: &Neighbour = &Neighbour::Unloaded;
+        let (dx, dy, dz) = (
             local_x.div_euclid(CHUNK_SIZE),
             local_y.div_euclid(CHUNK_SIZE),
             local_z.div_euclid(CHUNK_SIZE)
-        ) {
-            (0,0,0) => self.center.as_ref(),
-            (0,1,0) => self.top.as_ref(),
-            (0,-1,0) => self.bottom.as_ref(),
-            (1,0,0) => self.right.as_ref(),
-            (-1,0,0) => self.left.as_ref(),
-            (0,0,1) => self.front.as_ref(),
-            (0,0,-1) => self.back.as_ref(),
-            _ => None
-        } {
-            let chunk_x = local_x.rem_euclid(CHUNK_SIZE);
-            let chunk_y = local_y.rem_euclid(CHUNK_SIZE);
-            let chunk_z = local_z.rem_euclid(CHUNK_SIZE);
+        );
 
-            chunk.get_voxel(chunk_x, chunk_y, chunk_z)
-        } else {
-            VOXELS::EMPTY
+        if dx.abs() <= 1 && dy.abs() <= 1 && dz.abs() <= 1 
+        {
+            &self.neighbours[ChunkCluster::neighbour_index(dx, dy, dz)]
+        } 
+        else 
+        {
+            UNLOADED
         }
     }
 
 
-    pub fn is_solid(&self, local_x:i32, local_y:i32, local_z:i32) -> bool 
+    pub fn get_voxel(&self, local_x:i32, local_y:i32, local_z:i32) -> VOXELS 
     {
-        self.get_voxel(local_x, local_y, local_z) != VOXELS::EMPTY
+        match self.neighbour(local_x, local_y, local_z) 
+        {
+            Neighbour::Loaded(chunk) => chunk.get_voxel(
+                local_x.rem_euclid(CHUNK_SIZE),
+                local_y.rem_euclid(CHUNK_SIZE),
+                local_z.rem_euclid(CHUNK_SIZE)),
+            Neighbour::Buried => VOXELS::COBBLESTONE,
+            Neighbour::Unloaded => VOXELS::EMPTY
+        }
     }
 
 
-    pub fn is_face_visible(&self, x:i32, y:i32, z:i32, face:Face) -> bool 
+    pub fn get_light(&self, local_x:i32, local_y:i32, local_z:i32) -> u8 
     {
-        let (dx, dy, dz) = face.offset();
-        self.get_voxel(x+dx, y+dy, z+dz) == VOXELS::EMPTY
+        match self.neighbour(local_x, local_y, local_z) 
+        {
+            Neighbour::Loaded(chunk) => chunk.get_light(
+                local_x.rem_euclid(CHUNK_SIZE),
+                local_y.rem_euclid(CHUNK_SIZE),
+                local_z.rem_euclid(CHUNK_SIZE)),
+            Neighbour::Buried => 0,
+            //faces towards unloaded chunks are lit like open sky
+            Neighbour::Unloaded => light::Channel::Sky.set(0, light::MAX_LIGHT)
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    //walks the player around a small cube and then off into the distance, loading everything the
+    //scene would around it. Some of the chunks are edited and some edits are left pending ahead
+    //of the player
+    #[test]
+    fn eviction_stays_in_budget_over_a_long_walk()
+    {
+        let dir = std::env::temp_dir().join(format!("evict_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = Arc::new(region::RegionStore::new(&dir));
+        let mut world = World::new();
+        world.store = Some(store.clone());
+        let generator = world.generator();
+        let mut player = camera::Player::new();
+        let mut saved = Vec::new();
+
+        let waypoints = [(2, 2, 2), (-2, 2, 2), (-2, -2, 2), (2, -2, 2), (2, -2, -2), (-2, -2, -2), (-2, 2, -2), (2, 2, -2), (40, 10, -20)];
+        let mut center: (i32, i32, i32) = (0, 0, 0);
+        let mut path = Vec::new();
+        for target in waypoints
+        {
+            while center != target
+            {
+                center.0 += (target.0 - center.0).signum();
+                center.1 += (target.1 - center.1).signum();
+                center.2 += (target.2 - center.2).signum();
+                path.push(center);
+            }
+        }
+
+        for (step, center) in path.into_iter().enumerate()
+        {
+            (player.chunk_x, player.chunk_y, player.chunk_z) = center;
+
+            //sampling is slow, the column under the player is enough to see them go
+            world.heightmaps.entry((center.0, center.2)).or_insert_with(|| Arc::new(generator.heightmap((center.0, center.2))));
+            for pos in util::render_range(center).chain(util::border_range(center))
+            {
+                if !world.chunks.contains_key(&pos)
+                {
+                    //a generated chunk takes its pending edits with it
+                    let mut chunk = chunk::Chunk::new(pos.0, pos.1, pos.2);
+                    chunk.modified = pos.1 == center.1 - 1 || world.pending.remove(&pos).is_some();
+                    world.chunks.insert(pos, Arc::new(chunk));
+                }
+            }
+            let ahead = (center.0, center.1, center.2 + UNLOAD_DISTANCE);
+            if !world.chunks.contains_key(&ahead)
+            {
+                world.pending.entry(ahead).or_default().push(feature::Edit{
+                    pos: (ahead.0 * CHUNK_SIZE, ahead.1 * CHUNK_SIZE, ahead.2 * CHUNK_SIZE),
+                    voxel: VOXELS::WOOD,
+                    replace: feature::Replace::Air
+                });
+            }
+
+            let modified: HashSet<_> = world.chunks.values()
+                .filter(|chunk| chunk.modified)
+                .map(|chunk| chunk.pos)
+                .collect();
+            let pending: Vec<_> = world.pending.keys().copied().collect();
+            let evicted = world.evict_chunks(&player).unwrap();
+
+            assert!(world.chunks.len() <= CHUNK_BUDGET, "{} chunks at step {}", world.chunks.len(), step);
+            for pos in util::render_range(center).chain(util::border_range(center))
+            {
+                assert!(world.chunks.contains_key(&pos), "{:?} evicted next to the player", pos);
+            }
+            for pos in evicted
+            {
+                assert!(util::chunk_distance(pos, center) > RENDER_DISTANCE + 1);
+                if modified.contains(&pos)
+                {
+                    saved.push(pos);
+                }
+            }
+            for pos in pending
+            {
+                if !world.pending.contains_key(&pos)
+                {
+                    assert!(util::chunk_distance(pos, center) > UNLOAD_DISTANCE);
+                    assert!(!store.load_pending(pos).unwrap().is_empty());
+                }
+            }
+            assert!(world.pending.keys().all(|&pos| util::chunk_distance(pos, center) <= UNLOAD_DISTANCE));
+            assert!(world.heightmaps.keys().all(|&(x, z)| util::chunk_distance((x, center.1, z), center) <= UNLOAD_DISTANCE));
+        }
+
+        assert!(!saved.is_empty());
+        for pos in saved
+        {
+            assert!(store.load_chunk(pos).unwrap().is_some(), "{:?} wasn't saved", pos);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+
+    //walking doesn't get near the budget, everything up to the unload distance being loaded does
+    #[test]
+    fn eviction_keeps_the_render_range_over_budget()
+    {
+        let mut world = World::new().without_store();
+        let mut player = camera::Player::new();
+        (player.chunk_x, player.chunk_y, player.chunk_z) = (0, 0, 0);
+        for x in -UNLOAD_DISTANCE..=UNLOAD_DISTANCE
+        {
+            for y in -UNLOAD_DISTANCE..=UNLOAD_DISTANCE
+            {
+                for z in -UNLOAD_DISTANCE..=UNLOAD_DISTANCE
+                {
+                    world.chunks.insert((x, y, z), Arc::new(chunk::Chunk::new(x, y, z)));
+                }
+            }
+        }
+        assert!(world.chunks.len() > CHUNK_BUDGET);
+
+        let evicted = world.evict_chunks(&player).unwrap();
+        assert_eq!(world.chunks.len(), CHUNK_BUDGET);
+        assert!(evicted.iter().all(|&pos| util::chunk_distance(pos, (0, 0, 0)) > RENDER_DISTANCE + 1));
+        for pos in util::render_range((0, 0, 0)).chain(util::border_range((0, 0, 0)))
+        {
+            assert!(world.chunks.contains_key(&pos));
+        }
+
+        //modified chunks can't go anywhere without a store
+        let mut world = World::new().without_store();
+        for x in 0..2 * UNLOAD_DISTANCE
+        {
+            let mut chunk = chunk::Chunk::new(x, 0, 0);
+            chunk.modified = true;
+            world.chunks.insert((x, 0, 0), Arc::new(chunk));
+        }
+        assert!(world.evict_chunks(&player).unwrap().is_empty());
+        assert_eq!(world.chunks.len(), 2 * UNLOAD_DISTANCE as usize);
+    }
+
+
+    //decoration comes back from the seed, player edits and edits only the store had don't
+    #[test]
+    fn only_player_and_saved_edits_mark_chunks_modified()
+    {
+        let mut world = World::new().without_store();
+        let edit = |x, voxel| feature::Edit{pos: (x, 5, 5), voxel, replace: feature::Replace::Any};
+        world.chunks.insert((0, 0, 0), Arc::new(chunk::Chunk::new(0, 0, 0)));
+        world.apply_edits(vec![edit(1, VOXELS::WOOD), edit(40, VOXELS::WOOD)]).unwrap();
+        assert!(!world.chunks[&(0, 0, 0)].modified);
+
+        world.chunks.insert((1, 0, 0), Arc::new(chunk::Chunk::new(1, 0, 0)));
+        world.apply_pending((1, 0, 0), Vec::new()).unwrap();
+        assert_eq!(world.get_voxel(40, 5, 5), VOXELS::WOOD);
+        assert!(!world.chunks[&(1, 0, 0)].modified);
+
+        //lit chunks go through set_voxel and still aren't modified by decoration
+        world.light_chunks(&[(0, 0, 0), (1, 0, 0)]);
+        world.apply_edits(vec![edit(2, VOXELS::LEAF)]).unwrap();
+        assert_eq!(world.get_voxel(2, 5, 5), VOXELS::LEAF);
+        assert!(!world.chunks[&(0, 0, 0)].modified);
+
+        world.chunks.insert((2, 0, 0), Arc::new(chunk::Chunk::new(2, 0, 0)));
+        world.apply_pending((2, 0, 0), vec![edit(70, VOXELS::LEAF)]).unwrap();
+        assert!(world.chunks[&(2, 0, 0)].modified);
+
+        world.set_voxel(3, 5, 5, VOXELS::COBBLESTONE).unwrap();
+        assert!(world.chunks[&(0, 0, 0)].modified);
+    }
+
+
+    //a thousand chunks in a straight line, with and without a store. Nothing the world keeps
+    //about chunks may pile up behind the player
+    #[test]
+    fn eviction_is_bounded_on_a_straight_walk()
+    {
+        let dir = std::env::temp_dir().join(format!("walk_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut template = chunk::Chunk::new(0, 0, 0).with_voxels((0..CHUNK_VOL)
+            .map(|i| [VOXELS::COBBLESTONE, VOXELS::DIRT, VOXELS::GRASS, VOXELS::EMPTY][(i / CHUNK_AREA % 4) as usize])
+            .collect());
+        template.status = chunk::ChunkStatus::Clean;
+        let chunk_memory = size_of::<chunk::Chunk>() + template.voxels.heap_size();
+        let side = 2 * UNLOAD_DISTANCE as usize + 1;
+
+        for store in [true, false]
+        {
+            let mut world = World::new().without_store();
+            if store
+            {
+                world.store = Some(Arc::new(region::RegionStore::new(&dir)));
+            }
+            let heightmap = Arc::new(world.generator().heightmap((0, 0)));
+            let mut player = camera::Player::new();
+
+            for step in 0..400
+            {
+                let center = (step, 0, 0);
+                (player.chunk_x, player.chunk_y, player.chunk_z) = center;
+                for x in center.0-RENDER_DISTANCE-1..=center.0+RENDER_DISTANCE+1
+                {
+                    for z in center.2-RENDER_DISTANCE-1..=center.2+RENDER_DISTANCE+1
+                    {
+                        world.heightmaps.entry((x, z)).or_insert_with(|| heightmap.clone());
+                    }
+                }
+                for pos in util::render_range(center).chain(util::border_range(center))
+                {
+                    if !world.chunks.contains_key(&pos)
+                    {
+                        let mut chunk = template.clone();
+                        chunk.pos = pos;
+                        world.pending.remove(&pos);
+                        world.chunks.insert(pos, Arc::new(chunk));
+                    }
+                }
+                //a tree next to the path reaching into a chunk that never loads
+                let side_pos = (center.0, center.1, center.2 + UNLOAD_DISTANCE);
+                world.pending.entry(side_pos).or_default().push(feature::Edit{
+                    pos: (side_pos.0 * CHUNK_SIZE, side_pos.1 * CHUNK_SIZE, side_pos.2 * CHUNK_SIZE),
+                    voxel: VOXELS::LEAF,
+                    replace: feature::Replace::Air
+                });
+
+                world.evict_chunks(&player).unwrap();
+                assert!(world.chunks.len() <= CHUNK_BUDGET, "{} chunks at step {}", world.chunks.len(), step);
+                assert!(world.voxel_memory() <= CHUNK_BUDGET * chunk_memory, "{} bytes at step {}", world.voxel_memory(), step);
+                assert!(world.pending.len() <= side * side * side, "{} pending at step {}", world.pending.len(), step);
+                assert!(world.heightmaps.len() <= side * side, "{} heightmaps at step {}", world.heightmaps.len(), step);
+                assert!(world.last_used.len() <= world.chunks.len());
+            }
+            assert!(world.pending.len() <= side);
+            if let Some(store) = &world.store
+            {
+                assert_eq!(store.load_pending((0, 0, UNLOAD_DISTANCE)).unwrap().len(), 1);
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}